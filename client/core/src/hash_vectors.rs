@@ -440,6 +440,30 @@ impl HashVectors {
       Ok(0)
     }
   }
+
+  // Handles `sender` acknowledging every message below local sequence number
+  // `up_to_seq`: drops those entries from the pairwise vector with `sender`
+  // and returns how many were dropped. Entries `sender` has not validated
+  // yet are kept, since a later validation payload may still refer to them,
+  // and so is the most recent entry, as the next message hash and the next
+  // validation payload are both derived from it
+  pub fn ack(&mut self, sender: &DeviceId, up_to_seq: u64) -> usize {
+    let pairwise_vector = match self.vectors.get_mut(sender) {
+      Some(pairwise_vector) => pairwise_vector,
+      None => return 0,
+    };
+
+    let mut removed = 0;
+    while pairwise_vector.vector.len() > 1
+        && (pairwise_vector.vector[0].local_seq as u64) < up_to_seq
+        && pairwise_vector.vector[0].local_seq < pairwise_vector.validated_local_seq {
+      pairwise_vector.offset += 1;
+      pairwise_vector.vector.pop_front();
+      removed += 1;
+    }
+    pairwise_vector.vector.shrink_to_fit();
+    removed
+  }
 }
 
 #[cfg(test)]
@@ -944,6 +968,113 @@ mod test {
     ).unwrap();
   }
 
+  /* ack tests */
+
+  #[test]
+  fn test_ack() {
+    let idkey_0 = String::from("0");
+    let idkey_1 = String::from("1");
+    let idkey_2 = String::from("2");
+    let mut hash_vectors_0 = HashVectors::new(idkey_0.clone());
+    let mut hash_vectors_1 = HashVectors::new(idkey_1.clone());
+    let recipients = vec![idkey_0.clone(), idkey_1.clone()];
+
+    // 0 -> {0, 1}, three times
+    for message in ["first message", "second message", "third message"] {
+      let (common_payload, recipient_payloads) = hash_vectors_0.prepare_message(
+          recipients.clone(),
+          message.to_string()
+      );
+      hash_vectors_0.parse_message(
+          &idkey_0,
+          common_payload.clone(),
+          recipient_payloads.get(&idkey_0).unwrap()
+      ).unwrap();
+      hash_vectors_1.parse_message(
+          &idkey_0,
+          common_payload,
+          recipient_payloads.get(&idkey_1).unwrap()
+      ).unwrap();
+    }
+    assert_eq!(hash_vectors_0.vectors.get(&idkey_1).unwrap().vector.len(), 3);
+
+    // 1 has not validated anything yet, so nothing can be dropped
+    assert_eq!(hash_vectors_0.ack(&idkey_1, 3), 0);
+    // acks only concern the sender's own pairwise vector
+    assert_eq!(hash_vectors_0.ack(&idkey_2, 3), 0);
+
+    // 1 -> {0, 1}, validating everything up to the third message; the
+    // entries before it are trimmed on receipt
+    let (common_payload, recipient_payloads) = hash_vectors_1.prepare_message(
+        recipients.clone(),
+        String::from("reply")
+    );
+    assert_eq!(recipient_payloads.get(&idkey_0).unwrap().validation_seq, Some(2));
+    hash_vectors_0.parse_message(
+        &idkey_1,
+        common_payload,
+        recipient_payloads.get(&idkey_0).unwrap()
+    ).unwrap();
+    let pairwise_vector = hash_vectors_0.vectors.get(&idkey_1).unwrap();
+    assert_eq!(pairwise_vector.offset, 2);
+    assert_eq!(pairwise_vector.vector.len(), 2);
+    assert_eq!(pairwise_vector.validated_local_seq, 3);
+
+    // the reply itself is not validated yet
+    assert_eq!(hash_vectors_0.ack(&idkey_1, 10), 1);
+    let pairwise_vector = hash_vectors_0.vectors.get(&idkey_1).unwrap();
+    assert_eq!(pairwise_vector.offset, 3);
+    assert_eq!(pairwise_vector.vector.len(), 1);
+    assert_eq!(pairwise_vector.vector[0].local_seq, 3);
+    assert_eq!(hash_vectors_0.ack(&idkey_1, 10), 0);
+  }
+
+  #[test]
+  fn test_ack_keeps_unvalidated_entries() {
+    let idkey_0 = String::from("0");
+    let idkey_1 = String::from("1");
+    let mut hash_vectors_0 = HashVectors::new(idkey_0.clone());
+    let mut hash_vectors_1 = HashVectors::new(idkey_1.clone());
+    let recipients = vec![idkey_0.clone(), idkey_1.clone()];
+
+    let mut payloads = Vec::new();
+    for message in ["first message", "second message"] {
+      let (common_payload, recipient_payloads) = hash_vectors_0.prepare_message(
+          recipients.clone(),
+          message.to_string()
+      );
+      hash_vectors_0.parse_message(
+          &idkey_0,
+          common_payload.clone(),
+          recipient_payloads.get(&idkey_0).unwrap()
+      ).unwrap();
+      payloads.push((common_payload, recipient_payloads));
+    }
+
+    // 1 has only seen the first message when it replies
+    let (common_payload, recipient_payloads) = payloads.remove(0);
+    hash_vectors_1.parse_message(
+        &idkey_0,
+        common_payload,
+        recipient_payloads.get(&idkey_1).unwrap()
+    ).unwrap();
+    let (reply_payload, reply_recipient_payloads) = hash_vectors_1.prepare_message(
+        recipients.clone(),
+        String::from("reply")
+    );
+
+    // an over-eager ack must not drop what the reply is about to validate
+    assert_eq!(hash_vectors_0.ack(&idkey_1, 10), 0);
+    assert_eq!(
+        hash_vectors_0.parse_message(
+            &idkey_1,
+            reply_payload,
+            reply_recipient_payloads.get(&idkey_0).unwrap()
+        ).unwrap(),
+        Some((2, String::from("reply")))
+    );
+  }
+
   /* more complex tests */
 
   #[test]
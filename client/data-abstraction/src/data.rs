@@ -1,24 +1,34 @@
 use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::hash_map::Entry;
 use serde::{Serialize, Deserialize};
+use thiserror::Error;
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct BasicData {
   data_id: String,
-  data_val: String,
+  data_val: Vec<u8>,
+  // bumped on every local modification made through `DataStore::entry`
+  #[serde(default)]
+  version: u64,
 }
 
 impl BasicData {
-  pub fn new(data_id: String, data_val: String) -> BasicData {
-    Self { data_id, data_val }
+  pub fn new(data_id: String, data_val: impl Into<Vec<u8>>) -> BasicData {
+    Self { data_id, data_val: data_val.into(), version: 0 }
   }
 
   fn data_id(&self) -> &String {
     &self.data_id
   }
 
-  fn data_val(&self) -> &String {
+  pub fn data_val(&self) -> &Vec<u8> {
     &self.data_val
   }
+
+  pub fn version(&self) -> u64 {
+    self.version
+  }
 }
 
 // TODO struct GroupData
@@ -89,12 +99,96 @@ impl Validator {
   }
 }
 
-#[derive(Debug, PartialEq)]
+// Data belonging to a group is stored under "<group_id>::<data_id>"; the
+// separator cannot occur in uuids or base64-encoded idkeys
+const GROUP_DATA_SEPARATOR: &str = "::";
+
+#[derive(Debug, PartialEq, Error)]
+pub enum CipherError {
+  #[error("value could not be encrypted")]
+  EncryptionFailed,
+  #[error("value of {0} could not be decrypted")]
+  DecryptionFailed(String),
+}
+
+// Encrypts data values at rest
+pub trait ValueCipher {
+  fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, CipherError>;
+  fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, CipherError>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct DataStore {
   store: HashMap<String, BasicData>,
   //validator: Validator,
 }
 
+// The changes that turn one data store into another. Entries count as
+// changed if either their value or their version differs
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DataStoreDiff {
+  added: HashMap<String, BasicData>,
+  removed: HashSet<String>,
+  changed: HashMap<String, BasicData>,
+}
+
+impl DataStoreDiff {
+  pub fn added(&self) -> &HashMap<String, BasicData> {
+    &self.added
+  }
+
+  pub fn removed(&self) -> &HashSet<String> {
+    &self.removed
+  }
+
+  pub fn changed(&self) -> &HashMap<String, BasicData> {
+    &self.changed
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+  }
+}
+
+// A view into a single data_id of a `DataStore`, analogous to
+// `std::collections::hash_map::Entry`
+pub struct DataEntry<'a> {
+  entry: Entry<'a, String, BasicData>,
+}
+
+impl<'a> DataEntry<'a> {
+  pub fn key(&self) -> &String {
+    self.entry.key()
+  }
+
+  pub fn or_insert(self, default: Vec<u8>) -> &'a BasicData {
+    self.or_insert_with(|| default)
+  }
+
+  pub fn or_insert_with<F: FnOnce() -> Vec<u8>>(self, default: F) -> &'a BasicData {
+    match self.entry {
+      Entry::Occupied(entry) => entry.into_mut(),
+      Entry::Vacant(entry) => {
+        let data_id = entry.key().to_string();
+        entry.insert(BasicData::new(data_id, default()))
+      },
+    }
+  }
+
+  // Modifies an existing value in place and bumps its version
+  pub fn and_modify<F: FnOnce(&mut Vec<u8>)>(self, f: F) -> Self {
+    match self.entry {
+      Entry::Occupied(mut entry) => {
+        let data_val = entry.get_mut();
+        f(&mut data_val.data_val);
+        data_val.version += 1;
+        Self { entry: Entry::Occupied(entry) }
+      },
+      Entry::Vacant(entry) => Self { entry: Entry::Vacant(entry) },
+    }
+  }
+}
+
 //fn get_all_data_of_type
 impl DataStore {
   pub fn new() -> DataStore {
@@ -134,11 +228,96 @@ impl DataStore {
   pub fn get_all_data(&self) -> &HashMap<String, BasicData> {
     &self.store
   }
+
+  // Computes the changes needed to turn `baseline` into this store
+  pub fn diff(&self, baseline: &DataStore) -> DataStoreDiff {
+    let mut added = HashMap::<String, BasicData>::new();
+    let mut changed = HashMap::<String, BasicData>::new();
+    for (data_id, data_val) in self.store.iter() {
+      match baseline.store.get(data_id) {
+        None => {
+          added.insert(data_id.to_string(), data_val.clone());
+        },
+        Some(baseline_val) if baseline_val != data_val => {
+          changed.insert(data_id.to_string(), data_val.clone());
+        },
+        Some(_) => {},
+      }
+    }
+    let removed = baseline.store
+        .keys()
+        .filter(|data_id| !self.store.contains_key(*data_id))
+        .cloned()
+        .collect::<HashSet::<String>>();
+
+    DataStoreDiff { added, removed, changed }
+  }
+
+  pub fn apply_diff(&mut self, diff: &DataStoreDiff) {
+    for data_id in diff.removed.iter() {
+      self.store.remove(data_id);
+    }
+    for (data_id, data_val) in diff.added.iter().chain(diff.changed.iter()) {
+      self.store.insert(data_id.to_string(), data_val.clone());
+    }
+  }
+
+  // Re-encrypts every value from `old` to `new`, e.g. when rotating the
+  // at-rest cipher. Either every value is re-encrypted or, on error, none
+  pub fn rekey(
+      &mut self,
+      old: &dyn ValueCipher,
+      new: &dyn ValueCipher,
+  ) -> Result<(), CipherError> {
+    let mut rekeyed = HashMap::<String, Vec<u8>>::new();
+    for (data_id, data_val) in self.store.iter() {
+      let plaintext = old.decrypt(&data_val.data_val)
+          .map_err(|_| CipherError::DecryptionFailed(data_id.to_string()))?;
+      rekeyed.insert(data_id.to_string(), new.encrypt(&plaintext)?);
+    }
+
+    for (data_id, ciphertext) in rekeyed.into_iter() {
+      self.store.get_mut(&data_id).unwrap().data_val = ciphertext;
+    }
+    Ok(())
+  }
+
+  pub fn entry(&mut self, data_id: String) -> DataEntry<'_> {
+    DataEntry { entry: self.store.entry(data_id) }
+  }
+
+  pub fn group_data_id(group_id: &str, data_id: &str) -> String {
+    [group_id, data_id].join(GROUP_DATA_SEPARATOR)
+  }
+
+  pub fn data_ids_for_group(&self, group_id: &str) -> Vec<&String> {
+    let prefix = DataStore::group_data_id(group_id, "");
+    let mut data_ids = self.store
+        .keys()
+        .filter(|data_id| data_id.starts_with(&prefix))
+        .collect::<Vec::<&String>>();
+    data_ids.sort();
+    data_ids
+  }
 }
 
+#[cfg(test)]
 mod tests {
   use std::collections::HashMap;
-  use crate::data::{DataStore, BasicData};
+  use std::collections::HashSet;
+  use crate::data::{DataStore, BasicData, CipherError, ValueCipher};
+
+  struct XorCipher(u8);
+
+  impl ValueCipher for XorCipher {
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, CipherError> {
+      Ok(plaintext.iter().map(|byte| byte ^ self.0).collect())
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, CipherError> {
+      Ok(ciphertext.iter().map(|byte| byte ^ self.0).collect())
+    }
+  }
 
   #[test]
   fn test_new() {
@@ -161,4 +340,84 @@ mod tests {
     data_store.delete_data(data.data_id());
     assert_eq!(data_store.get_data(data.data_id()), None);
   }
+
+  #[test]
+  fn test_entry() {
+    let mut data_store = DataStore::new();
+    let data_id = String::from("0");
+
+    let data_val = data_store.entry(data_id.clone()).or_insert(vec![1]);
+    assert_eq!(data_val.data_val(), &vec![1]);
+    assert_eq!(data_val.version(), 0);
+
+    // an existing value is not replaced
+    let data_val = data_store.entry(data_id.clone()).or_insert_with(|| vec![9]);
+    assert_eq!(data_val.data_val(), &vec![1]);
+
+    let data_val = data_store.entry(data_id.clone())
+        .and_modify(|data_val| data_val.extend([2, 3]))
+        .or_insert(vec![]);
+    assert_eq!(data_val.data_val(), &vec![1, 2, 3]);
+    assert_eq!(data_val.version(), 1);
+    assert_eq!(data_store.get_data(&data_id).unwrap().version(), 1);
+
+    // and_modify does nothing to a missing value
+    let data_val = data_store.entry(String::from("1"))
+        .and_modify(|data_val| data_val.push(0))
+        .or_insert(vec![]);
+    assert_eq!(data_val.data_val(), &Vec::<u8>::new());
+    assert_eq!(data_val.data_id(), &String::from("1"));
+    assert_eq!(data_val.version(), 0);
+  }
+
+  #[test]
+  fn test_diff() {
+    let mut baseline = DataStore::new();
+    for data_id in ["kept", "changed", "bumped", "removed"] {
+      baseline.set_data(data_id.to_string(), BasicData::new(data_id.to_string(), "val"));
+    }
+
+    let mut data_store = baseline.clone();
+    assert!(data_store.diff(&baseline).is_empty());
+
+    data_store.set_data(String::from("changed"), BasicData::new(String::from("changed"), "new"));
+    data_store.entry(String::from("bumped")).and_modify(|_| {});
+    data_store.delete_data(&String::from("removed"));
+    data_store.set_data(String::from("added"), BasicData::new(String::from("added"), "val"));
+
+    let diff = data_store.diff(&baseline);
+    assert_eq!(
+        diff.added().keys().collect::<Vec::<&String>>(),
+        vec![&String::from("added")]
+    );
+    assert_eq!(
+        diff.changed().keys().cloned().collect::<HashSet::<String>>(),
+        HashSet::from([String::from("changed"), String::from("bumped")])
+    );
+    assert_eq!(
+        diff.removed(),
+        &HashSet::from([String::from("removed")])
+    );
+
+    baseline.apply_diff(&diff);
+    assert_eq!(baseline, data_store);
+  }
+
+  #[test]
+  fn test_rekey() {
+    let old = XorCipher(0x2a);
+    let new = XorCipher(0x5c);
+    let mut data_store = DataStore::new();
+    for (data_id, plaintext) in [("a", "first"), ("b", "second")] {
+      let ciphertext = old.encrypt(plaintext.as_bytes()).unwrap();
+      data_store.set_data(data_id.to_string(), BasicData::new(data_id.to_string(), ciphertext));
+    }
+
+    data_store.rekey(&old, &new).unwrap();
+    for (data_id, plaintext) in [("a", "first"), ("b", "second")] {
+      let data_val = data_store.get_data(&data_id.to_string()).unwrap();
+      assert_ne!(data_val.data_val(), &old.encrypt(plaintext.as_bytes()).unwrap());
+      assert_eq!(new.decrypt(data_val.data_val()).unwrap(), plaintext.as_bytes());
+    }
+  }
 }
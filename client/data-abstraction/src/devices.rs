@@ -1,24 +1,381 @@
+use serde::{Serialize, Deserialize};
 use std::collections::HashSet;
 use std::collections::HashMap;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 use uuid::Uuid;
 
-use crate::groups::{Group, GroupStore};
-use crate::data::DataStore;
+use crate::groups::{Group, GroupInconsistency, GroupStore, GroupStoreDiff, GroupStoreStats};
+use crate::data::{BasicData, DataStore};
 
 #[derive(Debug, PartialEq, Error)]
 pub enum Error {
   #[error("attempted to delete group instead of device")]
   DeviceHasChildren,
+  #[error("sender is not authorized to perform this operation")]
+  Unauthorized,
+  #[error("{0} is not a linked group of this device")]
+  UnknownLinkedGroup(String),
+  #[error("idkey {0} already belongs to a different group")]
+  DuplicateIdKey(String),
+  #[error("there is no pending update at index {0}")]
+  NoSuchPendingUpdate(usize),
+  #[error("invite is addressed to {0}, not to this device")]
+  InviteNotForDevice(String),
+  #[error("{0} tried to link without a pending link request")]
+  UnsolicitedLink(String),
+  #[error("linked group {0} and incoming linked group {1} differ in contact level")]
+  ContactLevelMismatch(String, String),
+  #[error("device is sealed")]
+  DeviceSealed,
+  #[error("linked name {0} is already the id of a non-linked group")]
+  NameCollision(String),
+  #[error("{0} is not a linked device of this device")]
+  NotLinkedMember(String),
+  #[error("op {1} from {0} was already applied")]
+  DuplicateOp(String, u64),
+  #[error("op {seq} from {origin} arrived before op {expected}")]
+  MissingOps {
+    origin: String,
+    expected: u64,
+    seq: u64,
+  },
+  #[error("journaled op {index} failed: {source}")]
+  ReplayFailed {
+    index: usize,
+    source: Box<Error>,
+  },
+  #[error(transparent)]
+  GroupErr {
+    #[from]
+    source: crate::groups::Error,
+  },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeviceInconsistency {
+  MissingLinkedGroup(String),
+  ContactLinkedGroup(String),
+  MissingDeviceGroup(String),
+  // (device group, linked group) where the linked group is not a parent
+  DeviceGroupNotLinked(String, String),
+  GroupStore(GroupInconsistency),
+}
+
+// An incoming group whose id already exists locally with different contents
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conflict {
+  group_id: String,
+  existing: Group,
+  incoming: Group,
+}
+
+impl Conflict {
+  pub fn group_id(&self) -> &String {
+    &self.group_id
+  }
+
+  pub fn existing(&self) -> &Group {
+    &self.existing
+  }
+
+  pub fn incoming(&self) -> &Group {
+    &self.incoming
+  }
+}
+
+// How a linked-group merge treats an incoming group whose id already exists
+// locally with different contents
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictPolicy {
+  // combine both versions: the union of their edges and attributes, with
+  // incoming attribute values replacing local ones
+  #[default]
+  Union,
+  // keep the local version and drop the incoming one
+  PreferExisting,
+}
+
+// Linked devices gained and lost by an operation
+#[derive(Debug, Clone, PartialEq)]
+pub struct MembershipDelta {
+  added: HashSet<String>,
+  removed: HashSet<String>,
+}
+
+impl MembershipDelta {
+  pub fn added(&self) -> &HashSet<String> {
+    &self.added
+  }
+
+  pub fn removed(&self) -> &HashSet<String> {
+    &self.removed
+  }
+}
+
+// Audit record of a single update_linked_group merge
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergeRecord {
+  sender: String,
+  // None if no clock was set on the device
+  timestamp: Option<u64>,
+  added: usize,
+}
+
+impl MergeRecord {
+  pub fn sender(&self) -> &String {
+    &self.sender
+  }
+
+  pub fn timestamp(&self) -> Option<u64> {
+    self.timestamp
+  }
+
+  pub fn added(&self) -> usize {
+    self.added
+  }
+}
+
+// An update_linked_group payload held until the user confirms or rejects it
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingUpdate {
+  sender: String,
+  temp_linked_name: String,
+  members_to_add: HashMap<String, Group>,
+}
+
+impl PendingUpdate {
+  pub fn sender(&self) -> &String {
+    &self.sender
+  }
+
+  pub fn temp_linked_name(&self) -> &String {
+    &self.temp_linked_name
+  }
+
+  pub fn members_to_add(&self) -> &HashMap<String, Group> {
+    &self.members_to_add
+  }
+}
+
+// A journaled device operation, replayed by `Device::replay_log` to rebuild
+// a device after a crash
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DeviceOp {
+  AddPendingLink(String),
+  UpdateLinkedGroup {
+    sender: String,
+    temp_linked_name: String,
+    members_to_add: HashMap<String, Group>,
+  },
+  ConfirmUpdateLinkedGroup {
+    new_linked_name: String,
+    new_groups: HashMap<String, Group>,
+  },
+  DeleteDevice(String),
+  SetData(String, BasicData),
+}
+
+// Which groups two devices should exchange to reconcile their stores; a
+// group is in at most one direction
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncPlan {
+  // groups the peer lacks or holds an older version of, sorted
+  to_send: Vec<String>,
+  // groups this device lacks or holds an older version of, sorted
+  to_request: Vec<String>,
+}
+
+impl SyncPlan {
+  pub fn to_send(&self) -> &[String] {
+    &self.to_send
+  }
+
+  pub fn to_request(&self) -> &[String] {
+    &self.to_request
+  }
+}
+
+// Periodic health summary of a device, see `Device::health_report`
+#[derive(Debug, Clone, PartialEq)]
+pub struct HealthReport {
+  inconsistencies: Vec<DeviceInconsistency>,
+  stats: GroupStoreStats,
+  // pending links older than the ttl, sorted
+  expired_links: Vec<String>,
+  data_count: usize,
+}
+
+impl HealthReport {
+  pub fn inconsistencies(&self) -> &[DeviceInconsistency] {
+    &self.inconsistencies
+  }
+
+  pub fn stats(&self) -> &GroupStoreStats {
+    &self.stats
+  }
+
+  pub fn expired_links(&self) -> &[String] {
+    &self.expired_links
+  }
+
+  pub fn data_count(&self) -> usize {
+    self.data_count
+  }
+
+  pub fn is_healthy(&self) -> bool {
+    self.inconsistencies.is_empty() && self.expired_links.is_empty()
+  }
+}
+
+// What a joining device can learn about an invite before accepting it
+#[derive(Debug, Clone, PartialEq)]
+pub struct InviteEvaluation {
+  inviter: String,
+  targets_this_device: bool,
+  // devices in the invite's linked group, including the invited one
+  member_count: usize,
+}
+
+impl InviteEvaluation {
+  pub fn inviter(&self) -> &String {
+    &self.inviter
+  }
+
+  pub fn targets_this_device(&self) -> bool {
+    self.targets_this_device
+  }
+
+  pub fn member_count(&self) -> usize {
+    self.member_count
+  }
+}
+
+// Who a broadcast from this device goes to. Contacts that resolve as linked
+// devices are split out from the regular recipients
+#[derive(Debug, Clone, PartialEq)]
+pub struct BroadcastPlan {
+  self_id: String,
+  // sorted by idkey
+  recipients: Vec<String>,
+  // sorted by idkey
+  contacts: Vec<String>,
+}
+
+impl BroadcastPlan {
+  pub fn self_id(&self) -> &String {
+    &self.self_id
+  }
+
+  pub fn recipients(&self) -> &[String] {
+    &self.recipients
+  }
+
+  pub fn contacts(&self) -> &[String] {
+    &self.contacts
+  }
 }
 
 #[derive(Debug, PartialEq)]
+pub enum LinkState {
+  Idle,
+  PendingOutbound(HashSet<String>),
+}
+
+// Sent by a device asking to be linked to `target_idkey`'s linked group
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LinkRequest {
+  sender: String,
+  linked_name: String,
+  members: HashMap<String, Group>,
+}
+
+impl LinkRequest {
+  pub fn sender(&self) -> &String {
+    &self.sender
+  }
+
+  pub fn linked_name(&self) -> &String {
+    &self.linked_name
+  }
+
+  pub fn members(&self) -> &HashMap<String, Group> {
+    &self.members
+  }
+}
+
+// Handed by an existing device to a brand-new device so that the latter can
+// join the former's linked group
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JoinInvite {
+  inviter: String,
+  target_idkey: String,
+  linked_name: String,
+  groups: HashMap<String, Group>,
+}
+
+impl JoinInvite {
+  pub fn inviter(&self) -> &String {
+    &self.inviter
+  }
+
+  pub fn target_idkey(&self) -> &String {
+    &self.target_idkey
+  }
+
+  pub fn linked_name(&self) -> &String {
+    &self.linked_name
+  }
+
+  pub fn groups(&self) -> &HashMap<String, Group> {
+    &self.groups
+  }
+}
+
+// Full snapshot of a device's state, taken by `Device::checkpoint`
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceCheckpoint {
+  device: Device,
+}
+
+// Injected time source. It is configuration rather than device state, so
+// any two clocks compare equal
+#[derive(Debug, Clone, Copy)]
+struct Clock(fn() -> u64);
+
+impl PartialEq for Clock {
+  fn eq(&self, _other: &Self) -> bool {
+    true
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Device {
   idkey: String,
   group_store: GroupStore,
   data_store: DataStore,
   linked_name: String,
-  pending_link_idkey: Option<String>,
+  linked_names: HashSet<String>,
+  pending_link_idkeys: HashSet<String>,
+  // when each pending link was added, for expiring abandoned links
+  pending_link_times: HashMap<String, Instant>,
+  pending_updates: Vec<PendingUpdate>,
+  // source of timestamps for automatically touching the linked group
+  clock: Option<Clock>,
+  merge_history: Vec<MergeRecord>,
+  // whether update_linked_group accepts senders without a pending link
+  allow_unsolicited: bool,
+  conflict_policy: ConflictPolicy,
+  // while sealed, operations that change the device's groups are refused
+  sealed: bool,
+  // peers the crypto layer currently has a live session with
+  active_sessions: HashSet<String>,
+  // peers blocked in the group store by `quarantine`
+  quarantined: HashSet<String>,
+  // sequence number of the last op applied from each origin device
+  version_vector: HashMap<String, u64>,
+  // applied remote ops as (origin, sequence number assigned by origin, op)
+  op_log: Vec<(String, u64, DeviceOp)>,
 }
 
 impl Device {
@@ -26,6 +383,28 @@ impl Device {
       idkey: String,
       linked_name_arg: Option<String>,
       pending_link_idkey: Option<String>
+  ) -> Device {
+    Device::new_with_data(idkey, linked_name_arg, pending_link_idkey, DataStore::new())
+  }
+
+  // Same as `new`, but rejects a linked name equal to this device's idkey,
+  // which would make the linked group and the device group collide; `new`
+  // does not check it
+  pub fn try_new(
+      idkey: String,
+      linked_name_arg: Option<String>,
+      pending_link_idkey: Option<String>
+  ) -> Result<Device, Error> {
+    Device::try_new_with_data(idkey, linked_name_arg, pending_link_idkey, DataStore::new())
+  }
+
+  // Same as `new`, but starts from existing data (e.g. when restoring a
+  // device) instead of an empty data store
+  pub fn new_with_data(
+      idkey: String,
+      linked_name_arg: Option<String>,
+      pending_link_idkey: Option<String>,
+      data_store: DataStore,
   ) -> Device {
     let linked_name = linked_name_arg.unwrap_or(Uuid::new_v4().to_string());
     let mut group_store = GroupStore::new();
@@ -42,400 +421,3229 @@ impl Device {
         false,
         false
     ));
-    group_store.link_groups(&linked_name, &idkey);
+    // only fails if the linked name collides with idkey (see try_new)
+    let _ = group_store.link_groups(&linked_name, &idkey);
 
     Self {
       idkey,
       group_store,
-      data_store: DataStore::new(),
+      data_store,
+      linked_names: HashSet::from([linked_name.clone()]),
       linked_name,
-      pending_link_idkey,
+      pending_link_times: pending_link_idkey
+          .iter()
+          .map(|idkey| (idkey.clone(), Instant::now()))
+          .collect(),
+      pending_link_idkeys: pending_link_idkey.into_iter().collect(),
+      pending_updates: Vec::new(),
+      clock: None,
+      merge_history: Vec::new(),
+      allow_unsolicited: false,
+      conflict_policy: ConflictPolicy::default(),
+      sealed: false,
+      active_sessions: HashSet::new(),
+      quarantined: HashSet::new(),
+      version_vector: HashMap::new(),
+      op_log: Vec::new(),
     }
   }
 
-  pub fn idkey(&self) -> &String {
-    &self.idkey
+  // Same as `new_with_data`, with the linked name check of `try_new`
+  pub fn try_new_with_data(
+      idkey: String,
+      linked_name_arg: Option<String>,
+      pending_link_idkey: Option<String>,
+      data_store: DataStore,
+  ) -> Result<Device, Error> {
+    if linked_name_arg.as_ref() == Some(&idkey) {
+      return Err(Error::NameCollision(idkey));
+    }
+    Ok(Device::new_with_data(idkey, linked_name_arg, pending_link_idkey, data_store))
   }
 
-  pub fn linked_name(&self) -> &String {
-    &self.linked_name
+  // By default, update_linked_group only accepts senders this device has a
+  // pending link with, so that a peer cannot forcibly merge itself into the
+  // linked group. Trusted deployments can lift this restriction
+  pub fn allow_unsolicited(mut self, allow_unsolicited: bool) -> Device {
+    self.allow_unsolicited = allow_unsolicited;
+    self
   }
 
-  pub fn linked_devices_excluding_self(&self) -> Vec<String> {
-    self.group_store()
-        .resolve_ids(vec![self.linked_name()])
-        .iter()
-        .filter(|&x| *x != self.idkey())
-        .map(|&x| x.clone())
-        .collect::<Vec::<String>>()
+  // Default policy for update_linked_group and confirm_update_linked_group
+  pub fn with_conflict_policy(mut self, conflict_policy: ConflictPolicy) -> Device {
+    self.conflict_policy = conflict_policy;
+    self
   }
 
-  pub fn linked_devices_excluding_self_and_other(&self, other: &String) -> Vec<String> {
-    self.group_store()
-        .resolve_ids(vec![self.linked_name()])
-        .iter()
-        .filter(|&x| *x != self.idkey() && *x != other)
-        .map(|&x| x.clone())
-        .collect::<Vec::<String>>()
+  pub fn conflict_policy(&self) -> ConflictPolicy {
+    self.conflict_policy
   }
 
-  pub fn linked_devices(&self) -> HashSet<&String> {
-    self.group_store().resolve_ids(vec![self.linked_name()])
+  // Freezes the device (e.g. during recovery or a dispute) so that linking
+  // and deleting fail with DeviceSealed until it is unsealed
+  pub fn seal(&mut self) {
+    self.sealed = true;
   }
 
-  pub fn group_store(&self) -> &GroupStore {
-    &self.group_store
+  pub fn unseal(&mut self) {
+    self.sealed = false;
   }
 
-  pub fn group_store_mut(&mut self) -> &mut GroupStore {
-    &mut self.group_store
+  pub fn is_sealed(&self) -> bool {
+    self.sealed
   }
 
-  pub fn data_store(&self) -> &DataStore {
-    &self.data_store
+  pub fn idkey(&self) -> &String {
+    &self.idkey
   }
 
-  pub fn data_store_mut(&mut self) -> &mut DataStore {
-    &mut self.data_store
+  // The primary linked group, i.e. the one other devices are linked into
+  pub fn linked_name(&self) -> &String {
+    &self.linked_name
   }
 
-  fn set_pending_link_idkey(&mut self, idkey: String) {
-    self.pending_link_idkey = Some(idkey);
+  // Renames this device's group after its identity key changed
+  pub fn rotate_idkey(&mut self, new_idkey: String) -> Result<(), Error> {
+    self.group_store.rename_group(&self.idkey, &new_idkey)?;
+    self.idkey = new_idkey;
+    self.touch_with_clock();
+    Ok(())
   }
 
-  fn get_pending_link_idkey(&self) -> &Option<String> {
-    &self.pending_link_idkey
+  // Once a clock is set, every method that changes the group store touches
+  // the (primary) linked group with its current time
+  pub fn set_clock(&mut self, clock: fn() -> u64) {
+    self.clock = Some(Clock(clock));
   }
 
-  fn clear_pending_link_idkey(&mut self) {
-    self.pending_link_idkey = None;
+  // Marks a local change by advancing the linked group's updated_at, which
+  // is used to break ties when merging group stores; never moves it back
+  pub fn touch(&mut self, now: u64) {
+    let linked_name = self.linked_name.clone();
+    if let Some(linked_group) = self.group_store.get_group_mut(&linked_name) {
+      if linked_group.updated_at().is_none_or(|updated_at| updated_at < now) {
+        linked_group.set_updated_at(now);
+      }
+    }
   }
 
-  // TODO user needs to confirm via, e.g. pop-up
-  pub fn update_linked_group(
-      &mut self,
-      sender: String,
-      temp_linked_name: String,
-      mut members_to_add: HashMap<String, Group>,
-  ) -> Result<(), Error> {
-    println!("IN UPDATE_LINKED_GROUP");
-    let currently_linked_devices = self.linked_devices();
-    let perm_linked_name = self.linked_name().clone();
+  fn now(&self) -> Option<u64> {
+    self.clock.map(|Clock(clock)| clock())
+  }
 
-    let temp_linked_group = members_to_add.get(&temp_linked_name).unwrap().clone();
-    members_to_add.remove(&temp_linked_name);
+  fn touch_with_clock(&mut self) {
+    if let Some(now) = self.now() {
+      self.touch(now);
+    }
+  }
 
-    members_to_add.iter_mut().for_each(|(_, val)| {
-      GroupStore::group_replace(
-          val,
-          temp_linked_name.clone(),
-          perm_linked_name.to_string(),
-      );
-    });
+  pub fn merge_history(&self) -> &[MergeRecord] {
+    &self.merge_history
+  }
 
-    // set all groups whose id is not temp_linked_name
-    members_to_add.iter_mut().for_each(|(id, val)| {
-      self.group_store.set_group(id.to_string(), val.clone());
-    });
+  pub fn linked_names(&self) -> &HashSet<String> {
+    &self.linked_names
+  }
 
-    // merge temp_linked_name group into perm_linked_name group
-    for parent in temp_linked_group.parents() {
-      self.group_store.add_parent(&perm_linked_name, parent);
-    }
-    for child in temp_linked_group.children().as_ref().unwrap() {
-      self.group_store.add_child(&perm_linked_name, child);
+  // Creates an additional linked group (e.g. a separate work cluster) that
+  // this device belongs to but that is never merged with the primary one
+  pub fn add_linked_group(
+      &mut self,
+      linked_name_arg: Option<String>,
+  ) -> Result<String, Error> {
+    let linked_name = linked_name_arg.unwrap_or(Uuid::new_v4().to_string());
+    // the new group would overwrite any existing group of the same id,
+    // whether it is a device, contact, intermediate or linked group
+    if self.group_store.get_group(&linked_name).is_some() {
+      return Err(Error::NameCollision(linked_name));
     }
 
-    Ok(())
+    self.group_store.set_group(linked_name.clone(), Group::new(
+        Some(linked_name.clone()),
+        false,
+        true
+    ));
+    self.group_store.link_groups(&linked_name, &self.idkey)?;
+    self.linked_names.insert(linked_name.clone());
+    self.touch_with_clock();
+
+    Ok(linked_name)
   }
 
-  pub fn confirm_update_linked_group(
+  // An existing group adopted as linked group must be able to have
+  // children, unlike a device group
+  fn check_linked_name(&self, linked_name: &String) -> Result<(), Error> {
+    match self.group_store.get_group(linked_name) {
+      Some(group_val) if group_val.is_leaf() => {
+        Err(Error::NameCollision(linked_name.to_string()))
+      },
+      _ => Ok(()),
+    }
+  }
+
+  pub fn set_primary_linked_name(
       &mut self,
-      new_linked_name: String,
-      new_groups: HashMap<String, Group>,
+      linked_name: String,
   ) -> Result<(), Error> {
-    println!("IN CONFIRM_UPDATE_LINKED_GROUP");
-    self.group_store.delete_group(&self.linked_name.clone());
-
-    self.linked_name = new_linked_name;
-    for (group_id, group_val) in new_groups.iter() {
-      self.group_store.set_group(group_id.to_string(), group_val.clone());
+    if !self.linked_names.contains(&linked_name) {
+      return Err(Error::UnknownLinkedGroup(linked_name));
     }
 
-    self.clear_pending_link_idkey();
+    self.linked_name = linked_name;
 
     Ok(())
   }
 
-  // FIXME Currently, this function is unnecessary since none of this data
-  // is persistent and will be automatically GC'd when the `device` field
-  // of the glue object is set to `None`. But in the future, this function
-  // should be used to clean up any related persistent data
-  pub fn delete_device(&mut self, to_delete: String) -> Result<(), Error> {
-    let device_group = self.group_store.get_group(&to_delete).unwrap().clone();
-    if device_group.children().as_ref().is_some() {
-      return Err(Error::DeviceHasChildren);
+  // Moves this device's group from the primary linked group to
+  // `new_linked_name`, which must already exist in the group store
+  pub fn switch_linked_group(
+      &mut self,
+      new_linked_name: String,
+  ) -> Result<(), Error> {
+    if new_linked_name == self.linked_name {
+      return Ok(());
     }
+    self.check_linked_name(&new_linked_name)?;
 
-    // remove child link to this device from 
-    // every parent (should have no children)
-    for parent in device_group.parents().iter() {
-      self.group_store.remove_child(parent, &to_delete);
-    }
+    let old_linked_name = self.linked_name.clone();
+    let idkey = self.idkey.clone();
+    self.group_store.link_groups(&new_linked_name, &idkey)?;
+    self.group_store.unlink_groups(&old_linked_name, &idkey)?;
 
-    self.group_store.delete_group(&to_delete);
+    self.linked_names.remove(&old_linked_name);
+    self.linked_names.insert(new_linked_name.clone());
+    self.linked_name = new_linked_name;
+    self.touch_with_clock();
 
     Ok(())
   }
-}
 
-mod tests {
-  use crate::devices::Device;
-  use crate::groups::{Group, GroupStore};
-  use std::collections::HashSet;
+  // sorted by idkey so that broadcasts are sent in a stable order
+  pub fn linked_devices_excluding_self(&self) -> Vec<String> {
+    let mut linked_devices = self.linked_devices_opt(false)
+        .iter()
+        .map(|&x| x.clone())
+        .collect::<Vec::<String>>();
+    linked_devices.sort();
+    linked_devices
+  }
 
-  #[test]
-  fn test_new_standalone() {
-    let idkey = String::from("0");
-    let linked_name = String::from("linked");
-    let device = Device::new(idkey.clone(), Some(linked_name.clone()), None);
+  // Linked devices other than this one, in no particular order, without
+  // collecting them into a Vec first
+  pub fn linked_peers(&self) -> impl Iterator<Item = &String> {
+    self.linked_peers_opt(false)
+  }
 
-    let linked_group = device.group_store().get_group(&linked_name).unwrap();
-    assert_eq!(linked_group.group_id(), &linked_name);
-    assert_eq!(linked_group.contact_level(), &false);
-    assert_eq!(linked_group.parents(), &HashSet::<String>::new());
-    assert_eq!(linked_group.children(), &Some(HashSet::<String>::from([idkey.clone()])));
+  // Same as `linked_peers`, optionally skipping peers without a live session
+  pub fn linked_peers_opt(&self, active_only: bool) -> impl Iterator<Item = &String> {
+    self.linked_devices()
+        .into_iter()
+        .filter(move |&x| x != self.idkey())
+        .filter(move |&x| !active_only || self.active_sessions.contains(x))
+  }
 
-    let idkey_group = device.group_store().get_group(&idkey).unwrap();
-    assert_eq!(idkey_group.group_id(), &idkey);
-    assert_eq!(idkey_group.contact_level(), &false);
-    assert_eq!(idkey_group.parents(), &HashSet::<String>::from([linked_name.clone()]));
-    assert_eq!(idkey_group.children(), &None);
+  // Excludes a (e.g. suspected compromised) peer from resolution, and thus
+  // from linked_peers and broadcast plans, without deleting its group. A
+  // peer that is already blocked in the group store is left alone
+  pub fn quarantine(&mut self, peer: &str) -> bool {
+    if self.group_store.is_blocked(peer) {
+      return false;
+    }
+    self.group_store.block(peer);
+    self.quarantined.insert(peer.to_string())
+  }
 
-    assert_eq!(device.idkey, idkey);
-    assert_eq!(device.linked_name, linked_name);
-    assert_eq!(device.pending_link_idkey, None);
+  // Only unblocks peers that were blocked by quarantine
+  pub fn release(&mut self, peer: &str) -> bool {
+    if !self.quarantined.remove(peer) {
+      return false;
+    }
+    self.group_store.unblock(peer);
+    true
   }
 
-  #[test]
-  fn test_get_linked_name() {
-    let idkey = String::from("0");
-    let linked_name = String::from("linked");
-    let device_0 = Device::new(idkey.clone(), Some(linked_name.clone()), None);
-    assert_eq!(device_0.linked_name(), &linked_name);
+  pub fn quarantined(&self) -> &HashSet<String> {
+    &self.quarantined
+  }
 
-    let device_1 = Device::new(idkey, None, None);
-    assert_ne!(device_1.linked_name(), &linked_name);
+  // Called by the crypto layer when a session with `peer` is established
+  pub fn mark_session_active(&mut self, peer: &str) {
+    self.active_sessions.insert(peer.to_string());
   }
 
-  #[test]
-  fn test_update_linked_group() {
-    let idkey_0 = String::from("0");
-    let mut device_0 = Device::new(idkey_0.clone(), None, None);
-    let linked_name_0 = device_0.linked_name().clone();
-    let linked_members_0 = device_0.group_store().get_all_subgroups(&linked_name_0);
+  pub fn clear_session(&mut self, peer: &str) -> bool {
+    self.active_sessions.remove(peer)
+  }
+
+  // Linked peers with a live session
+  pub fn active_peers(&self) -> HashSet<&String> {
+    self.linked_peers_opt(true).collect()
+  }
+
+  pub fn linked_devices_excluding_self_and_other(&self, other: &String) -> Vec<String> {
+    let mut linked_devices = self.linked_devices_opt(false)
+        .iter()
+        .filter(|&x| *x != other)
+        .map(|&x| x.clone())
+        .collect::<Vec::<String>>();
+    linked_devices.sort();
+    linked_devices
+  }
+
+  // Linked devices other than this one and those in `extra_exclusions`,
+  // in a stable order
+  pub fn broadcast_plan(&self, extra_exclusions: &HashSet<String>) -> BroadcastPlan {
+    let (contacts, recipients): (Vec<String>, Vec<String>) = self
+        .linked_devices_excluding_self()
+        .into_iter()
+        .filter(|idkey| !extra_exclusions.contains(idkey))
+        .partition(|idkey| {
+          self.group_store
+              .get_group(idkey)
+              .is_some_and(|group_val| *group_val.contact_level())
+        });
+
+    BroadcastPlan {
+      self_id: self.idkey().clone(),
+      recipients,
+      contacts,
+    }
+  }
+
+  // Checks a peer's claimed membership path (e.g. from resolve_with_witness)
+  // against this device's own store: every consecutive pair must be a
+  // parent/child edge here, and the path must end at `member`. A path needs
+  // at least one edge, so a bare `[member]` proves nothing
+  pub fn verify_membership_proof(&self, member: &str, path: &[String]) -> bool {
+    if path.len() < 2 {
+      return false;
+    }
+    if path.last().map(|last| last.as_str()) != Some(member) {
+      return false;
+    }
+    if !self.group_store.get_all_groups().contains_key(member) {
+      return false;
+    }
+
+    path.windows(2).all(|edge| {
+      let (parent_id, child_id) = (&edge[0], &edge[1]);
+      let parent_has_child = self.group_store
+          .get_group(parent_id)
+          .is_some_and(|parent_val| parent_val.children().contains(child_id));
+      let child_has_parent = self.group_store
+          .get_group(child_id)
+          .is_some_and(|child_val| child_val.parents().contains(parent_id));
+      parent_has_child && child_has_parent
+    })
+  }
+
+  // Devices across all of this device's linked groups. This device's own
+  // group is a child of every linked group, so the result always contains
+  // this device's idkey
+  pub fn linked_devices(&self) -> HashSet<&String> {
+    self.group_store().resolve_ids(self.linked_names())
+  }
+
+  // One page of linked_devices, sorted by idkey so that consecutive pages
+  // neither overlap nor skip members
+  pub fn linked_devices_page(&self, offset: usize, limit: usize) -> Vec<String> {
+    let mut linked_devices = self.linked_devices()
+        .into_iter()
+        .collect::<Vec::<&String>>();
+    linked_devices.sort();
+    linked_devices
+        .into_iter()
+        .skip(offset)
+        .take(limit)
+        .cloned()
+        .collect()
+  }
+
+  pub fn linked_devices_opt(&self, include_self: bool) -> HashSet<&String> {
+    let mut linked_devices = self.linked_devices();
+    if !include_self {
+      linked_devices.remove(self.idkey());
+    }
+    linked_devices
+  }
+
+  // Short digest of the primary linked group's members that users can
+  // compare out of band: every device in the same linked group renders the
+  // same fingerprint, e.g. "3fa2-91c0-7b44-e810"
+  pub fn linked_group_fingerprint(&self) -> String {
+    use sha2::Digest;
+
+    let mut members = self.linked_devices_in(self.linked_name())
+        .into_iter()
+        .collect::<Vec::<&String>>();
+    members.sort();
+
+    let mut hasher = sha2::Sha256::new();
+    for (i, member) in members.iter().enumerate() {
+      hasher.update(u64::to_be_bytes(i as u64));
+      hasher.update(u64::to_be_bytes(member.len() as u64));
+      hasher.update(member.as_bytes());
+    }
+    let digest = hasher.finalize();
+
+    digest[..8]
+        .chunks(2)
+        .map(|chunk| format!("{:02x}{:02x}", chunk[0], chunk[1]))
+        .collect::<Vec::<String>>()
+        .join("-")
+  }
+
+  pub fn linked_devices_in(&self, linked_name: &String) -> HashSet<&String> {
+    match self.linked_names.get(linked_name) {
+      Some(linked_name) => self.group_store().resolve_ids(vec![linked_name]),
+      None => HashSet::new(),
+    }
+  }
+
+  pub fn group_store(&self) -> &GroupStore {
+    &self.group_store
+  }
+
+  // The device keeps no membership state of its own, so edits made here are
+  // safe: linked_devices always resolves against the store, whose resolve
+  // cache is invalidated by every mutating GroupStore method (including
+  // get_group_mut)
+  pub fn group_store_mut(&mut self) -> &mut GroupStore {
+    &mut self.group_store
+  }
+
+  // A self-contained copy of the branch under `group_id`, e.g. to share it
+  // with a contact without exposing the rest of the store
+  pub fn export_subtree(&self, group_id: &str) -> Result<GroupStore, Error> {
+    Ok(self.group_store.subgraph(group_id)?)
+  }
+
+  // Only the contact groups, with edges to any of this device's own
+  // (non-contact) groups removed, so the recipient learns nothing about
+  // this device's topology
+  pub fn export_contacts_only(&self) -> GroupStore {
+    let contact_ids = self.group_store
+        .iter()
+        .filter(|(_, val)| *val.contact_level())
+        .map(|(id, _)| id)
+        .collect::<HashSet::<&String>>();
+
+    let mut exported = GroupStore::new();
+    for id in contact_ids.iter() {
+      let mut val = self.group_store.get_group(id).unwrap().clone();
+      let parents = val.parents().clone();
+      for parent_id in parents.iter().filter(|parent_id| !contact_ids.contains(parent_id)) {
+        val.remove_parent(parent_id);
+      }
+      let children = val.children().clone();
+      for child_id in children.iter().filter(|child_id| !contact_ids.contains(child_id)) {
+        // only groups with children get here, so this cannot fail
+        let _ = val.remove_child(child_id);
+      }
+      exported.set_group(id.to_string(), val);
+    }
+    exported
+  }
+
+  // Only the changes relative to a peer's (possibly stale) copy of this
+  // device's group store need to be sent to that peer
+  pub fn delta_since(&self, baseline: &GroupStore) -> GroupStoreDiff {
+    self.group_store.diff(baseline)
+  }
+
+  // Versions of a group present on both sides are compared the same way
+  // GroupStore::merge compares them
+  pub fn diff_against(&self, peer_store: &GroupStore) -> SyncPlan {
+    let local_groups = self.group_store.get_all_groups();
+    let peer_groups = peer_store.get_all_groups();
+
+    let mut to_send = Vec::<String>::new();
+    for (id, local_val) in local_groups.iter() {
+      match peer_groups.get(id) {
+        Some(peer_val) if !GroupStore::merge_wins(local_val, peer_val) => {},
+        _ => to_send.push(id.to_string()),
+      }
+    }
+    let mut to_request = Vec::<String>::new();
+    for (id, peer_val) in peer_groups.iter() {
+      match local_groups.get(id) {
+        Some(local_val) if !GroupStore::merge_wins(peer_val, local_val) => {},
+        _ => to_request.push(id.to_string()),
+      }
+    }
+
+    to_send.sort();
+    to_request.sort();
+    SyncPlan { to_send, to_request }
+  }
+
+  pub fn apply_delta(&mut self, delta: &GroupStoreDiff) {
+    self.group_store.apply_diff(delta);
+    self.touch_with_clock();
+  }
+
+  // Bytes that a sync would put on the wire: the whole group store, or only
+  // the delta against `baseline` if the peer's copy is known. Uses the same
+  // JSON serialization as the messages that carry them
+  pub fn estimate_sync_size(&self, baseline: Option<&GroupStore>) -> usize {
+    let serialized = match baseline {
+      Some(baseline) => serde_json::to_vec(&self.delta_since(baseline)),
+      None => serde_json::to_vec(&self.group_store),
+    };
+    serialized.map_or(0, |bytes| bytes.len())
+  }
+
+  pub fn data_store(&self) -> &DataStore {
+    &self.data_store
+  }
+
+  pub fn data_store_mut(&mut self) -> &mut DataStore {
+    &mut self.data_store
+  }
+
+  // Keys of all data stored under group_id's namespace (see
+  // `DataStore::group_data_id`)
+  pub fn data_keys_for_group(&self, group_id: &str) -> Vec<&String> {
+    self.data_store.data_ids_for_group(group_id)
+  }
+
+  // The exported groups already contain the new device as a member of the
+  // linked group, so the new device ends up with the same state it would
+  // have after a full UpdateLinked/ConfirmUpdateLinked exchange
+  pub fn export_for_new_device(&mut self, new_idkey: &str) -> JoinInvite {
+    self.set_pending_link_idkey(new_idkey.to_string());
+
+    let mut groups = self.group_store().get_all_groups().clone();
+    let mut new_device_group = Group::new(Some(new_idkey.to_string()), false, false);
+    new_device_group.add_parent(self.linked_name().clone());
+    groups.insert(new_idkey.to_string(), new_device_group);
+    groups.get_mut(self.linked_name())
+        .unwrap()
+        .add_child(new_idkey.to_string())
+        .unwrap();
+
+    JoinInvite {
+      inviter: self.idkey().clone(),
+      target_idkey: new_idkey.to_string(),
+      linked_name: self.linked_name().clone(),
+      groups,
+    }
+  }
+
+  // Everything a periodic health check looks at; nothing is repaired or
+  // swept
+  pub fn health_report(&self, now: Instant, link_ttl: Duration) -> HealthReport {
+    HealthReport {
+      inconsistencies: self.validate().err().unwrap_or_default(),
+      stats: self.group_store.stats(),
+      expired_links: self.expired_links(link_ttl, now),
+      data_count: self.data_store.get_all_data().len(),
+    }
+  }
+
+  pub fn validate(&self) -> Result<(), Vec<DeviceInconsistency>> {
+    let mut inconsistencies = Vec::<DeviceInconsistency>::new();
+
+    let device_group = self.group_store.get_group(&self.idkey);
+    if device_group.is_none() {
+      inconsistencies.push(DeviceInconsistency::MissingDeviceGroup(
+          self.idkey.clone()
+      ));
+    }
+
+    let mut linked_names = self.linked_names.iter().collect::<Vec::<&String>>();
+    linked_names.sort();
+    for linked_name in linked_names {
+      match self.group_store.get_group(linked_name) {
+        Some(linked_group) if *linked_group.contact_level() => {
+          inconsistencies.push(DeviceInconsistency::ContactLinkedGroup(
+              linked_name.clone()
+          ));
+        },
+        Some(_) => {},
+        None => inconsistencies.push(DeviceInconsistency::MissingLinkedGroup(
+            linked_name.clone()
+        )),
+      }
+
+      if let Some(device_group) = device_group {
+        if !device_group.parents().contains(linked_name) {
+          inconsistencies.push(DeviceInconsistency::DeviceGroupNotLinked(
+              self.idkey.clone(),
+              linked_name.clone(),
+          ));
+        }
+      }
+    }
+
+    if let Err(group_inconsistencies) = self.group_store.validate() {
+      inconsistencies.extend(group_inconsistencies
+          .into_iter()
+          .map(DeviceInconsistency::GroupStore));
+    }
+
+    if inconsistencies.is_empty() {
+      Ok(())
+    } else {
+      Err(inconsistencies)
+    }
+  }
+
+  pub fn checkpoint(&self) -> DeviceCheckpoint {
+    DeviceCheckpoint { device: self.clone() }
+  }
+
+  pub fn restore_checkpoint(&mut self, checkpoint: DeviceCheckpoint) {
+    *self = checkpoint.device;
+  }
+
+  pub fn add_pending_link(&mut self, idkey: String) -> bool {
+    self.add_pending_link_at(idkey, Instant::now())
+  }
+
+  // Same as `add_pending_link`, but with an explicit time for the link to
+  // expire from; re-adding a pending link restarts its expiry
+  pub fn add_pending_link_at(&mut self, idkey: String, added_at: Instant) -> bool {
+    self.pending_link_times.insert(idkey.clone(), added_at);
+    self.pending_link_idkeys.insert(idkey)
+  }
+
+  pub fn remove_pending_link(&mut self, idkey: &String) -> bool {
+    self.pending_link_times.remove(idkey);
+    self.pending_link_idkeys.remove(idkey)
+  }
+
+  // Pending links older than `ttl` at `now`, sorted
+  pub fn expired_links(&self, ttl: Duration, now: Instant) -> Vec<String> {
+    let mut expired = self.pending_link_idkeys
+        .iter()
+        .filter(|idkey| {
+          self.pending_link_times
+              .get(*idkey)
+              .is_none_or(|added_at| now.saturating_duration_since(*added_at) > ttl)
+        })
+        .cloned()
+        .collect::<Vec::<String>>();
+    expired.sort();
+    expired
+  }
+
+  // Clears every pending link older than `ttl` at `now`, returning the
+  // cleared idkeys sorted
+  pub fn sweep_expired_links(&mut self, ttl: Duration, now: Instant) -> Vec<String> {
+    let expired = self.expired_links(ttl, now);
+    for idkey in expired.iter() {
+      self.remove_pending_link(idkey);
+    }
+    expired
+  }
+
+  pub fn pending_links(&self) -> &HashSet<String> {
+    &self.pending_link_idkeys
+  }
+
+  // Convenience for the common case of a single in-flight link; returns
+  // `None` if there are no pending links or more than one
+  pub fn pending_link_idkey(&self) -> Option<&String> {
+    if self.pending_link_idkeys.len() != 1 {
+      return None;
+    }
+    self.pending_link_idkeys.iter().next()
+  }
+
+  fn set_pending_link_idkey(&mut self, idkey: String) {
+    self.add_pending_link(idkey);
+  }
+
+  fn clear_pending_link_idkeys(&mut self) {
+    self.pending_link_idkeys.clear();
+    self.pending_link_times.clear();
+  }
+
+  // Human-readable summary of this device's state, including its groups
+  // rendered as an indented tree
+  pub fn describe(&self) -> String {
+    let mut pending_links = self.pending_link_idkeys
+        .iter()
+        .map(|idkey| idkey.as_str())
+        .collect::<Vec::<&str>>();
+    pending_links.sort();
+    let pending_links = match pending_links.is_empty() {
+      true => String::from("none"),
+      false => pending_links.join(", "),
+    };
+
+    let mut description = format!(
+        "device {}\nlinked name: {}\npending links: {}\nlinked devices: {}\ngroups:\n",
+        self.idkey,
+        self.linked_name,
+        pending_links,
+        self.linked_devices().len(),
+    );
+
+    let mut roots = self.group_store
+        .iter()
+        .filter(|(_, val)| val.parents().is_empty())
+        .map(|(id, _)| id)
+        .collect::<Vec::<&String>>();
+    roots.sort();
+    for root in roots {
+      self.describe_group(&mut description, &mut Vec::new(), root);
+    }
+    description
+  }
+
+  fn describe_group<'a>(
+      &'a self,
+      description: &mut String,
+      path: &mut Vec<&'a String>,
+      group_id: &'a String,
+  ) {
+    description.push_str(&"  ".repeat(path.len() + 1));
+    description.push_str(group_id);
+    // stop at cycles rather than recursing forever
+    if path.contains(&group_id) {
+      description.push_str(" (cycle)\n");
+      return;
+    }
+    description.push('\n');
+
+    let children = self.group_store
+        .get_group(group_id)
+        .filter(|val| !val.is_leaf())
+        .map(|val| val.children());
+    if let Some(children) = children {
+      let mut children = children.iter().collect::<Vec::<&String>>();
+      children.sort();
+      path.push(group_id);
+      for child in children {
+        self.describe_group(description, path, child);
+      }
+      path.pop();
+    }
+  }
+
+  pub fn link_state(&self) -> LinkState {
+    if self.pending_link_idkeys.is_empty() {
+      return LinkState::Idle;
+    }
+    LinkState::PendingOutbound(self.pending_link_idkeys.clone())
+  }
+
+  pub fn initiate_link(&mut self, target_idkey: String) -> LinkRequest {
+    self.set_pending_link_idkey(target_idkey);
+
+    LinkRequest {
+      sender: self.idkey().clone(),
+      linked_name: self.linked_name().clone(),
+      members: self.group_store().get_all_subgroups(self.linked_name()),
+    }
+  }
+
+  // Holds an incoming update_linked_group payload until the user confirms
+  // (confirm_pending) or rejects (reject_pending) it
+  pub fn enqueue_update_linked_group(
+      &mut self,
+      sender: String,
+      temp_linked_name: String,
+      members_to_add: HashMap<String, Group>,
+  ) {
+    self.pending_updates.push(PendingUpdate {
+      sender,
+      temp_linked_name,
+      members_to_add,
+    });
+  }
+
+  pub fn pending_updates(&self) -> &[PendingUpdate] {
+    &self.pending_updates
+  }
+
+  // Applies the pending update at `index`; it stays queued if applying it
+  // fails
+  pub fn confirm_pending(&mut self, index: usize) -> Result<(), Error> {
+    let pending = match self.pending_updates.get(index) {
+      Some(pending) => pending.clone(),
+      None => return Err(Error::NoSuchPendingUpdate(index)),
+    };
+
+    self.update_linked_group(
+        pending.sender,
+        pending.temp_linked_name,
+        pending.members_to_add,
+    )?;
+    self.pending_updates.remove(index);
+
+    Ok(())
+  }
+
+  pub fn reject_pending(&mut self, index: usize) -> Result<PendingUpdate, Error> {
+    if index >= self.pending_updates.len() {
+      return Err(Error::NoSuchPendingUpdate(index));
+    }
+    Ok(self.pending_updates.remove(index))
+  }
+
+  // TODO user needs to confirm via, e.g. pop-up (see confirm_pending)
+  pub fn update_linked_group(
+      &mut self,
+      sender: String,
+      temp_linked_name: String,
+      members_to_add: HashMap<String, Group>,
+  ) -> Result<(), Error> {
+    let conflict_policy = self.conflict_policy;
+    self.update_linked_group_with_policy(
+        sender,
+        temp_linked_name,
+        members_to_add,
+        conflict_policy,
+    )
+  }
+
+  pub fn update_linked_group_with_policy(
+      &mut self,
+      sender: String,
+      temp_linked_name: String,
+      mut members_to_add: HashMap<String, Group>,
+      conflict_policy: ConflictPolicy,
+  ) -> Result<(), Error> {
+    println!("IN UPDATE_LINKED_GROUP");
+    if self.sealed {
+      return Err(Error::DeviceSealed);
+    }
+    if !self.allow_unsolicited && !self.pending_link_idkeys.contains(&sender) {
+      return Err(Error::UnsolicitedLink(sender));
+    }
+
+    let currently_linked_devices = self.linked_devices();
+    let linked_count_before = currently_linked_devices.len();
+    let perm_linked_name = self.linked_name().clone();
+
+    let temp_linked_group = members_to_add.get(&temp_linked_name).unwrap().clone();
+    members_to_add.remove(&temp_linked_name);
+
+    // a contact group cannot be merged into a non-contact one or vice versa
+    let perm_contact_level = self.group_store
+        .get_group(&perm_linked_name)
+        .map(|group_val| *group_val.contact_level());
+    if perm_contact_level != Some(*temp_linked_group.contact_level()) {
+      return Err(Error::ContactLevelMismatch(perm_linked_name, temp_linked_name));
+    }
+
+    members_to_add.iter_mut().for_each(|(_, val)| {
+      GroupStore::group_replace(
+          val,
+          temp_linked_name.clone(),
+          perm_linked_name.to_string(),
+      );
+    });
+
+    // an incoming member that has the same id as an existing group but
+    // different contents is combined with it according to the policy
+    for conflict in self.preview_update_conflicts(&members_to_add) {
+      let resolved = Device::resolve_conflict(
+          conflict_policy,
+          conflict.existing(),
+          conflict.incoming(),
+      )?;
+      members_to_add.insert(conflict.group_id().to_string(), resolved);
+    }
+
+    // record the sender as the origin of groups that are new to this device
+    for (id, val) in members_to_add.iter_mut() {
+      if self.group_store.get_group(id).is_none() {
+        val.set_origin(Some(sender.clone()));
+      }
+    }
+
+    // set all groups whose id is not temp_linked_name
+    members_to_add.iter_mut().for_each(|(id, val)| {
+      self.group_store.set_group(id.to_string(), val.clone());
+    });
+
+    // the link with sender (if any was in flight) is now complete
+    self.remove_pending_link(&sender);
+
+    // merge temp_linked_name group into perm_linked_name group, in sorted
+    // order so the result does not depend on HashSet iteration order
+    let mut parents = temp_linked_group.parents().iter().collect::<Vec::<&String>>();
+    parents.sort();
+    for parent in parents {
+      self.group_store.add_parent(&perm_linked_name, parent)?;
+    }
+    let mut children = temp_linked_group.children().iter().collect::<Vec::<&String>>();
+    children.sort();
+    for child in children {
+      self.group_store.add_child(&perm_linked_name, child)?;
+    }
+
+    self.touch_with_clock();
+    self.merge_history.push(MergeRecord {
+      sender,
+      timestamp: self.now(),
+      added: self.linked_devices().len().saturating_sub(linked_count_before),
+    });
+
+    Ok(())
+  }
+
+  // Combines a local group with an incoming group of the same id. Versions
+  // that disagree on the contact level or on whether the group can have
+  // children cannot be combined
+  fn resolve_conflict(
+      conflict_policy: ConflictPolicy,
+      existing_val: &Group,
+      incoming_val: &Group,
+  ) -> Result<Group, Error> {
+    if existing_val.contact_level() != incoming_val.contact_level()
+        || existing_val.is_leaf() != incoming_val.is_leaf() {
+      return Err(Error::DuplicateIdKey(existing_val.group_id().to_string()));
+    }
+
+    match conflict_policy {
+      ConflictPolicy::PreferExisting => Ok(existing_val.clone()),
+      ConflictPolicy::Union => {
+        let mut merged_val = existing_val.clone();
+        for parent_id in incoming_val.parents() {
+          merged_val.add_parent(parent_id.to_string());
+        }
+        for child_id in incoming_val.children().iter() {
+          merged_val.add_child(child_id.to_string())?;
+        }
+        for (key, val) in incoming_val.attributes() {
+          merged_val.set_attribute(key.to_string(), val.to_string());
+        }
+        if let Some(updated_at) = incoming_val.updated_at() {
+          if merged_val.updated_at().is_none_or(|cur| cur < updated_at) {
+            merged_val.set_updated_at(updated_at);
+          }
+        }
+        Ok(merged_val)
+      },
+    }
+  }
+
+  // Incoming groups that would overwrite a different local group of the
+  // same id, sorted by id; nothing is mutated
+  pub fn preview_update_conflicts(
+      &self,
+      members_to_add: &HashMap<String, Group>,
+  ) -> Vec<Conflict> {
+    let mut conflicts = members_to_add
+        .iter()
+        .filter_map(|(id, val)| {
+          match self.group_store.get_group(id) {
+            Some(existing_val) if existing_val != val => Some(Conflict {
+              group_id: id.to_string(),
+              existing: existing_val.clone(),
+              incoming: val.clone(),
+            }),
+            _ => None,
+          }
+        })
+        .collect::<Vec::<Conflict>>();
+    conflicts.sort_by(|a, b| a.group_id.cmp(&b.group_id));
+    conflicts
+  }
+
+  pub fn confirm_update_linked_group(
+      &mut self,
+      new_linked_name: String,
+      new_groups: HashMap<String, Group>,
+  ) -> Result<(), Error> {
+    let conflict_policy = self.conflict_policy;
+    self.confirm_update_linked_group_with_policy(
+        new_linked_name,
+        new_groups,
+        conflict_policy,
+    )
+  }
+
+  // The inviter's version of this device's own group is always taken as-is,
+  // since it places this device in the new linked group
+  pub fn confirm_update_linked_group_with_policy(
+      &mut self,
+      new_linked_name: String,
+      new_groups: HashMap<String, Group>,
+      conflict_policy: ConflictPolicy,
+  ) -> Result<(), Error> {
+    println!("IN CONFIRM_UPDATE_LINKED_GROUP");
+    if self.sealed {
+      return Err(Error::DeviceSealed);
+    }
+    self.check_linked_name(&new_linked_name)?;
+    let mut group_store = self.group_store.clone();
+    group_store.delete_group(&self.linked_name.clone());
+
+    let mut resolved_groups = HashMap::<String, Group>::new();
+    for (group_id, group_val) in new_groups.into_iter() {
+      let resolved = match group_store.get_group(&group_id) {
+        Some(existing_val) if existing_val != &group_val && &group_id != self.idkey() => {
+          Device::resolve_conflict(conflict_policy, existing_val, &group_val)?
+        },
+        _ => group_val,
+      };
+      resolved_groups.insert(group_id, resolved);
+    }
+    for (group_id, group_val) in resolved_groups.into_iter() {
+      group_store.set_group(group_id, group_val);
+    }
+    self.group_store = group_store;
+
+    self.linked_names.remove(&self.linked_name);
+    self.linked_names.insert(new_linked_name.clone());
+    self.linked_name = new_linked_name;
+    self.touch_with_clock();
+
+    self.clear_pending_link_idkeys();
+
+    Ok(())
+  }
+
+  // Lets application code decide (e.g. by asking the user) whether to trust
+  // an invite before calling join_via_invite; nothing is modified
+  pub fn evaluate_invite(&self, invite: &JoinInvite) -> InviteEvaluation {
+    let mut invite_store = GroupStore::new();
+    for (group_id, group_val) in invite.groups().iter() {
+      invite_store.set_group(group_id.to_string(), group_val.clone());
+    }
+    let member_count = if invite_store.get_group(invite.linked_name()).is_some() {
+      invite_store.resolve_ids(vec![invite.linked_name()]).len()
+    } else {
+      0
+    };
+
+    InviteEvaluation {
+      inviter: invite.inviter().clone(),
+      targets_this_device: invite.target_idkey() == self.idkey(),
+      member_count,
+    }
+  }
+
+  // Joiner side of export_for_new_device: adopts the inviter's linked group
+  // and groups in place of this device's own
+  pub fn join_via_invite(
+      &mut self,
+      invite: JoinInvite,
+  ) -> Result<MembershipDelta, Error> {
+    if invite.target_idkey() != self.idkey() {
+      return Err(Error::InviteNotForDevice(invite.target_idkey().to_string()));
+    }
+
+    let before = self.linked_devices()
+        .into_iter()
+        .cloned()
+        .collect::<HashSet::<String>>();
+    self.confirm_update_linked_group(invite.linked_name, invite.groups)?;
+    let after = self.linked_devices()
+        .into_iter()
+        .cloned()
+        .collect::<HashSet::<String>>();
+
+    Ok(MembershipDelta {
+      added: after.difference(&before).cloned().collect(),
+      removed: before.difference(&after).cloned().collect(),
+    })
+  }
+
+  // Deletes every contact group not in `still_valid`, returning the deleted
+  // ids sorted; this device's own (non-contact) groups are never touched
+  pub fn prune_stale_contacts(&mut self, still_valid: &HashSet<String>) -> Vec<String> {
+    let mut stale = self.group_store
+        .iter()
+        .filter(|(id, val)| *val.contact_level() && !still_valid.contains(*id))
+        .map(|(id, _)| id.to_string())
+        .collect::<Vec::<String>>();
+    stale.sort();
+
+    for id in stale.iter() {
+      self.group_store.delete_group(id);
+    }
+    if !stale.is_empty() {
+      self.touch_with_clock();
+    }
+    stale
+  }
+
+  // Hands `group_id` to another of this device's linked devices by
+  // rewriting its "owner" attribute
+  pub fn transfer_group_ownership(
+      &mut self,
+      group_id: &str,
+      new_owner_idkey: &str,
+  ) -> Result<(), Error> {
+    if self.sealed {
+      return Err(Error::DeviceSealed);
+    }
+    if !self.linked_devices().iter().any(|idkey| idkey.as_str() == new_owner_idkey) {
+      return Err(Error::NotLinkedMember(new_owner_idkey.to_string()));
+    }
+
+    let group_id = group_id.to_string();
+    match self.group_store.get_group_mut(&group_id) {
+      Some(group_val) => {
+        group_val.set_attribute(String::from("owner"), new_owner_idkey.to_string());
+      },
+      None => return Err(crate::groups::Error::GroupDoesNotExist(group_id).into()),
+    }
+    self.touch_with_clock();
+
+    Ok(())
+  }
+
+  // Whether delete_device would succeed for `to_delete`, without deleting it
+  pub fn can_delete_device(&self, to_delete: &str) -> bool {
+    match self.group_store.get_all_groups().get(to_delete) {
+      Some(device_group) => device_group.is_leaf(),
+      None => false,
+    }
+  }
+
+  // FIXME Currently, this function is unnecessary since none of this data
+  // is persistent and will be automatically GC'd when the `device` field
+  // of the glue object is set to `None`. But in the future, this function
+  // should be used to clean up any related persistent data
+  pub fn delete_device(&mut self, to_delete: String) -> Result<(), Error> {
+    if self.sealed {
+      return Err(Error::DeviceSealed);
+    }
+
+    let device_group = match self.group_store.get_group(&to_delete) {
+      Some(device_group) => device_group.clone(),
+      None => return Err(crate::groups::Error::GroupDoesNotExist(to_delete).into()),
+    };
+    if !device_group.is_leaf() {
+      return Err(Error::DeviceHasChildren);
+    }
+
+    // remove child link to this device from 
+    // every parent (should have no children)
+    for parent in device_group.parents().iter() {
+      self.group_store.remove_child(parent, &to_delete)?;
+    }
+
+    self.group_store.delete_group(&to_delete);
+    self.touch_with_clock();
+
+    Ok(())
+  }
+
+  // Deletes several devices, leaves first so that a group that can have
+  // children does not stop the leaves from being deleted. Stops at the
+  // first failure, returning the ids deleted so far along with the error
+  pub fn batch_delete_devices(
+      &mut self,
+      mut ids: Vec<String>,
+  ) -> Result<Vec<String>, (Vec<String>, Error)> {
+    ids.sort_by_key(|id| {
+      let is_leaf = self.group_store
+          .get_group(id)
+          .is_some_and(|group_val| group_val.is_leaf());
+      (!is_leaf, id.clone())
+    });
+    ids.dedup();
+
+    let mut deleted = Vec::<String>::new();
+    for id in ids.into_iter() {
+      if let Err(err) = self.delete_device(id.clone()) {
+        return Err((deleted, err));
+      }
+      deleted.push(id);
+    }
+    Ok(deleted)
+  }
+
+  // Rebuilds a device by applying `ops` in order to a fresh device. Stops at
+  // the first failing op and reports its index
+  pub fn replay_log(idkey: String, ops: Vec<DeviceOp>) -> Result<Device, Error> {
+    let mut device = Device::new(idkey, None, None);
+    for (index, op) in ops.into_iter().enumerate() {
+      if let Err(err) = device.apply_op(op) {
+        return Err(Error::ReplayFailed { index, source: Box::new(err) });
+      }
+    }
+    Ok(device)
+  }
+
+  fn apply_op(&mut self, op: DeviceOp) -> Result<(), Error> {
+    match op {
+      DeviceOp::AddPendingLink(idkey) => {
+        self.add_pending_link(idkey);
+        Ok(())
+      },
+      DeviceOp::UpdateLinkedGroup { sender, temp_linked_name, members_to_add } => {
+        self.update_linked_group(sender, temp_linked_name, members_to_add)
+      },
+      DeviceOp::ConfirmUpdateLinkedGroup { new_linked_name, new_groups } => {
+        self.confirm_update_linked_group(new_linked_name, new_groups)
+      },
+      DeviceOp::DeleteDevice(to_delete) => self.delete_device(to_delete),
+      DeviceOp::SetData(data_id, data_val) => {
+        self.data_store.set_data(data_id, data_val);
+        Ok(())
+      },
+    }
+  }
+
+  // Applies the op that device `origin` numbered `seq` (starting at 1) and
+  // advances that device's entry in the version vector. Each origin's ops
+  // must be applied exactly once and in order, however they were relayed
+  pub fn apply_remote_op(
+      &mut self,
+      origin: String,
+      seq: u64,
+      op: DeviceOp,
+  ) -> Result<(), Error> {
+    let applied = self.version_vector.get(&origin).copied().unwrap_or(0);
+    if seq <= applied {
+      return Err(Error::DuplicateOp(origin, seq));
+    }
+    if seq != applied + 1 {
+      return Err(Error::MissingOps { origin, expected: applied + 1, seq });
+    }
+
+    self.apply_op(op.clone())?;
+    self.version_vector.insert(origin.clone(), seq);
+    self.op_log.push((origin, seq, op));
+    Ok(())
+  }
+
+  pub fn version_vector(&self) -> &HashMap<String, u64> {
+    &self.version_vector
+  }
+
+  // Applied remote ops that a device with version vector `vv` has not seen
+  // yet, as (origin, seq, op) in the order they were applied here, which
+  // can be passed to `apply_remote_op` as is
+  pub fn export_since(&self, vv: &HashMap<String, u64>) -> Vec<(String, u64, DeviceOp)> {
+    self.op_log
+        .iter()
+        .filter(|(origin, seq, _)| *seq > vv.get(origin).copied().unwrap_or(0))
+        .cloned()
+        .collect()
+  }
+
+  // Replays a deletion performed by another device; only devices in this
+  // device's linked group may delete linked devices
+  pub fn apply_remote_delete(
+      &mut self,
+      deleted_idkey: String,
+      from_sender: String,
+  ) -> Result<(), Error> {
+    if !self.linked_devices().contains(&from_sender) {
+      return Err(Error::Unauthorized);
+    }
+
+    self.delete_device(deleted_idkey)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::data::{BasicData, DataStore};
+  use crate::devices::{
+    ConflictPolicy,
+    Device,
+    DeviceInconsistency,
+    DeviceOp,
+    Error,
+    LinkState,
+  };
+  use crate::groups::{Group, GroupInconsistency, GroupStore};
+  use std::collections::HashMap;
+  use std::collections::HashSet;
+  use std::time::{Duration, Instant};
+
+  // Runs the whole linking protocol: `joiner` requests to link with
+  // `inviter`, `inviter` merges the joiner's linked group, and `joiner`
+  // adopts the result. Returns the joiner's resulting linked devices
+  fn simulate_link(inviter: &mut Device, joiner: &mut Device) -> HashSet<String> {
+    // simulate send and receive of UpdateLinked message
+    inviter.add_pending_link(joiner.idkey().clone());
+    let request = joiner.initiate_link(inviter.idkey().clone());
+    match inviter.update_linked_group(
+        request.sender().clone(),
+        request.linked_name().clone(),
+        request.members().clone(),
+    ) {
+      Ok(_) => println!("Update succeeded"),
+      Err(err) => panic!("Error updating linked group: {:?}", err),
+    }
+
+    // simulate send and receive of ConfirmUpdateLinked message
+    match joiner.confirm_update_linked_group(
+        inviter.linked_name().clone(),
+        inviter.group_store().get_all_groups().clone()
+    ) {
+      Ok(_) => println!("Update succeeded"),
+      Err(err) => panic!("Error confirming update of linked group: {:?}", err),
+    }
+
+    joiner.linked_devices().into_iter().cloned().collect()
+  }
+
+  #[test]
+  fn test_new_standalone() {
+    let idkey = String::from("0");
+    let linked_name = String::from("linked");
+    let device = Device::new(idkey.clone(), Some(linked_name.clone()), None);
+
+    let linked_group = device.group_store().get_group(&linked_name).unwrap();
+    assert_eq!(linked_group.group_id(), &linked_name);
+    assert_eq!(linked_group.contact_level(), &false);
+    assert_eq!(linked_group.parents(), &HashSet::<String>::new());
+    assert_eq!(linked_group.children(), &HashSet::<String>::from([idkey.clone()]));
+
+    let idkey_group = device.group_store().get_group(&idkey).unwrap();
+    assert_eq!(idkey_group.group_id(), &idkey);
+    assert_eq!(idkey_group.contact_level(), &false);
+    assert_eq!(idkey_group.parents(), &HashSet::<String>::from([linked_name.clone()]));
+    assert!(idkey_group.is_leaf());
+
+    assert_eq!(device.idkey, idkey);
+    assert_eq!(device.linked_name, linked_name);
+    assert_eq!(device.pending_link_idkeys, HashSet::new());
+  }
+
+  #[test]
+  fn test_get_linked_name() {
+    let idkey = String::from("0");
+    let linked_name = String::from("linked");
+    let device_0 = Device::new(idkey.clone(), Some(linked_name.clone()), None);
+    assert_eq!(device_0.linked_name(), &linked_name);
+
+    let device_1 = Device::new(idkey, None, None);
+    assert_ne!(device_1.linked_name(), &linked_name);
+  }
+
+  #[test]
+  fn test_initiate_link() {
+    let idkey_0 = String::from("0");
+    let device_0 = Device::new(idkey_0.clone(), None, None);
+
+    let idkey_1 = String::from("1");
+    let mut device_1 = Device::new(idkey_1.clone(), None, None);
+    assert_eq!(device_1.link_state(), LinkState::Idle);
+
+    let link_request = device_1.initiate_link(idkey_0.clone());
+    assert_eq!(
+        device_1.link_state(),
+        LinkState::PendingOutbound(HashSet::from([idkey_0.clone()]))
+    );
+    assert_eq!(link_request.sender(), &idkey_1);
+    assert_eq!(link_request.linked_name(), device_1.linked_name());
+    assert_eq!(
+        link_request.members(),
+        &device_1.group_store().get_all_subgroups(device_1.linked_name())
+    );
+    assert_eq!(device_0.link_state(), LinkState::Idle);
+  }
+
+  #[test]
+  fn test_update_linked_group() {
+    let idkey_0 = String::from("0");
+    let mut device_0 = Device::new(idkey_0.clone(), None, None);
+    let linked_name_0 = device_0.linked_name().clone();
+    let linked_members_0 = device_0.group_store().get_all_subgroups(&linked_name_0);
+
+    let idkey_1 = String::from("1");
+    let device_1 = Device::new(idkey_1.clone(), None, Some(device_0.linked_name().to_string()));
+    let linked_name_1 = device_1.linked_name().clone();
+    let linked_members_1 = device_1.group_store().get_all_subgroups(&linked_name_1);
+
+    assert_ne!(linked_name_0, linked_name_1);
+    assert_ne!(linked_members_0, linked_members_1);
+    assert_eq!(linked_members_0.len(), 2);
+    assert_eq!(linked_members_1.len(), 2);
+
+    device_0.add_pending_link(idkey_1.clone());
+    // simulate send and receive of UpdateLinked message
+    match device_0.update_linked_group(
+        idkey_1.clone(),
+        linked_name_1.clone(),
+        linked_members_1.clone(),
+    ) {
+      Ok(_) => println!("Update succeeded"),
+      Err(err) => panic!("Error updating linked group: {:?}", err),
+    }
+
+    let merged_linked_members = device_0.group_store().get_all_subgroups(&linked_name_0);
+    assert_eq!(merged_linked_members.len(), 3);
+
+    let merged_linked_group = merged_linked_members.get(&linked_name_0).unwrap();
+    assert_eq!(merged_linked_group.group_id(), &linked_name_0);
+    assert_eq!(merged_linked_group.parents(), &HashSet::<String>::new());
+    assert_eq!(merged_linked_group.children(),
+        &HashSet::<String>::from([idkey_1.clone(), idkey_0.clone()]));
+
+    let merged_idkey_0_group = merged_linked_members.get(&idkey_0).unwrap();
+    assert_eq!(merged_idkey_0_group.group_id(), &idkey_0);
+    assert_eq!(merged_idkey_0_group.parents(),
+        &HashSet::<String>::from([linked_name_0.clone()]));
+    assert!(merged_idkey_0_group.is_leaf());
+
+    let merged_idkey_1_group = merged_linked_members.get(&idkey_1).unwrap();
+    assert_eq!(merged_idkey_1_group.group_id(), &idkey_1);
+    assert_eq!(merged_idkey_1_group.parents(),
+        &HashSet::<String>::from([linked_name_0.clone()]));
+    assert!(merged_idkey_1_group.is_leaf());
+  }
+
+  #[test]
+  fn test_confirm_update_linked() {
+    let idkey_0 = String::from("0");
+    let mut device_0 = Device::new(idkey_0.clone(), None, None);
+    let linked_name_0 = device_0.linked_name().clone();
+    let linked_members_0 = device_0.group_store().get_all_subgroups(&linked_name_0);
+
+    let idkey_1 = String::from("1");
+    let mut device_1 = Device::new(idkey_1.clone(), None, None);
+
+    let members = simulate_link(&mut device_0, &mut device_1);
+    assert_eq!(members, HashSet::from([idkey_0.clone(), idkey_1.clone()]));
+
+    let merged_linked_members = device_1.group_store().get_all_subgroups(&linked_name_0);
+    assert_eq!(merged_linked_members.len(), 3);
+
+    let merged_linked_group = merged_linked_members.get(&linked_name_0).unwrap();
+    assert_eq!(merged_linked_group.group_id(), &linked_name_0);
+    assert_eq!(merged_linked_group.parents(), &HashSet::<String>::new());
+    assert_eq!(merged_linked_group.children(),
+        &HashSet::<String>::from([idkey_1.clone(), idkey_0.clone()]));
+
+    let merged_idkey_0_group = merged_linked_members.get(&idkey_0).unwrap();
+    assert_eq!(merged_idkey_0_group.group_id(), &idkey_0);
+    assert_eq!(merged_idkey_0_group.parents(),
+        &HashSet::<String>::from([linked_name_0.clone()]));
+    assert!(merged_idkey_0_group.is_leaf());
+
+    let merged_idkey_1_group = merged_linked_members.get(&idkey_1).unwrap();
+    assert_eq!(merged_idkey_1_group.group_id(), &idkey_1);
+    assert_eq!(merged_idkey_1_group.parents(),
+        &HashSet::<String>::from([linked_name_0.clone()]));
+    assert!(merged_idkey_1_group.is_leaf());
+  }
+
+  #[test]
+  fn test_delete_self_device() {
+    let idkey_0 = String::from("0");
+    let mut device_0 = Device::new(idkey_0.clone(), None, None);
+    let linked_name_0 = device_0.linked_name().clone();
+    let linked_members_0 = device_0.group_store().get_all_subgroups(&linked_name_0);
+
+    let idkey_1 = String::from("1");
+    let mut device_1 = Device::new(idkey_1.clone(), None, Some(device_0.linked_name().to_string()));
+    let linked_name_1 = device_1.linked_name().clone();
+    let linked_members_1 = device_1.group_store().get_all_subgroups(&linked_name_1);
+
+    device_0.add_pending_link(idkey_1.clone());
+    // simulate send and receive of UpdateLinked message
+    match device_0.update_linked_group(
+        idkey_1.clone(),
+        linked_name_1.clone(),
+        linked_members_1.clone(),
+    ) {
+      Ok(_) => println!("Update succeeded"),
+      Err(err) => panic!("Error updating linked group: {:?}", err),
+    }
+
+    // simulate send and receive of ConfirmUpdateLinked message
+    match device_1.confirm_update_linked_group(
+        linked_name_0.clone(),
+        device_0.group_store().get_all_groups().clone()
+    ) {
+      Ok(_) => println!("Update succeeded"),
+      Err(err) => panic!("Error confirming update of linked group: {:?}", err),
+    }
+
+    match device_1.delete_device(idkey_1.clone()) {
+      Ok(_) => println!("Delete succeeded"),
+      Err(err) => panic!("Error deleting device: {:?}", err),
+    }
+
+    let linked_members = device_1.group_store().get_all_subgroups(&linked_name_0);
+    assert_eq!(linked_members.len(), 2);
+
+    let linked_group = linked_members.get(&linked_name_0).unwrap();
+    assert_eq!(linked_group.group_id(), &linked_name_0);
+    assert_eq!(linked_group.parents(), &HashSet::<String>::new());
+    assert_eq!(linked_group.children(),
+        &HashSet::<String>::from([idkey_0.clone()]));
+
+    let idkey_0_group = linked_members.get(&idkey_0).unwrap();
+    assert_eq!(idkey_0_group.group_id(), &idkey_0);
+    assert_eq!(idkey_0_group.parents(),
+        &HashSet::<String>::from([linked_name_0.clone()]));
+    assert!(idkey_0_group.is_leaf());
+
+    assert_eq!(None, linked_members.get(&idkey_1));
+  }
+
+  #[test]
+  fn test_delete_other_device() {
+    let idkey_0 = String::from("0");
+    let mut device_0 = Device::new(idkey_0.clone(), None, None);
+    let linked_name_0 = device_0.linked_name().clone();
+    let linked_members_0 = device_0.group_store().get_all_subgroups(&linked_name_0);
+
+    let idkey_1 = String::from("1");
+    let mut device_1 = Device::new(idkey_1.clone(), None, Some(device_0.linked_name().to_string()));
+    let linked_name_1 = device_1.linked_name().clone();
+    let linked_members_1 = device_1.group_store().get_all_subgroups(&linked_name_1);
+
+    device_0.add_pending_link(idkey_1.clone());
+    // simulate send and receive of UpdateLinked message
+    match device_0.update_linked_group(
+        idkey_1.clone(),
+        linked_name_1.clone(),
+        linked_members_1.clone(),
+    ) {
+      Ok(_) => println!("Update succeeded"),
+      Err(err) => panic!("Error updating linked group: {:?}", err),
+    }
+
+    // simulate send and receive of ConfirmUpdateLinked message
+    match device_1.confirm_update_linked_group(
+        linked_name_0.clone(),
+        device_0.group_store().get_all_groups().clone()
+    ) {
+      Ok(_) => println!("Update succeeded"),
+      Err(err) => panic!("Error confirming update of linked group: {:?}", err),
+    }
+
+    match device_0.delete_device(idkey_1.clone()) {
+      Ok(_) => println!("Delete succeeded"),
+      Err(err) => panic!("Error deleting device: {:?}", err),
+    }
+
+    let linked_members = device_0.group_store().get_all_subgroups(&linked_name_0);
+    assert_eq!(linked_members.len(), 2);
+
+    let linked_group = linked_members.get(&linked_name_0).unwrap();
+    assert_eq!(linked_group.group_id(), &linked_name_0);
+    assert_eq!(linked_group.parents(), &HashSet::<String>::new());
+    assert_eq!(linked_group.children(),
+        &HashSet::<String>::from([idkey_0.clone()]));
+
+    let idkey_0_group = linked_members.get(&idkey_0).unwrap();
+    assert_eq!(idkey_0_group.group_id(), &idkey_0);
+    assert_eq!(idkey_0_group.parents(),
+        &HashSet::<String>::from([linked_name_0.clone()]));
+    assert!(idkey_0_group.is_leaf());
+
+    assert_eq!(None, linked_members.get(&idkey_1));
+  }
+
+  #[test]
+  fn test_apply_remote_delete() {
+    let idkey_0 = String::from("0");
+    let mut device_0 = Device::new(idkey_0.clone(), None, None);
+    let linked_name_0 = device_0.linked_name().clone();
+
+    let idkey_1 = String::from("1");
+    let mut device_1 = Device::new(idkey_1.clone(), None, Some(idkey_0.clone()));
+    let linked_name_1 = device_1.linked_name().clone();
+    let linked_members_1 = device_1.group_store().get_all_subgroups(&linked_name_1);
+
+    device_0.add_pending_link(idkey_1.clone());
+    device_0.update_linked_group(
+        idkey_1.clone(),
+        linked_name_1.clone(),
+        linked_members_1.clone(),
+    ).unwrap();
+    device_1.confirm_update_linked_group(
+        linked_name_0.clone(),
+        device_0.group_store().get_all_groups().clone()
+    ).unwrap();
+
+    assert_eq!(
+        device_1.apply_remote_delete(idkey_0.clone(), idkey_0.clone()),
+        Ok(())
+    );
+    assert_eq!(device_1.linked_devices(), HashSet::from([&idkey_1]));
+    assert_eq!(device_1.group_store().get_group(&idkey_0), None);
+
+    // a deletion of an unknown device is an error, not a panic
+    assert_eq!(
+        device_1.apply_remote_delete(String::from("unknown"), idkey_1.clone()),
+        Err(Error::GroupErr {
+          source: crate::groups::Error::GroupDoesNotExist(String::from("unknown")),
+        })
+    );
+    assert_eq!(device_1.linked_devices(), HashSet::from([&idkey_1]));
+  }
+
+  #[test]
+  fn test_apply_remote_delete_unauthorized() {
+    let idkey_0 = String::from("0");
+    let mut device_0 = Device::new(idkey_0.clone(), None, None);
+
+    assert_eq!(
+        device_0.apply_remote_delete(idkey_0.clone(), String::from("1")),
+        Err(Error::Unauthorized)
+    );
+    assert_eq!(device_0.linked_devices(), HashSet::from([&idkey_0]));
+  }
+
+  #[test]
+  fn test_multiple_linked_groups() {
+    let idkey_0 = String::from("0");
+    let personal_name = String::from("personal");
+    let work_name = String::from("work");
+    let mut device_0 = Device::new(idkey_0.clone(), Some(personal_name.clone()), None);
+    assert_eq!(device_0.add_linked_group(Some(work_name.clone())), Ok(work_name.clone()));
+    assert_eq!(
+        device_0.linked_names(),
+        &HashSet::from([personal_name.clone(), work_name.clone()])
+    );
+    assert_eq!(device_0.linked_name(), &personal_name);
+
+    let idkey_1 = String::from("1");
+    let idkey_2 = String::from("2");
+    device_0.group_store_mut().set_group(
+        idkey_1.clone(),
+        Group::new(Some(idkey_1.clone()), false, false)
+    );
+    device_0.group_store_mut().set_group(
+        idkey_2.clone(),
+        Group::new(Some(idkey_2.clone()), false, false)
+    );
+    device_0.group_store_mut().link_groups(&personal_name, &idkey_1).unwrap();
+    device_0.group_store_mut().link_groups(&work_name, &idkey_2).unwrap();
+
+    assert_eq!(
+        device_0.linked_devices_in(&personal_name),
+        HashSet::from([&idkey_0, &idkey_1])
+    );
+    assert_eq!(
+        device_0.linked_devices_in(&work_name),
+        HashSet::from([&idkey_0, &idkey_2])
+    );
+    assert_eq!(
+        device_0.linked_devices(),
+        HashSet::from([&idkey_0, &idkey_1, &idkey_2])
+    );
+
+    assert_eq!(device_0.set_primary_linked_name(work_name.clone()), Ok(()));
+    assert_eq!(device_0.linked_name(), &work_name);
+    assert_eq!(
+        device_0.set_primary_linked_name(idkey_1.clone()),
+        Err(Error::UnknownLinkedGroup(idkey_1.clone()))
+    );
+  }
+
+  #[test]
+  fn test_checkpoint() {
+    let idkey_0 = String::from("0");
+    let mut device_0 = Device::new(idkey_0.clone(), None, None);
+
+    let idkey_1 = String::from("1");
+    let device_1 = Device::new(idkey_1.clone(), None, Some(idkey_0.clone()));
+    let linked_name_1 = device_1.linked_name().clone();
+    let linked_members_1 = device_1.group_store().get_all_subgroups(&linked_name_1);
+
+    let checkpoint = device_0.checkpoint();
+
+    device_0.add_pending_link(idkey_1.clone());
+    device_0.update_linked_group(
+        idkey_1.clone(),
+        linked_name_1.clone(),
+        linked_members_1.clone(),
+    ).unwrap();
+    assert_ne!(device_0.checkpoint(), checkpoint);
+    device_0.delete_device(idkey_0.clone()).unwrap();
+    assert_eq!(device_0.linked_devices(), HashSet::from([&idkey_1]));
+
+    device_0.restore_checkpoint(checkpoint.clone());
+    assert_eq!(device_0.checkpoint(), checkpoint);
+    assert_eq!(device_0.linked_devices(), HashSet::from([&idkey_0]));
+  }
+
+  #[test]
+  fn test_export_for_new_device() {
+    let idkey_0 = String::from("0");
+    let mut device_0 = Device::new(idkey_0.clone(), None, None);
+    let linked_name_0 = device_0.linked_name().clone();
+
+    let idkey_1 = String::from("1");
+    let mut device_1 = Device::new(idkey_1.clone(), None, None);
+
+    let invite = device_0.export_for_new_device(&idkey_1);
+    assert_eq!(
+        device_0.link_state(),
+        LinkState::PendingOutbound(HashSet::from([idkey_1.clone()]))
+    );
+    assert_eq!(invite.inviter(), &idkey_0);
+    assert_eq!(invite.target_idkey(), &idkey_1);
+    assert_eq!(invite.linked_name(), &linked_name_0);
+
+    match device_1.confirm_update_linked_group(
+        invite.linked_name().clone(),
+        invite.groups().clone()
+    ) {
+      Ok(_) => println!("Update succeeded"),
+      Err(err) => panic!("Error confirming update of linked group: {:?}", err),
+    }
+
+    let merged_linked_members = device_1.group_store().get_all_subgroups(&linked_name_0);
+    assert_eq!(merged_linked_members.len(), 3);
+
+    let merged_linked_group = merged_linked_members.get(&linked_name_0).unwrap();
+    assert_eq!(merged_linked_group.parents(), &HashSet::<String>::new());
+    assert_eq!(merged_linked_group.children(),
+        &HashSet::<String>::from([idkey_1.clone(), idkey_0.clone()]));
+
+    let merged_idkey_0_group = merged_linked_members.get(&idkey_0).unwrap();
+    assert_eq!(merged_idkey_0_group.parents(),
+        &HashSet::<String>::from([linked_name_0.clone()]));
+    assert!(merged_idkey_0_group.is_leaf());
+
+    let merged_idkey_1_group = merged_linked_members.get(&idkey_1).unwrap();
+    assert_eq!(merged_idkey_1_group.parents(),
+        &HashSet::<String>::from([linked_name_0.clone()]));
+    assert!(merged_idkey_1_group.is_leaf());
+  }
+
+  #[test]
+  fn test_linked_devices_excluding_self_sorted() {
+    let idkey_0 = String::from("0");
+    let linked_name = String::from("linked");
+    let mut device_0 = Device::new(idkey_0.clone(), Some(linked_name.clone()), None);
+
+    for idkey in ["5", "3", "9", "1", "7"] {
+      let idkey = String::from(idkey);
+      device_0.group_store_mut().set_group(
+          idkey.clone(),
+          Group::new(Some(idkey.clone()), false, false)
+      );
+      device_0.group_store_mut().link_groups(&linked_name, &idkey).unwrap();
+    }
+
+    let expected = vec!["1", "3", "5", "7", "9"]
+        .into_iter()
+        .map(String::from)
+        .collect::<Vec::<String>>();
+    for _ in 0..10 {
+      assert_eq!(device_0.linked_devices_excluding_self(), expected);
+    }
+    assert_eq!(
+        device_0.linked_devices_excluding_self_and_other(&String::from("5")),
+        vec![
+          String::from("1"),
+          String::from("3"),
+          String::from("7"),
+          String::from("9"),
+        ]
+    );
+  }
+
+  #[test]
+  fn test_update_linked_group_duplicate_idkey() {
+    let idkey_0 = String::from("0");
+    let mut device_0 = Device::new(idkey_0.clone(), None, None);
+
+    let idkey_1 = String::from("1");
+    let mut device_1 = Device::new(idkey_1.clone(), None, Some(idkey_0.clone()));
+    let linked_name_1 = device_1.linked_name().clone();
+
+    // device 1 claims a member whose idkey collides with device 0
+    device_1.group_store_mut().set_group(
+        idkey_0.clone(),
+        Group::new(Some(idkey_0.clone()), true, false)
+    );
+    device_1.group_store_mut().link_groups(&linked_name_1, &idkey_0).unwrap();
+    let linked_members_1 = device_1.group_store().get_all_subgroups(&linked_name_1);
+
+    device_0.add_pending_link(idkey_1.clone());
+    let checkpoint = device_0.checkpoint();
+    assert_eq!(
+        device_0.update_linked_group(
+            idkey_1.clone(),
+            linked_name_1.clone(),
+            linked_members_1.clone(),
+        ),
+        Err(Error::DuplicateIdKey(idkey_0.clone()))
+    );
+    assert_eq!(device_0.checkpoint(), checkpoint);
+  }
+
+  #[test]
+  fn test_switch_linked_group() {
+    let idkey_0 = String::from("0");
+    let linked_name_0 = String::from("linked_0");
+    let linked_name_1 = String::from("linked_1");
+    let mut device_0 = Device::new(idkey_0.clone(), Some(linked_name_0.clone()), None);
+    device_0.group_store_mut().set_group(
+        linked_name_1.clone(),
+        Group::new(Some(linked_name_1.clone()), false, true)
+    );
+
+    assert_eq!(device_0.switch_linked_group(linked_name_1.clone()), Ok(()));
+    assert_eq!(device_0.linked_name(), &linked_name_1);
+    assert_eq!(device_0.linked_names(), &HashSet::from([linked_name_1.clone()]));
+
+    let idkey_group = device_0.group_store().get_group(&idkey_0).unwrap();
+    assert_eq!(idkey_group.parents(), &HashSet::from([linked_name_1.clone()]));
+    assert_eq!(
+        device_0.group_store().get_group(&linked_name_0).unwrap().children(),
+        &HashSet::new()
+    );
+    assert_eq!(
+        device_0.group_store().get_group(&linked_name_1).unwrap().children(),
+        &HashSet::from([idkey_0.clone()])
+    );
+
+    assert_eq!(device_0.switch_linked_group(linked_name_0.clone()), Ok(()));
+    assert_eq!(device_0.linked_name(), &linked_name_0);
+    assert_eq!(
+        device_0.group_store().get_group(&idkey_0).unwrap().parents(),
+        &HashSet::from([linked_name_0.clone()])
+    );
+
+    assert_eq!(
+        device_0.switch_linked_group(String::from("none")),
+        Err(Error::GroupErr {
+          source: crate::groups::Error::GroupDoesNotExist(String::from("none")),
+        })
+    );
+    assert_eq!(device_0.linked_name(), &linked_name_0);
+  }
+
+  #[test]
+  fn test_validate() {
+    let idkey_0 = String::from("0");
+    let linked_name = String::from("linked");
+    let device_0 = Device::new(idkey_0.clone(), Some(linked_name.clone()), None);
+    assert_eq!(device_0.validate(), Ok(()));
+
+    // missing linked group
+    let mut device = device_0.clone();
+    device.group_store_mut().delete_group(&linked_name);
+    assert_eq!(
+        device.validate(),
+        Err(vec![
+          DeviceInconsistency::MissingLinkedGroup(linked_name.clone()),
+          DeviceInconsistency::DeviceGroupNotLinked(idkey_0.clone(), linked_name.clone()),
+        ])
+    );
+
+    // contact linked group
+    let mut device = device_0.clone();
+    device.group_store_mut()
+        .get_group_mut(&linked_name)
+        .unwrap()
+        .update_contact_level(true);
+    assert_eq!(
+        device.validate(),
+        Err(vec![DeviceInconsistency::ContactLinkedGroup(linked_name.clone())])
+    );
+
+    // missing device group
+    let mut device = device_0.clone();
+    device.group_store_mut().delete_group(&idkey_0);
+    assert_eq!(
+        device.validate(),
+        Err(vec![DeviceInconsistency::MissingDeviceGroup(idkey_0.clone())])
+    );
+
+    // device group not under the linked group
+    let mut device = device_0.clone();
+    device.group_store_mut().unlink_groups(&linked_name, &idkey_0).unwrap();
+    assert_eq!(
+        device.validate(),
+        Err(vec![DeviceInconsistency::DeviceGroupNotLinked(
+            idkey_0.clone(),
+            linked_name.clone(),
+        )])
+    );
+
+    // inconsistent group store
+    let mut device = device_0.clone();
+    device.group_store_mut()
+        .get_group_mut(&linked_name)
+        .unwrap()
+        .add_parent(String::from("none"));
+    assert_eq!(
+        device.validate(),
+        Err(vec![DeviceInconsistency::GroupStore(
+            GroupInconsistency::MissingParent(linked_name.clone(), String::from("none"))
+        )])
+    );
+  }
+
+  #[test]
+  fn test_concurrent_pending_links() {
+    let idkey_0 = String::from("0");
+    let mut device_0 = Device::new(idkey_0.clone(), None, None);
+    let linked_name_0 = device_0.linked_name().clone();
+
+    let idkey_1 = String::from("1");
+    let device_1 = Device::new(idkey_1.clone(), None, Some(idkey_0.clone()));
+    let idkey_2 = String::from("2");
+    let device_2 = Device::new(idkey_2.clone(), None, Some(idkey_0.clone()));
+
+    assert!(device_0.add_pending_link(idkey_1.clone()));
+    assert!(device_0.add_pending_link(idkey_2.clone()));
+    assert_eq!(
+        device_0.pending_links(),
+        &HashSet::from([idkey_1.clone(), idkey_2.clone()])
+    );
+    assert_eq!(device_0.pending_link_idkey(), None);
+
+    device_0.update_linked_group(
+        idkey_2.clone(),
+        device_2.linked_name().clone(),
+        device_2.group_store().get_all_subgroups(device_2.linked_name()),
+    ).unwrap();
+    assert_eq!(device_0.pending_links(), &HashSet::from([idkey_1.clone()]));
+    assert_eq!(device_0.pending_link_idkey(), Some(&idkey_1));
+
+    device_0.update_linked_group(
+        idkey_1.clone(),
+        device_1.linked_name().clone(),
+        device_1.group_store().get_all_subgroups(device_1.linked_name()),
+    ).unwrap();
+    assert_eq!(device_0.pending_links(), &HashSet::new());
+    assert_eq!(device_0.link_state(), LinkState::Idle);
+
+    assert_eq!(
+        device_0.group_store().get_group(&linked_name_0).unwrap().children(),
+        &HashSet::from([idkey_0.clone(), idkey_1.clone(), idkey_2.clone()])
+    );
+  }
+
+  #[test]
+  fn test_data_keys_for_group() {
+    let idkey_0 = String::from("0");
+    let linked_name = String::from("linked");
+    let mut device_0 = Device::new(idkey_0.clone(), Some(linked_name.clone()), None);
+
+    let linked_data_ids = [
+      DataStore::group_data_id(&linked_name, "a"),
+      DataStore::group_data_id(&linked_name, "b"),
+    ];
+    let device_data_id = DataStore::group_data_id(&idkey_0, "a");
+    for data_id in linked_data_ids.iter().chain([&device_data_id]) {
+      device_0.data_store_mut().set_data(
+          data_id.clone(),
+          BasicData::new(data_id.clone(), String::from("val"))
+      );
+    }
+    device_0.data_store_mut().set_data(
+        String::from("ungrouped"),
+        BasicData::new(String::from("ungrouped"), String::from("val"))
+    );
+
+    assert_eq!(
+        device_0.data_keys_for_group(&linked_name),
+        linked_data_ids.iter().collect::<Vec::<&String>>()
+    );
+    assert_eq!(device_0.data_keys_for_group(&idkey_0), vec![&device_data_id]);
+    assert_eq!(device_0.data_keys_for_group("other"), Vec::<&String>::new());
+  }
+
+  #[test]
+  fn test_delta_since() {
+    let idkey_0 = String::from("0");
+    let linked_name = String::from("linked");
+    let mut device_0 = Device::new(idkey_0.clone(), Some(linked_name.clone()), None);
+
+    let contact = Group::new(Some(String::from("contact")), true, false);
+    device_0.group_store_mut().set_group(contact.group_id().to_string(), contact.clone());
+
+    // peer's snapshot of device_0's state
+    let mut peer = device_0.clone();
+    assert!(device_0.delta_since(peer.group_store()).is_empty());
+
+    let idkey_1 = String::from("1");
+    device_0.group_store_mut().set_group(
+        idkey_1.clone(),
+        Group::new(Some(idkey_1.clone()), false, false)
+    );
+    device_0.group_store_mut().add_child(&linked_name, &idkey_1).unwrap();
+    device_0.group_store_mut().delete_group(contact.group_id());
+
+    let delta = device_0.delta_since(peer.group_store());
+    assert_eq!(delta.upserted().len(), 2);
+    assert_eq!(delta.removed(), &HashSet::from([contact.group_id().to_string()]));
+
+    peer.apply_delta(&delta);
+    assert_eq!(peer.group_store(), device_0.group_store());
+    assert!(device_0.delta_since(peer.group_store()).is_empty());
+  }
+
+  #[test]
+  fn test_linked_devices_opt() {
+    let idkey_0 = String::from("0");
+    let idkey_1 = String::from("1");
+    let idkey_2 = String::from("2");
+    let linked_name = String::from("linked");
+    let mut device_0 = Device::new(idkey_0.clone(), Some(linked_name.clone()), None);
+    for idkey in [&idkey_1, &idkey_2] {
+      device_0.group_store_mut().set_group(
+          idkey.clone(),
+          Group::new(Some(idkey.clone()), false, false)
+      );
+      device_0.group_store_mut().add_child(&linked_name, idkey).unwrap();
+    }
+
+    assert_eq!(device_0.linked_devices_opt(true), device_0.linked_devices());
+    assert_eq!(
+        device_0.linked_devices_opt(true),
+        HashSet::from([&idkey_0, &idkey_1, &idkey_2])
+    );
+    assert_eq!(
+        device_0.linked_devices_opt(false),
+        HashSet::from([&idkey_1, &idkey_2])
+    );
+
+    let mut excluding_self = device_0.linked_devices_opt(false)
+        .into_iter()
+        .cloned()
+        .collect::<Vec::<String>>();
+    excluding_self.sort();
+    assert_eq!(device_0.linked_devices_excluding_self(), excluding_self);
+    assert_eq!(
+        device_0.linked_devices_excluding_self_and_other(&idkey_1),
+        vec![idkey_2.clone()]
+    );
+  }
+
+  #[test]
+  fn test_preview_update_conflicts() {
+    let idkey_0 = String::from("0");
+    let linked_name = String::from("linked");
+    let mut device_0 = Device::new(idkey_0.clone(), Some(linked_name.clone()), None);
+
+    let contact = Group::new(Some(String::from("contact")), true, false);
+    device_0.group_store_mut().set_group(contact.group_id().to_string(), contact.clone());
+    let before = device_0.clone();
+
+    // same id and same contents as the local group
+    let clean = contact.clone();
+    // same id as the local device group but different contents
+    let conflicting = Group::new(Some(idkey_0.clone()), false, false);
+    // not present locally
+    let new_group = Group::new(Some(String::from("new")), false, false);
+
+    let members_to_add = HashMap::from([
+      (clean.group_id().to_string(), clean),
+      (conflicting.group_id().to_string(), conflicting.clone()),
+      (new_group.group_id().to_string(), new_group),
+    ]);
+
+    let conflicts = device_0.preview_update_conflicts(&members_to_add);
+    assert_eq!(conflicts.len(), 1);
+    assert_eq!(conflicts[0].group_id(), &idkey_0);
+    assert_eq!(conflicts[0].existing(), device_0.group_store().get_group(&idkey_0).unwrap());
+    assert_eq!(conflicts[0].incoming(), &conflicting);
+    assert_eq!(device_0, before);
+  }
+
+  #[test]
+  fn test_describe() {
+    let idkey_0 = String::from("0");
+    let idkey_1 = String::from("1");
+    let linked_name = String::from("linked");
+    let mut device_0 = Device::new(idkey_0.clone(), Some(linked_name.clone()), None);
+    device_0.group_store_mut().set_group(
+        idkey_1.clone(),
+        Group::new(Some(idkey_1.clone()), false, false)
+    );
+    device_0.group_store_mut().add_child(&linked_name, &idkey_1).unwrap();
+    device_0.add_pending_link(String::from("2"));
+
+    let description = device_0.describe();
+    assert!(description.contains("device 0\n"));
+    assert!(description.contains("linked name: linked\n"));
+    assert!(description.contains("pending links: 2\n"));
+    assert!(description.contains("linked devices: 2\n"));
+    assert!(description.ends_with("groups:\n  linked\n    0\n    1\n"));
+  }
+
+  #[test]
+  fn test_pending_updates() {
+    let idkey_0 = String::from("0");
+    let idkey_1 = String::from("1");
+    let idkey_2 = String::from("2");
+    let linked_name_0 = String::from("linked_0");
+    let mut device_0 = Device::new(idkey_0.clone(), Some(linked_name_0.clone()), None);
+    let mut device_1 = Device::new(idkey_1.clone(), Some(String::from("linked_1")), None);
+    let mut device_2 = Device::new(idkey_2.clone(), Some(String::from("linked_2")), None);
+
+    for device in [&mut device_1, &mut device_2] {
+      let request = device.initiate_link(idkey_0.clone());
+      device_0.enqueue_update_linked_group(
+          request.sender().clone(),
+          request.linked_name().clone(),
+          request.members().clone(),
+      );
+    }
+
+    assert_eq!(device_0.pending_updates().len(), 2);
+    assert_eq!(device_0.pending_updates()[0].sender(), &idkey_1);
+    assert_eq!(device_0.pending_updates()[1].sender(), &idkey_2);
+    // nothing is applied until confirmed
+    assert_eq!(device_0.linked_devices(), HashSet::from([&idkey_0]));
+
+    device_0.add_pending_link(idkey_1.clone());
+    let rejected = device_0.reject_pending(1).unwrap();
+    assert_eq!(rejected.sender(), &idkey_2);
+    assert_eq!(device_0.confirm_pending(0), Ok(()));
+    assert_eq!(device_0.pending_updates(), &[]);
+    assert_eq!(device_0.linked_devices(), HashSet::from([&idkey_0, &idkey_1]));
+
+    assert_eq!(device_0.confirm_pending(0), Err(Error::NoSuchPendingUpdate(0)));
+    assert_eq!(device_0.reject_pending(0), Err(Error::NoSuchPendingUpdate(0)));
+  }
+
+  #[test]
+  fn test_touch() {
+    let linked_name = String::from("linked");
+    let mut device_0 = Device::new(String::from("0"), Some(linked_name.clone()), None);
+    let device_1 = Device::new(String::from("1"), Some(linked_name.clone()), None);
+
+    let updated_at = |device: &Device| {
+      device.group_store().get_group(&linked_name).unwrap().updated_at()
+    };
+    assert_eq!(updated_at(&device_0), None);
+    assert_eq!(updated_at(&device_0), updated_at(&device_1));
+
+    device_0.touch(5);
+    assert_eq!(updated_at(&device_0), Some(5));
+    device_0.touch(7);
+    assert_eq!(updated_at(&device_0), Some(7));
+    device_0.touch(6);
+    assert_eq!(updated_at(&device_0), Some(7));
+  }
+
+  #[test]
+  fn test_touch_with_clock() {
+    let idkey_0 = String::from("0");
+    let idkey_1 = String::from("1");
+    let linked_name = String::from("linked");
+    let mut device_0 = Device::new(idkey_0.clone(), Some(linked_name.clone()), None);
+    device_0.group_store_mut().set_group(
+        idkey_1.clone(),
+        Group::new(Some(idkey_1.clone()), false, false)
+    );
+    device_0.group_store_mut().add_child(&linked_name, &idkey_1).unwrap();
+
+    let updated_at = |device: &Device| {
+      device.group_store().get_group(device.linked_name()).unwrap().updated_at()
+    };
+
+    device_0.set_clock(|| 42);
+    device_0.delete_device(idkey_1.clone()).unwrap();
+    assert_eq!(updated_at(&device_0), Some(42));
+
+    device_0.set_clock(|| 43);
+    device_0.rotate_idkey(String::from("0-rotated")).unwrap();
+    assert_eq!(updated_at(&device_0), Some(43));
+
+    device_0.set_clock(|| 44);
+    let work_name = device_0.add_linked_group(Some(String::from("work"))).unwrap();
+    assert_eq!(updated_at(&device_0), Some(44));
+
+    device_0.set_clock(|| 45);
+    device_0.group_store_mut().set_group(
+        String::from("contact"),
+        Group::new(Some(String::from("contact")), true, false)
+    );
+    assert_eq!(device_0.prune_stale_contacts(&HashSet::new()), vec!["contact"]);
+    assert_eq!(updated_at(&device_0), Some(45));
+
+    // nothing pruned, nothing touched
+    device_0.set_clock(|| 46);
+    device_0.prune_stale_contacts(&HashSet::new());
+    assert_eq!(updated_at(&device_0), Some(45));
+
+    device_0.set_clock(|| 47);
+    device_0.switch_linked_group(work_name.clone()).unwrap();
+    assert_eq!(device_0.linked_name(), &work_name);
+    assert_eq!(updated_at(&device_0), Some(47));
+  }
+
+  #[test]
+  fn test_join_via_invite() {
+    let idkey_0 = String::from("0");
+    let idkey_1 = String::from("1");
+    let mut device_0 = Device::new(idkey_0.clone(), None, None);
+    let mut device_1 = Device::new(idkey_1.clone(), None, None);
+    let mut device_2 = Device::new(String::from("2"), None, None);
+
+    let invite = device_0.export_for_new_device(&idkey_1);
+    assert_eq!(
+        device_2.join_via_invite(invite.clone()),
+        Err(Error::InviteNotForDevice(idkey_1.clone()))
+    );
+
+    let delta = device_1.join_via_invite(invite).unwrap();
+    assert_eq!(delta.added(), &HashSet::from([idkey_0.clone()]));
+    assert_eq!(delta.removed(), &HashSet::new());
+    assert_eq!(device_1.linked_name(), device_0.linked_name());
+    assert_eq!(device_1.linked_devices(), HashSet::from([&idkey_0, &idkey_1]));
+    assert_eq!(device_1.validate(), Ok(()));
+  }
+
+  #[test]
+  fn test_rotate_idkey() {
+    let idkey_0 = String::from("0");
+    let new_idkey = String::from("0_rotated");
+    let linked_name = String::from("linked");
+    let mut device_0 = Device::new(idkey_0.clone(), Some(linked_name.clone()), None);
+
+    assert_eq!(device_0.rotate_idkey(new_idkey.clone()), Ok(()));
+    assert_eq!(device_0.idkey(), &new_idkey);
+    assert_eq!(
+        device_0.group_store().get_group(&linked_name).unwrap().children(),
+        &HashSet::from([new_idkey.clone()])
+    );
+    assert!(device_0.group_store().get_group(&idkey_0).is_none());
+    assert_eq!(device_0.validate(), Ok(()));
+
+    assert_eq!(
+        device_0.rotate_idkey(linked_name.clone()),
+        Err(Error::GroupErr {
+          source: crate::groups::Error::GroupAlreadyExists(linked_name.clone()),
+        })
+    );
+    assert_eq!(device_0.idkey(), &new_idkey);
+  }
+
+  #[test]
+  fn test_from_edges_linked_group() {
+    let idkey_0 = String::from("0");
+    let idkey_1 = String::from("1");
+    let linked_name = String::from("linked");
+    let mut device_0 = Device::new(idkey_0.clone(), Some(linked_name.clone()), None);
+    let device_1 = Device::new(idkey_1.clone(), None, Some(linked_name.clone()));
+
+    device_0.add_pending_link(idkey_1.clone());
+    device_0.update_linked_group(
+        idkey_1.clone(),
+        device_1.linked_name().clone(),
+        device_1.group_store().get_all_subgroups(device_1.linked_name()),
+    ).unwrap();
+
+    let mut group_store = GroupStore::from_edges(&[("linked", "0"), ("linked", "1")], &[]);
+    assert_eq!(group_store.get_all_groups().len(), 3);
+    group_store.get_group_mut(&idkey_1).unwrap().set_origin(Some(idkey_1.clone()));
+    assert_eq!(&group_store, device_0.group_store());
+
+    let with_contact = GroupStore::from_edges(&[("linked", "0")], &["contact"]);
+    assert_eq!(
+        with_contact.get_group(&String::from("contact")).unwrap().contact_level(),
+        &true
+    );
+    assert_eq!(with_contact.get_group(&linked_name).unwrap().contact_level(), &false);
+  }
+
+  #[test]
+  fn test_merge_history() {
+    let idkey_0 = String::from("0");
+    let idkey_1 = String::from("1");
+    let idkey_2 = String::from("2");
+    let mut device_0 = Device::new(idkey_0.clone(), None, None);
+    let mut device_1 = Device::new(idkey_1.clone(), None, None);
+    let mut device_2 = Device::new(idkey_2.clone(), None, None);
+    assert_eq!(device_0.merge_history(), &[]);
+
+    let request = device_1.initiate_link(idkey_0.clone());
+    device_0.add_pending_link(request.sender().clone());
+    device_0.update_linked_group(
+        request.sender().clone(),
+        request.linked_name().clone(),
+        request.members().clone(),
+    ).unwrap();
+
+    device_0.set_clock(|| 42);
+    let request = device_2.initiate_link(idkey_0.clone());
+    device_0.add_pending_link(request.sender().clone());
+    device_0.update_linked_group(
+        request.sender().clone(),
+        request.linked_name().clone(),
+        request.members().clone(),
+    ).unwrap();
+
+    let history = device_0.merge_history();
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[0].sender(), &idkey_1);
+    assert_eq!(history[0].timestamp(), None);
+    assert_eq!(history[0].added(), 1);
+    assert_eq!(history[1].sender(), &idkey_2);
+    assert_eq!(history[1].timestamp(), Some(42));
+    assert_eq!(history[1].added(), 1);
+  }
+
+  #[test]
+  fn test_simulate_link() {
+    let idkey_0 = String::from("0");
+    let idkey_1 = String::from("1");
+    let linked_name_0 = String::from("linked_0");
+    let linked_name_1 = String::from("linked_1");
+
+    // the manual protocol, as in test_confirm_update_linked
+    let mut manual_0 = Device::new(idkey_0.clone(), Some(linked_name_0.clone()), None);
+    let mut manual_1 = Device::new(idkey_1.clone(), Some(linked_name_1.clone()), None);
+    manual_0.add_pending_link(idkey_1.clone());
+    manual_0.update_linked_group(
+        idkey_1.clone(),
+        linked_name_1.clone(),
+        manual_1.group_store().get_all_subgroups(&linked_name_1),
+    ).unwrap();
+    manual_1.confirm_update_linked_group(
+        linked_name_0.clone(),
+        manual_0.group_store().get_all_groups().clone(),
+    ).unwrap();
+
+    let mut device_0 = Device::new(idkey_0.clone(), Some(linked_name_0.clone()), None);
+    let mut device_1 = Device::new(idkey_1.clone(), Some(linked_name_1.clone()), None);
+    let members = simulate_link(&mut device_0, &mut device_1);
+
+    assert_eq!(members, HashSet::from([idkey_0.clone(), idkey_1.clone()]));
+    assert_eq!(device_0.group_store(), manual_0.group_store());
+    assert_eq!(device_1.group_store(), manual_1.group_store());
+    assert_eq!(device_1.linked_name(), &linked_name_0);
+    assert_eq!(device_1.link_state(), LinkState::Idle);
+  }
+
+  #[test]
+  fn test_export_subtree() {
+    let idkey_0 = String::from("0");
+    let linked_name = String::from("linked");
+    let mut device_0 = Device::new(idkey_0.clone(), Some(linked_name.clone()), None);
+    let shared = String::from("shared");
+    let member_a = String::from("a");
+    let member_b = String::from("b");
+    device_0.group_store_mut().set_group(shared.clone(), Group::new(Some(shared.clone()), false, true));
+    device_0.group_store_mut().link_groups(&linked_name, &shared).unwrap();
+    device_0.group_store_mut().link_groups_creating(&shared, &member_a).unwrap();
+    device_0.group_store_mut().link_groups_creating(&shared, &member_b).unwrap();
+
+    let exported = device_0.export_subtree(&shared).unwrap();
+    assert_eq!(exported.validate(), Ok(()));
+    assert_eq!(exported.get_all_groups().len(), 3);
+    assert!(exported.get_group(&linked_name).is_none());
+    assert_eq!(
+        exported.resolve_ids(vec![&shared]),
+        HashSet::from([&member_a, &member_b])
+    );
+
+    assert_eq!(
+        device_0.export_subtree("missing"),
+        Err(Error::GroupErr {
+          source: crate::groups::Error::GroupDoesNotExist(String::from("missing")),
+        })
+    );
+  }
+
+  #[test]
+  fn test_update_linked_group_order_independent() {
+    let idkey_0 = String::from("0");
+    let linked_name = String::from("linked");
+    let joiners = [String::from("1"), String::from("2")];
+
+    let merge_in_order = |order: [usize; 2]| {
+      let mut device_0 = Device::new(idkey_0.clone(), Some(linked_name.clone()), None);
+      for i in order {
+        let joiner = Device::new(
+            joiners[i].clone(),
+            Some(format!("linked_{}", joiners[i])),
+            None
+        );
+        device_0.add_pending_link(joiner.idkey().clone());
+        device_0.update_linked_group(
+            joiner.idkey().clone(),
+            joiner.linked_name().clone(),
+            joiner.group_store().get_all_subgroups(joiner.linked_name()),
+        ).unwrap();
+      }
+      device_0
+    };
+
+    let device_a = merge_in_order([0, 1]);
+    let device_b = merge_in_order([1, 0]);
+    assert_eq!(device_a.group_store(), device_b.group_store());
+    assert_eq!(
+        device_a.group_store().content_hash(),
+        device_b.group_store().content_hash()
+    );
+    assert_ne!(
+        device_a.group_store().content_hash(),
+        Device::new(idkey_0.clone(), Some(linked_name.clone()), None).group_store().content_hash()
+    );
+  }
 
-    let idkey_1 = String::from("1");
-    let device_1 = Device::new(idkey_1.clone(), None, Some(device_0.linked_name().to_string()));
-    let linked_name_1 = device_1.linked_name().clone();
-    let linked_members_1 = device_1.group_store().get_all_subgroups(&linked_name_1);
+  #[test]
+  fn test_can_delete_device() {
+    let idkey_0 = String::from("0");
+    let linked_name = String::from("linked");
+    let mut device_0 = Device::new(idkey_0.clone(), Some(linked_name.clone()), None);
 
-    assert_ne!(linked_name_0, linked_name_1);
-    assert_ne!(linked_members_0, linked_members_1);
-    assert_eq!(linked_members_0.len(), 2);
-    assert_eq!(linked_members_1.len(), 2);
+    assert!(device_0.can_delete_device(&idkey_0));
+    assert!(!device_0.can_delete_device(&linked_name));
+    assert!(!device_0.can_delete_device("missing"));
 
-    // simulate send and receive of UpdateLinked message
-    match device_0.update_linked_group(
-        idkey_1.clone(),
-        linked_name_1.clone(),
-        linked_members_1.clone(),
-    ) {
-      Ok(_) => println!("Update succeeded"),
-      Err(err) => panic!("Error updating linked group: {:?}", err),
-    }
+    // the predicate agrees with delete_device
+    let before = device_0.clone();
+    assert_eq!(device_0.delete_device(linked_name.clone()), Err(Error::DeviceHasChildren));
+    assert_eq!(device_0, before);
+    assert_eq!(device_0.delete_device(idkey_0.clone()), Ok(()));
+  }
 
-    let merged_linked_members = device_0.group_store().get_all_subgroups(&linked_name_0);
-    assert_eq!(merged_linked_members.len(), 3);
+  #[test]
+  fn test_new_with_data() {
+    let idkey_0 = String::from("0");
+    let linked_name = String::from("linked");
+    let data_id = String::from("data");
+    let mut data_store = DataStore::new();
+    data_store.set_data(data_id.clone(), BasicData::new(data_id.clone(), String::from("val")));
 
-    let merged_linked_group = merged_linked_members.get(&linked_name_0).unwrap();
-    assert_eq!(merged_linked_group.group_id(), &linked_name_0);
-    assert_eq!(merged_linked_group.parents(), &HashSet::<String>::new());
-    assert_eq!(merged_linked_group.children().as_ref(),
-        Some(&HashSet::<String>::from([idkey_1.clone(), idkey_0.clone()])));
+    let device_0 = Device::new_with_data(
+        idkey_0.clone(),
+        Some(linked_name.clone()),
+        None,
+        data_store.clone()
+    );
+    assert_eq!(
+        device_0.data_store().get_data(&data_id),
+        Some(&BasicData::new(data_id.clone(), String::from("val")))
+    );
+    assert_eq!(
+        device_0.group_store(),
+        Device::new(idkey_0.clone(), Some(linked_name.clone()), None).group_store()
+    );
+  }
 
-    let merged_idkey_0_group = merged_linked_members.get(&idkey_0).unwrap();
-    assert_eq!(merged_idkey_0_group.group_id(), &idkey_0);
-    assert_eq!(merged_idkey_0_group.parents(),
-        &HashSet::<String>::from([linked_name_0.clone()]));
-    assert_eq!(merged_idkey_0_group.children(), &None);
+  #[test]
+  fn test_linked_peers() {
+    let idkey_0 = String::from("0");
+    let linked_name = String::from("linked");
+    let mut device_0 = Device::new(idkey_0.clone(), Some(linked_name.clone()), None);
+    assert_eq!(device_0.linked_peers().count(), 0);
 
-    let merged_idkey_1_group = merged_linked_members.get(&idkey_1).unwrap();
-    assert_eq!(merged_idkey_1_group.group_id(), &idkey_1);
-    assert_eq!(merged_idkey_1_group.parents(),
-        &HashSet::<String>::from([linked_name_0.clone()]));
-    assert_eq!(merged_idkey_1_group.children(), &None);
+    for idkey in ["2", "1"] {
+      device_0.group_store_mut().link_groups_creating(&linked_name, idkey).unwrap();
+    }
+
+    let mut peers = device_0.linked_peers().cloned().collect::<Vec::<String>>();
+    peers.sort();
+    assert_eq!(peers, device_0.linked_devices_excluding_self());
+    assert_eq!(peers, vec![String::from("1"), String::from("2")]);
   }
 
   #[test]
-  fn test_confirm_update_linked() {
+  fn test_update_linked_group_contact_level_mismatch() {
+    let idkey_0 = String::from("0");
+    let idkey_1 = String::from("1");
+    let linked_name_0 = String::from("linked_0");
+    let linked_name_1 = String::from("linked_1");
+    let mut device_0 = Device::new(idkey_0.clone(), Some(linked_name_0.clone()), None);
+    let mut device_1 = Device::new(idkey_1.clone(), Some(linked_name_1.clone()), None);
+    device_1.group_store_mut()
+        .get_group_mut(&linked_name_1)
+        .unwrap()
+        .update_contact_level(true);
+
+    device_0.add_pending_link(idkey_1.clone());
+    let before = device_0.clone();
+    assert_eq!(
+        device_0.update_linked_group(
+            idkey_1.clone(),
+            linked_name_1.clone(),
+            device_1.group_store().get_all_subgroups(&linked_name_1),
+        ),
+        Err(Error::ContactLevelMismatch(linked_name_0.clone(), linked_name_1.clone()))
+    );
+    assert_eq!(device_0, before);
+  }
+
+  #[test]
+  fn test_update_linked_group_solicited() {
     let idkey_0 = String::from("0");
+    let idkey_1 = String::from("1");
     let mut device_0 = Device::new(idkey_0.clone(), None, None);
-    let linked_name_0 = device_0.linked_name().clone();
-    let linked_members_0 = device_0.group_store().get_all_subgroups(&linked_name_0);
+    let mut device_1 = Device::new(idkey_1.clone(), None, None);
+
+    device_0.add_pending_link(idkey_1.clone());
+    let request = device_1.initiate_link(idkey_0.clone());
+    assert_eq!(
+        device_0.update_linked_group(
+            request.sender().clone(),
+            request.linked_name().clone(),
+            request.members().clone(),
+        ),
+        Ok(())
+    );
+    assert_eq!(device_0.linked_devices(), HashSet::from([&idkey_0, &idkey_1]));
+    assert_eq!(device_0.link_state(), LinkState::Idle);
+  }
 
+  #[test]
+  fn test_update_linked_group_unsolicited() {
+    let idkey_0 = String::from("0");
     let idkey_1 = String::from("1");
-    let mut device_1 = Device::new(idkey_1.clone(), None, Some(device_0.linked_name().to_string()));
-    let linked_name_1 = device_1.linked_name().clone();
-    let linked_members_1 = device_1.group_store().get_all_subgroups(&linked_name_1);
+    let mut device_0 = Device::new(idkey_0.clone(), None, None);
+    let mut device_1 = Device::new(idkey_1.clone(), None, None);
+
+    // a pending link with someone else does not count
+    device_0.add_pending_link(String::from("2"));
+    let before = device_0.clone();
+    let request = device_1.initiate_link(idkey_0.clone());
+    assert_eq!(
+        device_0.update_linked_group(
+            request.sender().clone(),
+            request.linked_name().clone(),
+            request.members().clone(),
+        ),
+        Err(Error::UnsolicitedLink(idkey_1.clone()))
+    );
+    assert_eq!(device_0, before);
+  }
 
-    // simulate send and receive of UpdateLinked message
-    match device_0.update_linked_group(
-        idkey_1.clone(),
-        linked_name_1.clone(),
-        linked_members_1.clone(),
-    ) {
-      Ok(_) => println!("Update succeeded"),
-      Err(err) => panic!("Error updating linked group: {:?}", err),
+  #[test]
+  fn test_update_linked_group_unsolicited_allowed() {
+    let idkey_0 = String::from("0");
+    let idkey_1 = String::from("1");
+    let mut device_0 = Device::new(idkey_0.clone(), None, None).allow_unsolicited(true);
+    let mut device_1 = Device::new(idkey_1.clone(), None, None);
+
+    let request = device_1.initiate_link(idkey_0.clone());
+    assert_eq!(
+        device_0.update_linked_group(
+            request.sender().clone(),
+            request.linked_name().clone(),
+            request.members().clone(),
+        ),
+        Ok(())
+    );
+    assert_eq!(device_0.linked_devices(), HashSet::from([&idkey_0, &idkey_1]));
+  }
+
+  #[test]
+  fn test_export_contacts_only() {
+    let idkey_0 = String::from("0");
+    let linked_name = String::from("linked");
+    let mut device_0 = Device::new(idkey_0.clone(), Some(linked_name.clone()), None);
+    let contact = GroupStore::from_edges(&[("contact", "c0"), ("contact", "c1")], &["contact", "c0", "c1"]);
+    for (id, val) in contact.iter() {
+      device_0.group_store_mut().set_group(id.clone(), val.clone());
     }
+    device_0.group_store_mut().link_groups_creating("friends", "contact").unwrap();
 
-    // simulate send and receive of ConfirmUpdateLinked message
-    match device_1.confirm_update_linked_group(
-        linked_name_0.clone(),
-        device_0.group_store().get_all_groups().clone()
-    ) {
-      Ok(_) => println!("Update succeeded"),
-      Err(err) => panic!("Error confirming update of linked group: {:?}", err),
+    let exported = device_0.export_contacts_only();
+    assert_eq!(exported.validate(), Ok(()));
+    assert_eq!(exported, contact);
+    assert!(exported.iter().all(|(_, val)| *val.contact_level()));
+  }
+
+  #[test]
+  fn test_prune_stale_contacts() {
+    let idkey_0 = String::from("0");
+    let linked_name = String::from("linked");
+    let mut device_0 = Device::new(idkey_0.clone(), Some(linked_name.clone()), None);
+    let contacts = GroupStore::from_edges(
+        &[("valid", "v0"), ("stale", "s0"), ("stale", "s1")],
+        &["valid", "v0", "stale", "s0", "s1"],
+    );
+    for (id, val) in contacts.iter() {
+      device_0.group_store_mut().set_group(id.clone(), val.clone());
     }
 
-    let merged_linked_members = device_1.group_store().get_all_subgroups(&linked_name_0);
-    assert_eq!(merged_linked_members.len(), 3);
+    let still_valid = HashSet::from([String::from("valid"), String::from("v0")]);
+    assert_eq!(
+        device_0.prune_stale_contacts(&still_valid),
+        vec![String::from("s0"), String::from("s1"), String::from("stale")]
+    );
 
-    let merged_linked_group = merged_linked_members.get(&linked_name_0).unwrap();
-    assert_eq!(merged_linked_group.group_id(), &linked_name_0);
-    assert_eq!(merged_linked_group.parents(), &HashSet::<String>::new());
-    assert_eq!(merged_linked_group.children().as_ref(),
-        Some(&HashSet::<String>::from([idkey_1.clone(), idkey_0.clone()])));
+    let mut remaining = device_0.group_store()
+        .iter()
+        .map(|(id, _)| id.clone())
+        .collect::<Vec::<String>>();
+    remaining.sort();
+    assert_eq!(remaining, vec![idkey_0.clone(), linked_name.clone(), String::from("v0"), String::from("valid")]);
+    assert_eq!(device_0.validate(), Ok(()));
+    assert_eq!(device_0.prune_stale_contacts(&still_valid), Vec::<String>::new());
+  }
 
-    let merged_idkey_0_group = merged_linked_members.get(&idkey_0).unwrap();
-    assert_eq!(merged_idkey_0_group.group_id(), &idkey_0);
-    assert_eq!(merged_idkey_0_group.parents(),
-        &HashSet::<String>::from([linked_name_0.clone()]));
-    assert_eq!(merged_idkey_0_group.children(), &None);
+  #[test]
+  fn test_conflict_policy() {
+    let idkey_0 = String::from("0");
+    let idkey_1 = String::from("1");
+    let linked_name_0 = String::from("linked_0");
+    let linked_name_1 = String::from("linked_1");
+
+    // device 0 already knows device 1's group, under a different name
+    let link_with_policy = |conflict_policy: Option<ConflictPolicy>| {
+      let mut device_0 = Device::new(idkey_0.clone(), Some(linked_name_0.clone()), None);
+      if let Some(conflict_policy) = conflict_policy {
+        device_0 = device_0.with_conflict_policy(conflict_policy);
+      }
+      device_0.group_store_mut().link_groups_creating(&linked_name_0, &idkey_1).unwrap();
+      device_0.group_store_mut()
+          .get_group_mut(&idkey_1)
+          .unwrap()
+          .set_attribute(String::from("name"), String::from("local"));
+      device_0.add_pending_link(idkey_1.clone());
+
+      let mut device_1 = Device::new(idkey_1.clone(), Some(linked_name_1.clone()), None);
+      device_1.group_store_mut()
+          .get_group_mut(&idkey_1)
+          .unwrap()
+          .set_attribute(String::from("name"), String::from("incoming"));
+      device_0.update_linked_group(
+          idkey_1.clone(),
+          linked_name_1.clone(),
+          device_1.group_store().get_all_subgroups(&linked_name_1),
+      ).unwrap();
+      device_0
+    };
+
+    let device_0 = link_with_policy(None);
+    assert_eq!(device_0.conflict_policy(), ConflictPolicy::Union);
+    assert_eq!(
+        device_0.group_store().get_group(&idkey_1).unwrap().get_attribute("name"),
+        Some(&String::from("incoming"))
+    );
+    assert_eq!(device_0.validate(), Ok(()));
+
+    let device_0 = link_with_policy(Some(ConflictPolicy::PreferExisting));
+    assert_eq!(device_0.conflict_policy(), ConflictPolicy::PreferExisting);
+    assert_eq!(
+        device_0.group_store().get_group(&idkey_1).unwrap().get_attribute("name"),
+        Some(&String::from("local"))
+    );
+    assert_eq!(device_0.validate(), Ok(()));
+  }
 
-    let merged_idkey_1_group = merged_linked_members.get(&idkey_1).unwrap();
-    assert_eq!(merged_idkey_1_group.group_id(), &idkey_1);
-    assert_eq!(merged_idkey_1_group.parents(),
-        &HashSet::<String>::from([linked_name_0.clone()]));
-    assert_eq!(merged_idkey_1_group.children(), &None);
+  #[test]
+  fn test_seal() {
+    let idkey_0 = String::from("0");
+    let idkey_1 = String::from("1");
+    let linked_name_0 = String::from("linked_0");
+    let linked_name_1 = String::from("linked_1");
+    let mut device_0 = Device::new(idkey_0.clone(), Some(linked_name_0.clone()), None);
+    let mut device_1 = Device::new(idkey_1.clone(), Some(linked_name_1.clone()), None);
+    device_0.add_pending_link(idkey_1.clone());
+
+    device_0.seal();
+    device_1.seal();
+    assert!(device_0.is_sealed());
+    let before_0 = device_0.clone();
+    let before_1 = device_1.clone();
+
+    let members_1 = device_1.group_store().get_all_subgroups(&linked_name_1);
+    assert_eq!(
+        device_0.update_linked_group(idkey_1.clone(), linked_name_1.clone(), members_1.clone()),
+        Err(Error::DeviceSealed)
+    );
+    assert_eq!(
+        device_1.confirm_update_linked_group(
+            linked_name_0.clone(),
+            device_0.group_store().get_all_groups().clone(),
+        ),
+        Err(Error::DeviceSealed)
+    );
+    assert_eq!(device_0.delete_device(idkey_0.clone()), Err(Error::DeviceSealed));
+    assert_eq!(device_0, before_0);
+    assert_eq!(device_1, before_1);
+
+    // reads keep working
+    assert_eq!(device_0.linked_devices(), HashSet::from([&idkey_0]));
+    assert_eq!(device_0.validate(), Ok(()));
+
+    device_0.unseal();
+    device_1.unseal();
+    assert!(!device_0.is_sealed());
+    device_0.update_linked_group(idkey_1.clone(), linked_name_1.clone(), members_1).unwrap();
+    device_1.confirm_update_linked_group(
+        linked_name_0.clone(),
+        device_0.group_store().get_all_groups().clone(),
+    ).unwrap();
+    assert_eq!(device_1.linked_devices(), HashSet::from([&idkey_0, &idkey_1]));
+    device_0.delete_device(idkey_1.clone()).unwrap();
+    assert_eq!(device_0.linked_devices(), HashSet::from([&idkey_0]));
   }
 
   #[test]
-  fn test_delete_self_device() {
+  fn test_linked_group_fingerprint() {
+    let mut device_0 = Device::new(String::from("0"), None, None);
+    let mut device_1 = Device::new(String::from("1"), None, None);
+    let device_2 = Device::new(String::from("2"), None, None);
+    simulate_link(&mut device_0, &mut device_1);
+
+    let fingerprint = device_0.linked_group_fingerprint();
+    assert_eq!(fingerprint.len(), 19);
+    assert_eq!(device_1.linked_group_fingerprint(), fingerprint);
+    assert_ne!(device_2.linked_group_fingerprint(), fingerprint);
+  }
+
+  #[test]
+  fn test_batch_delete_devices() {
+    let mut device_0 = Device::new(String::from("0"), None, None);
+    let mut device_1 = Device::new(String::from("1"), None, None);
+    let mut device_2 = Device::new(String::from("2"), None, None);
+    simulate_link(&mut device_0, &mut device_1);
+    simulate_link(&mut device_0, &mut device_2);
+
+    assert_eq!(
+        device_0.batch_delete_devices(vec![String::from("2"), String::from("1")]),
+        Ok(vec![String::from("1"), String::from("2")])
+    );
+    assert_eq!(device_0.linked_devices(), HashSet::from([&String::from("0")]));
+    assert_eq!(device_0.validate(), Ok(()));
+  }
+
+  #[test]
+  fn test_batch_delete_devices_partial() {
+    let mut device_0 = Device::new(String::from("0"), None, None);
+    let mut device_1 = Device::new(String::from("1"), None, None);
+    simulate_link(&mut device_0, &mut device_1);
+    let linked_name = device_0.linked_name().clone();
+
+    // the linked group is ordered after the leaf and cannot be deleted
+    assert_eq!(
+        device_0.batch_delete_devices(vec![linked_name.clone(), String::from("1")]),
+        Err((vec![String::from("1")], Error::DeviceHasChildren))
+    );
+    assert!(device_0.group_store().get_group(&String::from("1")).is_none());
+    assert!(device_0.group_store().get_group(&linked_name).is_some());
+  }
+
+  #[test]
+  fn test_evaluate_invite() {
     let idkey_0 = String::from("0");
+    let idkey_1 = String::from("1");
     let mut device_0 = Device::new(idkey_0.clone(), None, None);
-    let linked_name_0 = device_0.linked_name().clone();
-    let linked_members_0 = device_0.group_store().get_all_subgroups(&linked_name_0);
+    let device_1 = Device::new(idkey_1.clone(), None, None);
+    let device_2 = Device::new(String::from("2"), None, None);
+    let invite = device_0.export_for_new_device(&idkey_1);
+
+    let evaluation = device_1.evaluate_invite(&invite);
+    assert_eq!(evaluation.inviter(), &idkey_0);
+    assert!(evaluation.targets_this_device());
+    assert_eq!(evaluation.member_count(), 2);
+
+    let evaluation = device_2.evaluate_invite(&invite);
+    assert_eq!(evaluation.inviter(), &idkey_0);
+    assert!(!evaluation.targets_this_device());
+    assert_eq!(evaluation.member_count(), 2);
+  }
 
+  #[test]
+  fn test_update_linked_group_origin() {
+    let idkey_0 = String::from("0");
     let idkey_1 = String::from("1");
-    let mut device_1 = Device::new(idkey_1.clone(), None, Some(device_0.linked_name().to_string()));
-    let linked_name_1 = device_1.linked_name().clone();
-    let linked_members_1 = device_1.group_store().get_all_subgroups(&linked_name_1);
+    let linked_name_0 = String::from("linked_0");
+    let linked_name_1 = String::from("linked_1");
+    let mut device_0 = Device::new(idkey_0.clone(), Some(linked_name_0.clone()), None);
+    let device_1 = Device::new(idkey_1.clone(), Some(linked_name_1.clone()), None);
 
-    // simulate send and receive of UpdateLinked message
-    match device_0.update_linked_group(
+    device_0.add_pending_link(idkey_1.clone());
+    device_0.update_linked_group(
         idkey_1.clone(),
         linked_name_1.clone(),
-        linked_members_1.clone(),
-    ) {
-      Ok(_) => println!("Update succeeded"),
-      Err(err) => panic!("Error updating linked group: {:?}", err),
-    }
+        device_1.group_store().get_all_subgroups(&linked_name_1),
+    ).unwrap();
 
-    // simulate send and receive of ConfirmUpdateLinked message
-    match device_1.confirm_update_linked_group(
-        linked_name_0.clone(),
-        device_0.group_store().get_all_groups().clone()
-    ) {
-      Ok(_) => println!("Update succeeded"),
-      Err(err) => panic!("Error confirming update of linked group: {:?}", err),
-    }
+    assert_eq!(device_0.group_store().origin_of(&idkey_1), Some(&idkey_1));
+    assert_eq!(device_0.group_store().origin_of(&idkey_0), None);
+    assert_eq!(device_0.group_store().origin_of(&linked_name_0), None);
+    assert_eq!(device_0.group_store().origin_of(&linked_name_1), None);
+  }
 
-    match device_1.delete_device(idkey_1.clone()) {
-      Ok(_) => println!("Delete succeeded"),
-      Err(err) => panic!("Error deleting device: {:?}", err),
+  #[test]
+  fn test_sweep_expired_links() {
+    let mut device = Device::new(String::from("0"), None, None);
+    let start = Instant::now();
+    let ttl = Duration::from_secs(60);
+    device.add_pending_link_at(String::from("1"), start);
+    device.add_pending_link_at(String::from("2"), start + Duration::from_secs(50));
+
+    let now = start + Duration::from_secs(100);
+    assert_eq!(device.sweep_expired_links(ttl, now), vec![String::from("1")]);
+    assert_eq!(device.pending_links(), &HashSet::from([String::from("2")]));
+    assert_eq!(device.sweep_expired_links(ttl, now), Vec::<String>::new());
+  }
+
+  #[test]
+  fn test_linked_name_collision() {
+    let idkey_0 = String::from("0");
+    let idkey_1 = String::from("1");
+    assert_eq!(
+        Device::try_new(idkey_0.clone(), Some(idkey_0.clone()), None),
+        Err(Error::NameCollision(idkey_0.clone()))
+    );
+
+    let mut device_0 = Device::try_new(idkey_0.clone(), None, None).unwrap();
+    let mut device_1 = Device::new(idkey_1.clone(), None, None);
+    simulate_link(&mut device_0, &mut device_1);
+
+    assert_eq!(
+        device_0.add_linked_group(Some(idkey_1.clone())),
+        Err(Error::NameCollision(idkey_1.clone()))
+    );
+    let friends = String::from("friends");
+    device_0.group_store_mut().set_group(
+        friends.clone(),
+        Group::new(Some(friends.clone()), true, true),
+    );
+    device_0.group_store_mut().add_child(&friends, &idkey_1).unwrap();
+    let before = device_0.clone();
+    assert_eq!(
+        device_0.add_linked_group(Some(friends.clone())),
+        Err(Error::NameCollision(friends.clone()))
+    );
+    let linked_name_0 = device_0.linked_name().clone();
+    assert_eq!(
+        device_0.add_linked_group(Some(linked_name_0.clone())),
+        Err(Error::NameCollision(linked_name_0.clone()))
+    );
+    assert_eq!(
+        device_0.switch_linked_group(idkey_0.clone()),
+        Err(Error::NameCollision(idkey_0.clone()))
+    );
+    assert_eq!(
+        device_0.confirm_update_linked_group(idkey_1.clone(), HashMap::new()),
+        Err(Error::NameCollision(idkey_1.clone()))
+    );
+    assert_eq!(device_0, before);
+  }
+
+  #[test]
+  fn test_try_new_with_data_linked_name_collision() {
+    let idkey_0 = String::from("0");
+    assert_eq!(
+        Device::try_new_with_data(
+            idkey_0.clone(),
+            Some(idkey_0.clone()),
+            None,
+            DataStore::new()
+        ),
+        Err(Error::NameCollision(idkey_0.clone()))
+    );
+    assert!(Device::try_new_with_data(idkey_0.clone(), None, None, DataStore::new()).is_ok());
+  }
+
+  #[test]
+  fn test_active_peers() {
+    let idkey_1 = String::from("1");
+    let idkey_2 = String::from("2");
+    let mut device_0 = Device::new(String::from("0"), None, None);
+    let mut device_1 = Device::new(idkey_1.clone(), None, None);
+    let mut device_2 = Device::new(idkey_2.clone(), None, None);
+    simulate_link(&mut device_0, &mut device_1);
+    simulate_link(&mut device_0, &mut device_2);
+    assert_eq!(device_0.active_peers(), HashSet::new());
+
+    device_0.mark_session_active(&idkey_1);
+    device_0.mark_session_active(&idkey_2);
+    // sessions with unlinked devices are not reported
+    device_0.mark_session_active("3");
+    assert_eq!(device_0.active_peers(), HashSet::from([&idkey_1, &idkey_2]));
+
+    assert!(device_0.clear_session(&idkey_2));
+    assert!(!device_0.clear_session(&idkey_2));
+    assert_eq!(device_0.active_peers(), HashSet::from([&idkey_1]));
+    assert_eq!(
+        device_0.linked_peers_opt(true).collect::<HashSet::<&String>>(),
+        HashSet::from([&idkey_1])
+    );
+    assert_eq!(
+        device_0.linked_peers().collect::<HashSet::<&String>>(),
+        HashSet::from([&idkey_1, &idkey_2])
+    );
+  }
+
+  #[test]
+  fn test_broadcast_plan() {
+    let idkey_0 = String::from("0");
+    let mut device_0 = Device::new(idkey_0.clone(), None, None);
+    for idkey in ["3", "1", "2"] {
+      let mut device = Device::new(idkey.to_string(), None, None);
+      simulate_link(&mut device_0, &mut device);
     }
 
-    let linked_members = device_1.group_store().get_all_subgroups(&linked_name_0);
-    assert_eq!(linked_members.len(), 2);
+    let plan = device_0.broadcast_plan(&HashSet::new());
+    assert_eq!(plan.self_id(), &idkey_0);
+    assert_eq!(plan.recipients(), &["1", "2", "3"]);
+    assert!(plan.contacts().is_empty());
+
+    let plan = device_0.broadcast_plan(&HashSet::from([String::from("2"), idkey_0.clone()]));
+    assert_eq!(plan.recipients(), &["1", "3"]);
+
+    // a contact reachable from the linked group is listed separately
+    let linked_name = device_0.linked_name().clone();
+    device_0.group_store_mut().link_groups_creating(&linked_name, "contact").unwrap();
+    device_0.group_store_mut().set_contact_level(&String::from("contact"), true).unwrap();
+    let plan = device_0.broadcast_plan(&HashSet::new());
+    assert_eq!(plan.recipients(), &["1", "2", "3"]);
+    assert_eq!(plan.contacts(), &["contact"]);
+  }
 
-    let linked_group = linked_members.get(&linked_name_0).unwrap();
-    assert_eq!(linked_group.group_id(), &linked_name_0);
-    assert_eq!(linked_group.parents(), &HashSet::<String>::new());
-    assert_eq!(linked_group.children().as_ref(),
-        Some(&HashSet::<String>::from([idkey_0.clone()])));
+  #[test]
+  fn test_verify_membership_proof() {
+    let mut device_0 = Device::new(String::from("0"), None, None);
+    let mut device_1 = Device::new(String::from("1"), None, None);
+    simulate_link(&mut device_0, &mut device_1);
+    let linked_name = device_0.linked_name().clone();
+
+    // a proof produced by the sender verifies against the receiver's store
+    let witnesses = device_1.group_store().resolve_with_witness(&linked_name);
+    let path = witnesses.get("1").unwrap();
+    assert_eq!(path, &vec![linked_name.clone(), String::from("1")]);
+    assert!(device_0.verify_membership_proof("1", path));
+  }
 
-    let idkey_0_group = linked_members.get(&idkey_0).unwrap();
-    assert_eq!(idkey_0_group.group_id(), &idkey_0);
-    assert_eq!(idkey_0_group.parents(),
-        &HashSet::<String>::from([linked_name_0.clone()]));
-    assert_eq!(idkey_0_group.children(), &None);
+  #[test]
+  fn test_verify_membership_proof_broken_edge() {
+    let mut device_0 = Device::new(String::from("0"), None, None);
+    let mut device_1 = Device::new(String::from("1"), None, None);
+    let device_2 = Device::new(String::from("2"), None, None);
+    simulate_link(&mut device_0, &mut device_1);
+    let linked_name = device_0.linked_name().clone();
+
+    // "1" is not a child of device 2's linked group
+    let path = vec![device_2.linked_name().clone(), String::from("1")];
+    assert!(!device_0.verify_membership_proof("1", &path));
+    // "0" is not a parent of "1"
+    let path = vec![linked_name.clone(), String::from("0"), String::from("1")];
+    assert!(!device_0.verify_membership_proof("1", &path));
+  }
 
-    assert_eq!(None, linked_members.get(&idkey_1));
+  #[test]
+  fn test_verify_membership_proof_wrong_member() {
+    let mut device_0 = Device::new(String::from("0"), None, None);
+    let mut device_1 = Device::new(String::from("1"), None, None);
+    simulate_link(&mut device_0, &mut device_1);
+    let linked_name = device_0.linked_name().clone();
+
+    let path = vec![linked_name.clone(), String::from("0")];
+    assert!(device_0.verify_membership_proof("0", &path));
+    assert!(!device_0.verify_membership_proof("1", &path));
+    assert!(!device_0.verify_membership_proof("1", &[]));
+    // a lone member has no edges to check
+    assert!(!device_0.verify_membership_proof("1", &[String::from("1")]));
   }
 
   #[test]
-  fn test_delete_other_device() {
+  fn test_replay_log() {
     let idkey_0 = String::from("0");
+    let idkey_1 = String::from("1");
     let mut device_0 = Device::new(idkey_0.clone(), None, None);
-    let linked_name_0 = device_0.linked_name().clone();
-    let linked_members_0 = device_0.group_store().get_all_subgroups(&linked_name_0);
+    let mut device_1 = Device::new(idkey_1.clone(), None, None);
+    simulate_link(&mut device_0, &mut device_1);
+    let data_val = BasicData::new(String::from("data"), "val");
+    device_1.data_store_mut().set_data(String::from("data"), data_val.clone());
+
+    let journal = vec![
+      DeviceOp::AddPendingLink(idkey_0.clone()),
+      DeviceOp::ConfirmUpdateLinkedGroup {
+        new_linked_name: device_0.linked_name().clone(),
+        new_groups: device_0.group_store().get_all_groups().clone(),
+      },
+      DeviceOp::SetData(String::from("data"), data_val),
+    ];
+    assert_eq!(Device::replay_log(idkey_1.clone(), journal), Ok(device_1));
+  }
 
-    let idkey_1 = String::from("1");
-    let mut device_1 = Device::new(idkey_1.clone(), None, Some(device_0.linked_name().to_string()));
-    let linked_name_1 = device_1.linked_name().clone();
-    let linked_members_1 = device_1.group_store().get_all_subgroups(&linked_name_1);
+  #[test]
+  fn test_replay_log_failure() {
+    let idkey_0 = String::from("0");
+    let journal = vec![
+      DeviceOp::AddPendingLink(String::from("1")),
+      DeviceOp::DeleteDevice(String::from("missing")),
+      DeviceOp::AddPendingLink(String::from("2")),
+    ];
+    assert_eq!(
+        Device::replay_log(idkey_0.clone(), journal),
+        Err(Error::ReplayFailed {
+          index: 1,
+          source: Box::new(crate::groups::Error::GroupDoesNotExist(String::from("missing")).into()),
+        })
+    );
+  }
 
-    // simulate send and receive of UpdateLinked message
-    match device_0.update_linked_group(
-        idkey_1.clone(),
-        linked_name_1.clone(),
-        linked_members_1.clone(),
-    ) {
-      Ok(_) => println!("Update succeeded"),
-      Err(err) => panic!("Error updating linked group: {:?}", err),
+  #[test]
+  fn test_diff_against() {
+    let linked_name = String::from("linked");
+    let mut device_0 = Device::new(String::from("0"), Some(linked_name.clone()), None);
+    let mut device_1 = device_0.clone();
+
+    // each side gains a contact the other lacks, and device 1 holds a newer
+    // version of the linked group
+    device_0.group_store_mut().set_group(
+        String::from("contact_0"),
+        Group::new(Some(String::from("contact_0")), true, false),
+    );
+    device_1.group_store_mut().set_group(
+        String::from("contact_1"),
+        Group::new(Some(String::from("contact_1")), true, false),
+    );
+    device_1.group_store_mut().get_group_mut(&linked_name).unwrap().set_updated_at(1);
+
+    let plan_0 = device_0.diff_against(device_1.group_store());
+    assert_eq!(plan_0.to_send(), &["contact_0"]);
+    assert_eq!(plan_0.to_request(), &["contact_1", "linked"]);
+
+    // the peer's plan is the mirror image
+    let plan_1 = device_1.diff_against(device_0.group_store());
+    assert_eq!(plan_1.to_send(), plan_0.to_request());
+    assert_eq!(plan_1.to_request(), plan_0.to_send());
+
+    assert_eq!(
+        device_0.diff_against(device_0.group_store()),
+        device_1.diff_against(device_1.group_store())
+    );
+    assert!(device_0.diff_against(device_0.group_store()).to_send().is_empty());
+  }
+
+  #[test]
+  fn test_linked_devices_page() {
+    let mut device = Device::new(String::from("00"), None, None);
+    let linked_name = device.linked_name().clone();
+    for i in 1..25 {
+      device.group_store_mut()
+          .link_groups_creating(&linked_name, &format!("{:02}", i))
+          .unwrap();
     }
+    assert_eq!(device.linked_devices().len(), 25);
 
-    // simulate send and receive of ConfirmUpdateLinked message
-    match device_1.confirm_update_linked_group(
-        linked_name_0.clone(),
-        device_0.group_store().get_all_groups().clone()
-    ) {
-      Ok(_) => println!("Update succeeded"),
-      Err(err) => panic!("Error confirming update of linked group: {:?}", err),
+    let mut pages = Vec::<Vec::<String>>::new();
+    for offset in (0..30).step_by(10) {
+      pages.push(device.linked_devices_page(offset, 10));
     }
+    assert_eq!(
+        pages.iter().map(|page| page.len()).collect::<Vec::<usize>>(),
+        vec![10, 10, 5]
+    );
+    assert_eq!(pages[0][0], "00");
+    assert_eq!(pages[2][4], "24");
+
+    let all = pages.into_iter().flatten().collect::<Vec::<String>>();
+    let expected = (0..25).map(|i| format!("{:02}", i)).collect::<Vec::<String>>();
+    assert_eq!(all, expected);
+    assert!(device.linked_devices_page(30, 10).is_empty());
+  }
 
-    match device_0.delete_device(idkey_1.clone()) {
-      Ok(_) => println!("Delete succeeded"),
-      Err(err) => panic!("Error deleting device: {:?}", err),
+  #[test]
+  fn test_quarantine() {
+    let idkey_0 = String::from("0");
+    let idkey_1 = String::from("1");
+    let idkey_2 = String::from("2");
+    let mut device_0 = Device::new(idkey_0.clone(), None, None);
+    let mut device_1 = Device::new(idkey_1.clone(), None, None);
+    let mut device_2 = Device::new(idkey_2.clone(), None, None);
+    simulate_link(&mut device_0, &mut device_1);
+    simulate_link(&mut device_0, &mut device_2);
+
+    assert!(device_0.quarantine(&idkey_1));
+    assert_eq!(device_0.quarantined(), &HashSet::from([idkey_1.clone()]));
+    assert_eq!(device_0.linked_devices(), HashSet::from([&idkey_0, &idkey_2]));
+    assert_eq!(device_0.linked_peers().collect::<Vec::<&String>>(), vec![&idkey_2]);
+    assert_eq!(device_0.broadcast_plan(&HashSet::new()).recipients(), &["2"]);
+    // the group itself is kept
+    assert!(device_0.group_store().get_group(&idkey_1).is_some());
+
+    assert!(device_0.release(&idkey_1));
+    assert!(!device_0.release(&idkey_1));
+    assert!(device_0.quarantined().is_empty());
+    assert_eq!(device_0.linked_devices(), HashSet::from([&idkey_0, &idkey_1, &idkey_2]));
+    assert_eq!(device_0.broadcast_plan(&HashSet::new()).recipients(), &["1", "2"]);
+
+    // a peer blocked through the group store stays blocked
+    device_0.group_store_mut().block(&idkey_2);
+    assert!(!device_0.quarantine(&idkey_2));
+    assert!(!device_0.release(&idkey_2));
+    assert!(device_0.group_store().is_blocked(&idkey_2));
+  }
+
+  #[test]
+  fn test_export_since() {
+    let set_data = |data_id: &str| {
+      DeviceOp::SetData(data_id.to_string(), BasicData::new(data_id.to_string(), "val"))
+    };
+    let mut device_0 = Device::new(String::from("0"), None, None);
+    let mut lagging = Device::new(String::from("3"), None, None);
+
+    let ops = [
+      ("1", 1, set_data("a")),
+      ("2", 1, set_data("b")),
+      ("1", 2, set_data("c")),
+      ("2", 2, set_data("d")),
+      ("1", 3, set_data("e")),
+    ];
+    for (origin, seq, op) in ops.iter() {
+      device_0.apply_remote_op(origin.to_string(), *seq, op.clone()).unwrap();
+    }
+    // the lagging device got the first two ops from each origin by another
+    // route and in a different order
+    for index in [1, 3, 0, 2] {
+      let (origin, seq, op) = &ops[index];
+      lagging.apply_remote_op(origin.to_string(), *seq, op.clone()).unwrap();
+    }
+    assert_eq!(
+        device_0.version_vector(),
+        &HashMap::from([(String::from("1"), 3), (String::from("2"), 2)])
+    );
+    assert_eq!(
+        lagging.version_vector(),
+        &HashMap::from([(String::from("1"), 2), (String::from("2"), 2)])
+    );
+
+    let missing = device_0.export_since(lagging.version_vector());
+    assert_eq!(missing, vec![(String::from("1"), 3, set_data("e"))]);
+    for (origin, seq, op) in missing {
+      lagging.apply_remote_op(origin, seq, op).unwrap();
     }
+    assert_eq!(lagging.version_vector(), device_0.version_vector());
+    assert!(lagging.data_store().get_data(&String::from("e")).is_some());
+
+    assert_eq!(device_0.export_since(&HashMap::new()).len(), 5);
+    assert_eq!(
+        device_0.export_since(&HashMap::from([(String::from("1"), 1)]))
+            .into_iter()
+            .map(|(_, _, op)| op)
+            .collect::<Vec::<DeviceOp>>(),
+        vec![set_data("b"), set_data("c"), set_data("d"), set_data("e")]
+    );
+  }
 
-    let linked_members = device_0.group_store().get_all_subgroups(&linked_name_0);
-    assert_eq!(linked_members.len(), 2);
+  #[test]
+  fn test_apply_remote_op_rejects_duplicates_and_gaps() {
+    let set_data = |data_id: &str| {
+      DeviceOp::SetData(data_id.to_string(), BasicData::new(data_id.to_string(), "val"))
+    };
+    let mut device = Device::new(String::from("0"), None, None);
+    let origin = String::from("1");
+    device.apply_remote_op(origin.clone(), 1, set_data("a")).unwrap();
+
+    assert_eq!(
+        device.apply_remote_op(origin.clone(), 1, set_data("a")),
+        Err(Error::DuplicateOp(origin.clone(), 1))
+    );
+    assert_eq!(
+        device.apply_remote_op(origin.clone(), 3, set_data("c")),
+        Err(Error::MissingOps { origin: origin.clone(), expected: 2, seq: 3 })
+    );
+    assert!(device.data_store().get_data(&String::from("c")).is_none());
+    assert_eq!(device.version_vector(), &HashMap::from([(origin.clone(), 1)]));
+    assert_eq!(device.export_since(&HashMap::new()).len(), 1);
+  }
 
-    let linked_group = linked_members.get(&linked_name_0).unwrap();
-    assert_eq!(linked_group.group_id(), &linked_name_0);
-    assert_eq!(linked_group.parents(), &HashSet::<String>::new());
-    assert_eq!(linked_group.children().as_ref(),
-        Some(&HashSet::<String>::from([idkey_0.clone()])));
+  #[test]
+  fn test_estimate_sync_size() {
+    let mut device = Device::new(String::from("0"), None, None);
+    let contacts = (0..20).map(|i| format!("contact_{}", i)).collect::<Vec::<String>>();
+    for contact in contacts.iter() {
+      device.group_store_mut().set_group(
+          contact.to_string(),
+          Group::new(Some(contact.to_string()), true, false),
+      );
+    }
+    let baseline = device.group_store().clone();
+    device.group_store_mut().set_group(
+        String::from("new_group"),
+        Group::new(Some(String::from("new_group")), false, true),
+    );
+
+    let full = device.estimate_sync_size(None);
+    let delta = device.estimate_sync_size(Some(&baseline));
+    assert_eq!(full, serde_json::to_vec(device.group_store()).unwrap().len());
+    assert!(delta > 0);
+    assert!(delta < full);
+  }
 
-    let idkey_0_group = linked_members.get(&idkey_0).unwrap();
-    assert_eq!(idkey_0_group.group_id(), &idkey_0);
-    assert_eq!(idkey_0_group.parents(),
-        &HashSet::<String>::from([linked_name_0.clone()]));
-    assert_eq!(idkey_0_group.children(), &None);
+  #[test]
+  fn test_health_report() {
+    let start = Instant::now();
+    let ttl = Duration::from_secs(60);
+    let mut device = Device::new(String::from("0"), None, None);
+    device.data_store_mut().set_data(
+        String::from("key"),
+        BasicData::new(String::from("key"), "val"),
+    );
+    device.add_pending_link_at(String::from("1"), start);
+
+    let report = device.health_report(start + Duration::from_secs(30), ttl);
+    assert!(report.is_healthy());
+    assert!(report.inconsistencies().is_empty());
+    assert!(report.expired_links().is_empty());
+    assert_eq!(report.stats(), &device.group_store().stats());
+    assert_eq!(report.data_count(), 1);
+
+    let linked_name = device.linked_name().clone();
+    device.group_store_mut().delete_group(&linked_name);
+    let report = device.health_report(start + Duration::from_secs(90), ttl);
+    assert!(!report.is_healthy());
+    assert_eq!(report.inconsistencies(), device.validate().unwrap_err().as_slice());
+    assert_eq!(report.expired_links(), &[String::from("1")]);
+    // the report does not sweep
+    assert!(device.pending_links().contains(&String::from("1")));
+  }
 
-    assert_eq!(None, linked_members.get(&idkey_1));
+  #[test]
+  fn test_group_store_mut_invalidates_linked_devices() {
+    let mut device = Device::new(String::from("0"), None, None);
+    let idkey = device.idkey().clone();
+    let linked_name = device.linked_name().clone();
+    device.group_store_mut().enable_resolve_cache();
+    assert_eq!(device.linked_devices(), HashSet::from([&idkey]));
+
+    // an in-place edit through get_group_mut
+    let new_idkey = String::from("1");
+    let mut new_group = Group::new(Some(new_idkey.clone()), false, false);
+    new_group.add_parent(linked_name.clone());
+    device.group_store_mut().set_group(new_idkey.clone(), new_group);
+    device.group_store_mut()
+        .get_group_mut(&linked_name)
+        .unwrap()
+        .add_child(new_idkey.clone())
+        .unwrap();
+    assert_eq!(device.linked_devices(), HashSet::from([&idkey, &new_idkey]));
+
+    device.group_store_mut().delete_group(&new_idkey);
+    assert_eq!(device.linked_devices(), HashSet::from([&idkey]));
+
+    // replacing the store wholesale
+    let mut replacement = device.group_store().clone();
+    replacement.block(&idkey);
+    *device.group_store_mut() = replacement;
+    assert!(device.linked_devices().is_empty());
   }
-}
 
+  #[test]
+  fn test_transfer_group_ownership() {
+    let mut device_0 = Device::new(String::from("0"), None, None);
+    let mut device_1 = Device::new(String::from("1"), None, None);
+    simulate_link(&mut device_0, &mut device_1);
+
+    let org = String::from("org");
+    let mut org_group = Group::new(Some(org.clone()), false, true);
+    org_group.set_attribute(String::from("owner"), String::from("0"));
+    device_0.group_store_mut().set_group(org.clone(), org_group);
+
+    assert_eq!(device_0.transfer_group_ownership(&org, "1"), Ok(()));
+    assert_eq!(
+        device_0.group_store().get_group(&org).unwrap().get_attribute("owner"),
+        Some(&String::from("1"))
+    );
+
+    assert_eq!(
+        device_0.transfer_group_ownership(&org, "2"),
+        Err(Error::NotLinkedMember(String::from("2")))
+    );
+    assert_eq!(
+        device_0.group_store().get_group(&org).unwrap().get_attribute("owner"),
+        Some(&String::from("1"))
+    );
+
+    assert_eq!(
+        device_0.transfer_group_ownership("missing", "0"),
+        Err(Error::GroupErr {
+          source: crate::groups::Error::GroupDoesNotExist(String::from("missing")),
+        })
+    );
+  }
+}
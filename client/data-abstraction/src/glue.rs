@@ -220,11 +220,45 @@ impl Glue {
       sender: &String,
       message: Message,
   ) -> Result<(), Error> {
+    for (recipients, outbound_message) in self.handle_message(sender, message)? {
+      self.send_message(
+          recipients,
+          &Message::to_string(&outbound_message).unwrap(),
+      ).await;
+    }
+
+    Ok(())
+  }
+
+  // Applies an incoming message to the device and returns any responses
+  // that the protocol requires to be sent back, each with its recipients
+  fn handle_message(
+      &mut self,
+      sender: &String,
+      message: Message,
+  ) -> Result<Vec<(Vec<String>, Message)>, Error> {
     match message {
-      Message::UpdateLinked(sender, temp_linked_name, members_to_add) => {
-        self.update_linked_group(sender, temp_linked_name, members_to_add)
-            .await
-            .map_err(Error::from)
+      Message::UpdateLinked(claimed_sender, temp_linked_name, members_to_add) => {
+        // the approval check is only meaningful for the transport sender
+        if &claimed_sender != sender {
+          return Err(crate::devices::Error::UnsolicitedLink(claimed_sender).into());
+        }
+        let device = self.device_mut().as_mut().unwrap();
+        device.update_linked_group(
+            sender.clone(),
+            temp_linked_name,
+            members_to_add
+        )?;
+
+        // send all groups (TODO and data) to new members
+        // TODO notify contacts of new members
+        return Ok(vec![(
+            vec![sender.clone()],
+            Message::ConfirmUpdateLinked(
+                device.linked_name().to_string(),
+                device.group_store().get_all_groups().clone()
+            ),
+        )]);
       },
       Message::ConfirmUpdateLinked(new_linked_name, new_groups) => {
         self.device_mut()
@@ -233,8 +267,7 @@ impl Glue {
             .confirm_update_linked_group(
                 new_linked_name,
                 new_groups
-            )
-            .map_err(Error::from)
+            )?;
       },
       Message::SetGroup(group_id, group_val) => {
         self.device_mut()
@@ -242,15 +275,13 @@ impl Glue {
             .unwrap()
             .group_store_mut()
             .set_group(group_id, group_val);
-        Ok(())
       },
       Message::LinkGroups(parent_id, child_id) => {
         self.device_mut()
             .as_mut()
             .unwrap()
             .group_store_mut()
-            .link_groups(&parent_id, &child_id)
-            .map_err(Error::from)
+            .link_groups(&parent_id, &child_id)?;
       },
       Message::DeleteGroup(group_id) => {
         self.device_mut()
@@ -258,39 +289,34 @@ impl Glue {
             .unwrap()
             .group_store_mut()
             .delete_group(&group_id);
-        Ok(())
       },
       Message::AddParent(group_id, parent_id) => {
         self.device_mut()
             .as_mut()
             .unwrap()
             .group_store_mut()
-            .add_parent(&group_id, &parent_id)
-            .map_err(Error::from)
+            .add_parent(&group_id, &parent_id)?;
       },
       Message::RemoveParent(group_id, parent_id) => {
         self.device_mut()
             .as_mut()
             .unwrap()
             .group_store_mut()
-            .remove_parent(&group_id, &parent_id)
-            .map_err(Error::from)
+            .remove_parent(&group_id, &parent_id)?;
       },
       Message::AddChild(group_id, child_id) => {
         self.device_mut()
             .as_mut()
             .unwrap()
             .group_store_mut()
-            .add_child(&group_id, &child_id)
-            .map_err(Error::from)
+            .add_child(&group_id, &child_id)?;
       },
       Message::RemoveChild(group_id, child_id) => {
         self.device_mut()
             .as_mut()
             .unwrap()
             .group_store_mut()
-            .remove_child(&group_id, &child_id)
-            .map_err(Error::from)
+            .remove_child(&group_id, &child_id)?;
       },
       Message::UpdateData(data_id, data_val) => {
         self.device_mut()
@@ -298,7 +324,6 @@ impl Glue {
             .unwrap()
             .data_store_mut()
             .set_data(data_id, data_val);
-        Ok(())
       },
       Message::DeleteData(data_id) => {
         self.device_mut()
@@ -306,29 +331,27 @@ impl Glue {
             .unwrap()
             .data_store_mut()
             .delete_data(&data_id);
-        Ok(())
       },
       Message::DeleteSelfDevice => {
         let idkey = self.idkey().clone();
         self.device_mut()
             .as_mut()
             .unwrap()
-            .delete_device(idkey)
-            .map(|_| self.device = None)
-            .map_err(Error::from)
+            .delete_device(idkey)?;
+        self.device = None;
       },
       Message::DeleteOtherDevice(idkey_to_delete) => {
         self.device_mut()
             .as_mut()
             .unwrap()
-            .delete_device(idkey_to_delete)
-            .map_err(Error::from)
+            .apply_remote_delete(idkey_to_delete, sender.to_string())?;
       },
       Message::Test(msg) => {
         println!("msg");
-        Ok(())
       },
     }
+
+    Ok(Vec::new())
   }
 
   /* Remaining functionality */
@@ -337,63 +360,30 @@ impl Glue {
     self.device = Some(Device::new(self.idkey(), None, None));
   }
 
-  pub async fn create_linked_device(&mut self, idkey: String) {
-    self.device = Some(Device::new(self.idkey(), None, Some(idkey.clone())));
+  // Inviter side of linking: the user approves the device with `idkey`,
+  // whose UpdateLinked message is otherwise rejected as unsolicited
+  pub fn approve_link(&mut self, idkey: String) -> bool {
+    self.device_mut().as_mut().unwrap().add_pending_link(idkey)
+  }
 
-    let linked_name = &self.device()
-        .as_ref()
-        .unwrap()
-        .linked_name()
-        .clone();
+  pub async fn create_linked_device(&mut self, idkey: String) {
+    self.device = Some(Device::new(self.idkey(), None, None));
 
-    let linked_members_to_add = self.device_mut()
+    let link_request = self.device_mut()
         .as_mut()
         .unwrap()
-        .group_store()
-        .get_all_subgroups(linked_name);
+        .initiate_link(idkey.clone());
 
     self.send_message(
         vec![idkey],
         &Message::to_string(&Message::UpdateLinked(
-            self.idkey(),
-            linked_name.to_string(),
-            linked_members_to_add,
+            link_request.sender().to_string(),
+            link_request.linked_name().to_string(),
+            link_request.members().clone(),
         )).unwrap(),
     ).await;
   }
 
-  async fn update_linked_group(
-      &mut self,
-      sender: String,
-      temp_linked_name: String,
-      members_to_add: HashMap<String, Group>,
-  ) -> Result<(), Error> {
-    self.device_mut()
-        .as_mut()
-        .unwrap()
-        .update_linked_group(sender.clone(), temp_linked_name.clone(), members_to_add)
-        .map_err(Error::from);
-    let perm_linked_name = self.device().as_ref().unwrap().linked_name().to_string();
-
-    // send all groups (TODO and data) to new members
-    self.send_message(
-        vec![sender],
-        &Message::to_string(&Message::ConfirmUpdateLinked(
-            perm_linked_name,
-            self.device()
-                .as_ref()
-                .unwrap()
-                .group_store()
-                .get_all_groups()
-                .clone()
-        )).unwrap(),
-    ).await;
-
-    // TODO notify contacts of new members
-
-    Ok(())
-  }
-
   pub async fn delete_self_device(&mut self) -> Result<(), Error> {
     // TODO send to contact devices too
     self.send_message(
@@ -463,9 +453,11 @@ impl Glue {
   }
 }
 
+#[cfg(test)]
 mod tests {
-  use crate::glue::{Glue, Message};
+  use crate::glue::{Error, Glue, Message};
   use crate::groups::{Group};
+  use crate::devices::Device;
   use futures::channel::mpsc;
 
   #[tokio::test]
@@ -480,6 +472,93 @@ mod tests {
     }
   }
 
+  #[test]
+  fn test_handle_update_linked_returns_confirm() {
+    let mut glue_0 = Glue::new(None, None, false);
+    glue_0.create_standalone_device();
+    let linked_name_0 = glue_0.device().as_ref().unwrap().linked_name().clone();
+
+    let idkey_1 = String::from("1");
+    let device_1 = Device::new(idkey_1.clone(), None, None);
+    let linked_name_1 = device_1.linked_name().clone();
+    glue_0.approve_link(idkey_1.clone());
+
+    let outbound_messages = glue_0.handle_message(
+        &idkey_1,
+        Message::UpdateLinked(
+            idkey_1.clone(),
+            linked_name_1.clone(),
+            device_1.group_store().get_all_subgroups(&linked_name_1),
+        )
+    ).unwrap();
+
+    assert_eq!(outbound_messages.len(), 1);
+    let (recipients, outbound_message) = &outbound_messages[0];
+    assert_eq!(recipients, &vec![idkey_1.clone()]);
+    match outbound_message {
+      Message::ConfirmUpdateLinked(new_linked_name, new_groups) => {
+        assert_eq!(new_linked_name, &linked_name_0);
+        assert_eq!(
+            new_groups,
+            glue_0.device().as_ref().unwrap().group_store().get_all_groups()
+        );
+        assert!(new_groups.contains_key(&idkey_1));
+      },
+      other => panic!("Expected ConfirmUpdateLinked, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn test_handle_update_linked_rejects_unapproved() {
+    let mut glue_0 = Glue::new(None, None, false);
+    glue_0.create_standalone_device();
+
+    let idkey_1 = String::from("1");
+    let device_1 = Device::new(idkey_1.clone(), None, None);
+    let linked_name_1 = device_1.linked_name().clone();
+
+    let result = glue_0.handle_message(
+        &idkey_1,
+        Message::UpdateLinked(
+            idkey_1.clone(),
+            linked_name_1.clone(),
+            device_1.group_store().get_all_subgroups(&linked_name_1),
+        )
+    );
+    assert!(matches!(
+        result,
+        Err(Error::DeviceErr { source: crate::devices::Error::UnsolicitedLink(_) })
+    ));
+    assert_eq!(glue_0.device().as_ref().unwrap().linked_devices().len(), 1);
+  }
+
+  #[test]
+  fn test_handle_update_linked_rejects_spoofed_sender() {
+    let mut glue_0 = Glue::new(None, None, false);
+    glue_0.create_standalone_device();
+
+    let idkey_1 = String::from("1");
+    let idkey_2 = String::from("2");
+    let device_2 = Device::new(idkey_2.clone(), None, None);
+    let linked_name_2 = device_2.linked_name().clone();
+    glue_0.approve_link(idkey_1.clone());
+
+    // device 2 claims to be the approved device 1
+    let result = glue_0.handle_message(
+        &idkey_2,
+        Message::UpdateLinked(
+            idkey_1.clone(),
+            linked_name_2.clone(),
+            device_2.group_store().get_all_subgroups(&linked_name_2),
+        )
+    );
+    assert!(matches!(
+        result,
+        Err(Error::DeviceErr { source: crate::devices::Error::UnsolicitedLink(_) })
+    ));
+    assert_eq!(glue_0.device().as_ref().unwrap().linked_devices().len(), 1);
+  }
+
   #[tokio::test]
   async fn test_handle_events() {
     let mut glue_0 = Glue::new(None, None, false);
@@ -519,6 +598,7 @@ mod tests {
     glue_1.core.receive_message().await;
     println!("creating device 1");
 
+    glue_0.approve_link(glue_1.idkey());
     // also sends message to device 0 to link devices
     glue_1.create_linked_device(glue_0.idkey()).await;
 
@@ -539,6 +619,7 @@ mod tests {
     // upload otkeys to server
     glue_1.core.receive_message().await;
 
+    glue_0.approve_link(glue_1.idkey());
     // also sends message to device 0 to link devices
     println!("LINKING <1> to <0>\n");
     glue_1.create_linked_device(glue_0.idkey()).await;
@@ -567,6 +648,7 @@ mod tests {
     // upload otkeys to server
     glue_1.core.receive_message().await;
 
+    glue_0.approve_link(glue_1.idkey());
     // also sends message to device 0 to link devices
     glue_1.create_linked_device(glue_0.idkey()).await;
     // receive update_linked...
@@ -601,6 +683,7 @@ mod tests {
     // upload otkeys to server
     glue_1.core.receive_message().await;
 
+    glue_0.approve_link(glue_1.idkey());
     // also sends message to device 0 to link devices
     glue_1.create_linked_device(glue_0.idkey()).await;
     // receive update_linked...
@@ -635,6 +718,7 @@ mod tests {
     // upload otkeys to server
     glue_1.core.receive_message().await;
 
+    glue_0.approve_link(glue_1.idkey());
     // also sends message to device 0 to link devices
     glue_1.create_linked_device(glue_0.idkey()).await;
     // receive update_linked...
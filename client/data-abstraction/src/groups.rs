@@ -1,6 +1,11 @@
 use serde::{Serialize, Deserialize};
+use std::cell::RefCell;
+use std::collections::BTreeSet;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
 use thiserror::Error;
 use uuid::Uuid;
 
@@ -10,14 +15,106 @@ pub enum Error {
   GroupHasNoChildren(String),
   #[error("group {0} does not exist")]
   GroupDoesNotExist(String),
+  #[error("contact level of group {0} conflicts with its parents or children")]
+  ContactLevelConflict(String),
+  #[error("traversal exceeded the maximum number of groups")]
+  LimitExceeded,
+  #[error("group {0} already exists")]
+  GroupAlreadyExists(String),
+  #[error("linking {0} under {1} would create a cycle")]
+  WouldCreateCycle(String, String),
+  #[error("incoming groups are nested more than {0} levels deep")]
+  DepthExceeded(usize),
+  #[error("merge plan was computed against an older version of the store")]
+  StalePlan,
+  #[error("edge op {index} failed: {source}")]
+  EdgeOpFailed {
+    index: usize,
+    source: Box<Error>,
+  },
 }
 
+// Attribute keys whose values are group ids, so that group_replace rewrites
+// them along with parent and child references
+pub const ID_ATTRIBUTE_KEYS: &[&str] = &["owner"];
+
+// Contents of a group compared, in this order, when merging two versions of
+// it: updated_at, contact level, parents, leafness and children, attributes
+// and origin
+type MergeOrderKey<'a> = (
+    Option<u64>,
+    bool,
+    Vec<&'a String>,
+    (bool, Vec<&'a String>),
+    Vec<(&'a String, &'a String)>,
+    Option<&'a String>,
+);
+
+#[derive(Debug, PartialEq, Error)]
+#[error("groups contain a cycle")]
+pub struct CycleError;
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "GroupRepr", into = "GroupRepr")]
 pub struct Group {
+  group_id: String,
+  contact_level: bool,
+  parents: HashSet<String>,
+  // always empty for leaf (device) groups, which cannot have children
+  children: HashSet<String>,
+  is_leaf: bool,
+  #[serde(default)]
+  updated_at: Option<u64>,
+  #[serde(default)]
+  attributes: HashMap<String, String>,
+  // sender that introduced this group, None for locally created groups
+  #[serde(default)]
+  origin: Option<String>,
+}
+
+// Serialized form of a Group, which predates `is_leaf`: leaf groups are
+// encoded as having no children set at all (`"children": null`)
+#[derive(Serialize, Deserialize)]
+struct GroupRepr {
   group_id: String,
   contact_level: bool,
   parents: HashSet<String>,
   children: Option<HashSet<String>>,
+  #[serde(default)]
+  updated_at: Option<u64>,
+  #[serde(default)]
+  attributes: HashMap<String, String>,
+  #[serde(default)]
+  origin: Option<String>,
+}
+
+impl From<GroupRepr> for Group {
+  fn from(repr: GroupRepr) -> Group {
+    Group {
+      group_id: repr.group_id,
+      contact_level: repr.contact_level,
+      parents: repr.parents,
+      is_leaf: repr.children.is_none(),
+      children: repr.children.unwrap_or_default(),
+      updated_at: repr.updated_at,
+      attributes: repr.attributes,
+      origin: repr.origin,
+    }
+  }
+}
+
+impl From<Group> for GroupRepr {
+  fn from(group: Group) -> GroupRepr {
+    GroupRepr {
+      group_id: group.group_id,
+      contact_level: group.contact_level,
+      parents: group.parents,
+      children: (!group.is_leaf).then_some(group.children),
+      updated_at: group.updated_at,
+      attributes: group.attributes,
+      origin: group.origin,
+    }
+  }
 }
 
 impl Group {
@@ -33,16 +130,16 @@ impl Group {
       init_group_id = group_id.unwrap();
     }
 
-    let mut children = None;
-    if init_children {
-      children = Some(HashSet::<String>::new());
-    }
 
     Self {
       group_id: init_group_id,
       contact_level,
       parents: HashSet::<String>::new(),
-      children,
+      children: HashSet::<String>::new(),
+      is_leaf: !init_children,
+      updated_at: None,
+      attributes: HashMap::<String, String>::new(),
+      origin: None,
     }
   }
 
@@ -63,6 +160,38 @@ impl Group {
     old_contact_level
   }
 
+  pub fn updated_at(&self) -> Option<u64> {
+    self.updated_at
+  }
+
+  pub fn set_updated_at(&mut self, updated_at: u64) -> Option<u64> {
+    self.updated_at.replace(updated_at)
+  }
+
+  pub fn origin(&self) -> Option<&String> {
+    self.origin.as_ref()
+  }
+
+  pub fn set_origin(&mut self, origin: Option<String>) -> Option<String> {
+    std::mem::replace(&mut self.origin, origin)
+  }
+
+  pub fn attributes(&self) -> &HashMap<String, String> {
+    &self.attributes
+  }
+
+  pub fn get_attribute(&self, key: &str) -> Option<&String> {
+    self.attributes.get(key)
+  }
+
+  pub fn set_attribute(&mut self, key: String, val: String) -> Option<String> {
+    self.attributes.insert(key, val)
+  }
+
+  pub fn remove_attribute(&mut self, key: &str) -> Option<String> {
+    self.attributes.remove(key)
+  }
+
   pub fn parents(&self) -> &HashSet<String> {
     &self.parents
   }
@@ -75,40 +204,293 @@ impl Group {
     self.parents.remove(parent_id)
   }
 
-  pub fn children(&self) -> &Option<HashSet<String>> {
+  pub fn children(&self) -> &HashSet<String> {
     &self.children
   }
 
+  // Leaf groups (devices) cannot have children, unlike intermediate groups
+  // that merely have none at the moment
+  pub fn is_leaf(&self) -> bool {
+    self.is_leaf
+  }
+
   pub fn add_child(&mut self, child_id: String) -> Result<(), Error> {
-    match self.children {
-      Some(_) => {
-        self.children.as_mut().unwrap().insert(child_id);
-        Ok(())
-      },
-      None => Err(Error::GroupHasNoChildren(self.group_id().to_string())),
+    if self.is_leaf {
+      return Err(Error::GroupHasNoChildren(self.group_id().to_string()));
     }
+    self.children.insert(child_id);
+    Ok(())
   }
 
   pub fn remove_child(&mut self, child_id: &String) -> Result<bool, Error> {
-    match self.children {
-      Some(_) => Ok(self.children.as_mut().unwrap().remove(child_id)),
-      None => Err(Error::GroupHasNoChildren(self.group_id().to_string())),
+    if self.is_leaf {
+      return Err(Error::GroupHasNoChildren(self.group_id().to_string()));
     }
+    Ok(self.children.remove(child_id))
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GroupInconsistency {
+  // stored under a key other than its group_id
+  IdMismatch(String),
+  // (group, parent) where parent does not exist
+  MissingParent(String, String),
+  // (group, child) where child does not exist
+  MissingChild(String, String),
+  // (group, parent) where parent does not list group as a child
+  ParentMissingChildEdge(String, String),
+  // (group, child) where child does not list group as a parent
+  ChildMissingParentEdge(String, String),
+  // a leaf group that nonetheless has children
+  LeafWithChildren(String),
+}
+
+// Ids of the groups a merge added and replaced, sorted
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergeReport {
+  pub created: Vec<String>,
+  pub updated: Vec<String>,
+}
+
+// How `merge_with_policy` combines the attributes of a group that exists in
+// both stores
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AttributeMergePolicy {
+  // the winning group's attributes replace the other's entirely
+  #[default]
+  Overwrite,
+  // keys from both groups; the winning group's value on a clash
+  Union,
+  // keys from both groups; the local value on a clash
+  KeepExisting,
+}
+
+// Outcome of merging another store, computed by `plan_merge` without
+// modifying anything and applied by `commit_plan`
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergePlan {
+  // generation of the store the plan was computed against
+  generation: u64,
+  upserts: HashMap<String, Group>,
+  report: MergeReport,
+  // ids present in both stores with different contents, sorted
+  conflicts: Vec<String>,
+}
+
+impl MergePlan {
+  pub fn report(&self) -> &MergeReport {
+    &self.report
+  }
+
+  pub fn conflicts(&self) -> &[String] {
+    &self.conflicts
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GroupStoreStats {
+  pub group_count: usize,
+  pub edge_count: usize,
+  pub max_depth: usize,
+  pub contact_count: usize,
+  pub root_count: usize,
+}
+
+// A single structural change to a group store, for replaying op-logs
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum EdgeOp {
+  SetGroup(String, Group),
+  DeleteGroup(String),
+  // (parent, child)
+  LinkGroups(String, String),
+  // (parent, child)
+  UnlinkGroups(String, String),
+  // (group, parent)
+  AddParent(String, String),
+  // (group, parent)
+  RemoveParent(String, String),
+  // (group, child)
+  AddChild(String, String),
+  // (group, child)
+  RemoveChild(String, String),
+}
+
+// The changes that turn one group store into another: groups that are new or
+// whose contents changed, and ids of groups that no longer exist
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GroupStoreDiff {
+  upserted: HashMap<String, Group>,
+  removed: HashSet<String>,
+}
+
+impl GroupStoreDiff {
+  pub fn upserted(&self) -> &HashMap<String, Group> {
+    &self.upserted
+  }
+
+  pub fn removed(&self) -> &HashSet<String> {
+    &self.removed
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.upserted.is_empty() && self.removed.is_empty()
+  }
+}
+
+// Borrowed neighbor lists of every group in a store, built once by
+// `GroupStore::as_adjacency` for callers that look up many neighbors
+#[derive(Debug, Clone, PartialEq)]
+pub struct Adjacency<'a> {
+  children: HashMap<&'a str, Vec<&'a String>>,
+  parents: HashMap<&'a str, Vec<&'a String>>,
+}
+
+impl<'a> Adjacency<'a> {
+  // Empty for unknown groups and for groups that cannot have children
+  pub fn children_of(&self, group_id: &str) -> &[&'a String] {
+    self.children.get(group_id).map_or(&[], |children| children.as_slice())
+  }
+
+  pub fn parents_of(&self, group_id: &str) -> &[&'a String] {
+    self.parents.get(group_id).map_or(&[], |parents| parents.as_slice())
+  }
+}
+
+// Notified by `GroupStore::merge` of every group that exists in both stores
+// with different contents, along with the version the merge kept
+pub trait MergeObserver {
+  fn on_conflict(&self, id: &str, existing: &Group, incoming: &Group, chosen: &Group);
+}
+
+// Shared handle to a store's observer, which is configuration rather than
+// store contents
+#[derive(Clone)]
+struct MergeObserverHandle(Arc<dyn MergeObserver + Send + Sync>);
+
+impl std::fmt::Debug for MergeObserverHandle {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.write_str("MergeObserver")
+  }
+}
+
+// Memoized resolve_ids results keyed by group id. Each entry also records
+// every group visited while resolving it, so that an edit to a group only
+// evicts the entries whose resolution passed through that group
+#[derive(Debug, Clone, Default)]
+pub struct ResolveCache {
+  // group id -> (resolved ids, visited ids)
+  entries: HashMap<String, (HashSet<String>, HashSet<String>)>,
+}
+
+impl ResolveCache {
+  fn invalidate(&mut self, group_id: &str) {
+    self.entries.retain(|_, (_, visited)| !visited.contains(group_id));
   }
 }
 
-#[derive(Debug, PartialEq)]
+// Only the groups themselves are serialized; the resolve cache and the
+// generation start out fresh after deserializing
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GroupStore {
   store: HashMap<String, Group>,
+  #[serde(skip)]
+  resolve_cache: RefCell<Option<ResolveCache>>,
+  // bumped on every change, so that stale merge plans can be detected
+  #[serde(skip)]
+  generation: u64,
+  // groups that never contribute members to resolution, e.g. because they
+  // were revoked
+  #[serde(default)]
+  blocklist: HashSet<String>,
+  #[serde(skip)]
+  merge_observer: Option<MergeObserverHandle>,
+}
+
+// The resolve cache and generation are bookkeeping and do not take part in
+// equality
+impl PartialEq for GroupStore {
+  fn eq(&self, other: &Self) -> bool {
+    self.store == other.store && self.blocklist == other.blocklist
+  }
 }
 
 impl GroupStore {
   pub fn new() -> GroupStore {
     Self {
       store: HashMap::<String, Group>::new(),
+      resolve_cache: RefCell::new(None),
+      generation: 0,
+      blocklist: HashSet::new(),
+      merge_observer: None,
+    }
+  }
+
+  // Builds a store from parent -> child edges, mostly for concise test setup.
+  // Groups that appear as a parent can have children, all others are device
+  // groups; ids in `contacts` are marked as contacts. Panics if the
+  // resulting store is inconsistent
+  pub fn from_edges(edges: &[(&str, &str)], contacts: &[&str]) -> GroupStore {
+    let mut group_store = GroupStore::new();
+    let parents = edges
+        .iter()
+        .map(|(parent_id, _)| *parent_id)
+        .collect::<HashSet::<&str>>();
+
+    let ids = edges
+        .iter()
+        .flat_map(|(parent_id, child_id)| [*parent_id, *child_id])
+        .chain(contacts.iter().cloned());
+    for id in ids {
+      if group_store.store.contains_key(id) {
+        continue;
+      }
+      group_store.set_group(
+          id.to_string(),
+          Group::new(Some(id.to_string()), contacts.contains(&id), parents.contains(id))
+      );
+    }
+
+    for (parent_id, child_id) in edges {
+      group_store.link_groups(&parent_id.to_string(), &child_id.to_string()).unwrap();
+    }
+
+    group_store.validate().unwrap();
+    group_store
+  }
+
+  // Memoizes resolve_ids per group until a group it depends on is edited
+  pub fn enable_resolve_cache(&mut self) {
+    let mut resolve_cache = self.resolve_cache.borrow_mut();
+    if resolve_cache.is_none() {
+      *resolve_cache = Some(ResolveCache::default());
+    }
+  }
+
+  // Must be called whenever `group_id` is (or may be) modified
+  fn mark_changed(&mut self, group_id: &str) {
+    self.generation += 1;
+    if let Some(resolve_cache) = self.resolve_cache.get_mut() {
+      resolve_cache.invalidate(group_id);
     }
   }
 
+  // Excludes `group_id` and everything only reachable through it from
+  // resolution until it is unblocked
+  pub fn block(&mut self, group_id: &str) -> bool {
+    self.mark_changed(group_id);
+    self.blocklist.insert(group_id.to_string())
+  }
+
+  pub fn unblock(&mut self, group_id: &str) -> bool {
+    self.mark_changed(group_id);
+    self.blocklist.remove(group_id)
+  }
+
+  pub fn is_blocked(&self, group_id: &str) -> bool {
+    self.blocklist.contains(group_id)
+  }
+
   pub fn get_group(&self, group_id: &String) -> Option<&Group> {
     self.store.get(group_id)
   }
@@ -117,6 +499,7 @@ impl GroupStore {
       &mut self,
       group_id: &String
   ) -> Option<&mut Group> {
+    self.mark_changed(group_id);
     self.store.get_mut(group_id)
   }
 
@@ -125,29 +508,86 @@ impl GroupStore {
       group_id: String,
       group_val: Group
   ) -> Option<Group> {
+    self.mark_changed(&group_id);
     self.store.insert(group_id, group_val)
   }
 
-  pub fn add_parent(
+  // Same as `set_group`, but refuses a group that references a parent or
+  // child which does not exist (other than the group itself)
+  pub fn set_group_checked(
       &mut self,
-      base_group_id: &String,
-      to_parent_id: &String,
+      group_id: String,
+      group_val: Group,
   ) -> Result<(), Error> {
-    if self.get_group(base_group_id).is_none() {
-      return Err(Error::GroupDoesNotExist(base_group_id.to_string()));
+    let referenced = group_val.parents()
+        .iter()
+        .chain(group_val.children().iter());
+    for referenced_id in referenced {
+      if referenced_id != &group_id && !self.store.contains_key(referenced_id) {
+        return Err(Error::GroupDoesNotExist(referenced_id.to_string()));
+      }
     }
 
-    if self.get_group(to_parent_id).is_none() {
-      return Err(Error::GroupDoesNotExist(to_parent_id.to_string()));
+    self.set_group(group_id, group_val);
+    Ok(())
+  }
+
+  // Inserts all groups parents-first, so that no inserted group references
+  // a parent from the batch that has not been inserted yet. Nothing is
+  // inserted if the batch contains a cycle
+  pub fn bulk_set_groups(
+      &mut self,
+      groups: HashMap<String, Group>,
+  ) -> Result<(), CycleError> {
+    let order = GroupStore::topological_order(&groups)?;
+    let mut groups = groups;
+    for group_id in order {
+      let group_val = groups.remove(&group_id).unwrap();
+      self.set_group(group_id, group_val);
     }
+    Ok(())
+  }
 
-    let mut base_group = self.get_group_mut(base_group_id).unwrap().clone();
-    base_group.add_parent(to_parent_id.to_string());
-    self.set_group(base_group_id.to_string(), base_group);
+  // A contact group may only have contact children, so that a device's own
+  // (non-contact) groups are never reachable from a contact group
+  pub fn set_contact_level(
+      &mut self,
+      group_id: &str,
+      contact_level: bool,
+  ) -> Result<(), Error> {
+    let group_val = match self.store.get(group_id) {
+      Some(group_val) => group_val,
+      None => return Err(Error::GroupDoesNotExist(group_id.to_string())),
+    };
+
+    let conflicts = if contact_level {
+      group_val.children().iter().any(|child| {
+        self.store.get(child).is_some_and(|val| !val.contact_level())
+      })
+    } else {
+      group_val.parents().iter().any(|parent| {
+        self.store.get(parent).is_some_and(|val| *val.contact_level())
+      })
+    };
+    if conflicts {
+      return Err(Error::ContactLevelConflict(group_id.to_string()));
+    }
+
+    self.mark_changed(group_id);
+    self.store.get_mut(group_id).unwrap().update_contact_level(contact_level);
 
     Ok(())
   }
 
+  // also adds base_group_id to to_parent_id's children
+  pub fn add_parent(
+      &mut self,
+      base_group_id: &String,
+      to_parent_id: &String,
+  ) -> Result<(), Error> {
+    self.link_groups(to_parent_id, base_group_id)
+  }
+
   pub fn remove_parent(
       &mut self,
       base_group_id: &String,
@@ -168,26 +608,13 @@ impl GroupStore {
     Ok(())
   }
 
+  // also adds base_group_id to to_child_id's parents
   pub fn add_child(
       &mut self,
       base_group_id: &String,
       to_child_id: &String,
   ) -> Result<(), Error> {
-    if self.get_group(base_group_id).is_none() {
-      return Err(Error::GroupDoesNotExist(base_group_id.to_string()));
-    }
-
-    if self.get_group(to_child_id).is_none() {
-      return Err(Error::GroupDoesNotExist(to_child_id.to_string()));
-    }
-
-    let mut base_group = self.get_group_mut(base_group_id).unwrap().clone();
-    base_group.add_child(to_child_id.to_string())
-        .map(|_| {
-          self.set_group(base_group_id.to_string(), base_group);
-          Ok(())
-        })
-        .unwrap()
+    self.link_groups(base_group_id, to_child_id)
   }
 
   pub fn remove_child(
@@ -212,6 +639,24 @@ impl GroupStore {
         .unwrap()
   }
 
+  // Unlinks child from parent in both directions and, if
+  // `delete_if_orphaned` is set and child is left without parents, deletes
+  // the child group as well. Returns whether the child was deleted
+  pub fn remove_child_cascade(
+      &mut self,
+      parent_id: &String,
+      child_id: &String,
+      delete_if_orphaned: bool,
+  ) -> Result<bool, Error> {
+    self.unlink_groups(parent_id, child_id)?;
+
+    if delete_if_orphaned && self.get_group(child_id).unwrap().parents().is_empty() {
+      self.delete_group(child_id);
+      return Ok(true);
+    }
+    Ok(false)
+  }
+
   pub fn link_groups(
       &mut self,
       to_parent_id: &String,
@@ -227,10 +672,10 @@ impl GroupStore {
 
     // set child of to_parent group
     let mut to_parent_group = self.get_group_mut(to_parent_id).unwrap().clone();
-    if to_parent_group.children.is_none() {
+    if to_parent_group.is_leaf() {
       return Err(Error::GroupHasNoChildren(to_parent_id.to_string()));
     }
-    to_parent_group.add_child(to_child_id.to_string());
+    to_parent_group.add_child(to_child_id.to_string())?;
     self.set_group(to_parent_id.to_string(), to_parent_group);
 
     // set parent of to_child group
@@ -241,6 +686,32 @@ impl GroupStore {
     Ok(())
   }
 
+  // Like link_groups, but first creates any missing endpoint as a
+  // non-contact group without children: an empty group for the parent and
+  // a device (leaf) group for the child
+  pub fn link_groups_creating(
+      &mut self,
+      to_parent_id: &str,
+      to_child_id: &str,
+  ) -> Result<(), Error> {
+    let to_parent_id = to_parent_id.to_string();
+    let to_child_id = to_child_id.to_string();
+    if self.get_group(&to_parent_id).is_none() {
+      self.set_group(
+          to_parent_id.clone(),
+          Group::new(Some(to_parent_id.clone()), false, true)
+      );
+    }
+    if self.get_group(&to_child_id).is_none() {
+      self.set_group(
+          to_child_id.clone(),
+          Group::new(Some(to_child_id.clone()), false, false)
+      );
+    }
+
+    self.link_groups(&to_parent_id, &to_child_id)
+  }
+
   pub fn unlink_groups(
       &mut self,
       parent_id: &String,
@@ -256,10 +727,10 @@ impl GroupStore {
 
     // unset child of parent group
     let mut parent_group = self.get_group_mut(parent_id).unwrap().clone();
-    if parent_group.children.is_none() {
+    if parent_group.is_leaf() {
       return Err(Error::GroupHasNoChildren(parent_id.to_string()));
     }
-    parent_group.remove_child(child_id);
+    parent_group.remove_child(child_id)?;
     self.set_group(parent_id.to_string(), parent_group);
 
     // unset parent of child group
@@ -270,464 +741,3191 @@ impl GroupStore {
     Ok(())
   }
 
-  pub fn delete_group(&mut self, group_id: &String) -> Option<Group> {
-    if self.get_group(group_id).is_none() {
-      return None;
-    }
-
-    let group_val = self.get_group(group_id).unwrap().clone();
+  // Replaces group_id's children with `children`, unlinking removed children
+  // and linking added ones so that their parent sets stay consistent
+  pub fn set_children(
+      &mut self,
+      group_id: &str,
+      children: HashSet<String>,
+  ) -> Result<(), Error> {
+    let cur_children = match self.store.get(group_id) {
+      Some(group_val) if group_val.is_leaf() => {
+        return Err(Error::GroupHasNoChildren(group_id.to_string()));
+      },
+      Some(group_val) => group_val.children().clone(),
+      None => return Err(Error::GroupDoesNotExist(group_id.to_string())),
+    };
 
-    // delete from all parents' children lists
-    for parent_id in &group_val.parents {
-      let mut parent_group = self.get_group_mut(&parent_id).unwrap().clone();
-      parent_group.remove_child(group_id);
-      self.set_group(parent_id.to_string(), parent_group);
+    for child_id in children.difference(&cur_children) {
+      if !self.store.contains_key(child_id) {
+        return Err(Error::GroupDoesNotExist(child_id.to_string()));
+      }
     }
 
-    // delete from any childrens' parents lists
-    if let Some(children) = group_val.children {
-      for child_id in children {
-        let mut child_group = self.get_group_mut(&child_id).unwrap().clone();
-        child_group.remove_parent(group_id);
-        self.set_group(child_id.to_string(), child_group);
-      }
+    let group_id = group_id.to_string();
+    for child_id in cur_children.difference(&children) {
+      self.unlink_groups(&group_id, child_id)?;
+    }
+    for child_id in children.difference(&cur_children) {
+      self.link_groups(&group_id, child_id)?;
     }
 
-    self.store.remove(group_id)
+    Ok(())
   }
 
-  pub fn is_device_group(&self, group_val: &Group) -> bool {
-    if group_val.children.is_none() {
-      return true;
+  // Replaces group_id's parents with `parents`, updating the children sets of
+  // removed and added parents accordingly
+  pub fn set_parents(
+      &mut self,
+      group_id: &str,
+      parents: HashSet<String>,
+  ) -> Result<(), Error> {
+    let cur_parents = match self.store.get(group_id) {
+      Some(group_val) => group_val.parents().clone(),
+      None => return Err(Error::GroupDoesNotExist(group_id.to_string())),
+    };
+
+    for parent_id in parents.difference(&cur_parents) {
+      match self.store.get(parent_id) {
+        Some(parent_val) if parent_val.is_leaf() => {
+          return Err(Error::GroupHasNoChildren(parent_id.to_string()));
+        },
+        Some(_) => {},
+        None => return Err(Error::GroupDoesNotExist(parent_id.to_string())),
+      }
     }
-    false
-  }
 
-  pub fn add_members(
-      &mut self,
-      base_group_id: &String,
-      ids_to_add: Vec<&String>,
-  ) {
-    for id_to_add in ids_to_add {
-      self.link_groups(base_group_id, id_to_add);
+    let group_id = group_id.to_string();
+    for parent_id in cur_parents.difference(&parents) {
+      self.unlink_groups(parent_id, &group_id)?;
     }
+    for parent_id in parents.difference(&cur_parents) {
+      self.link_groups(parent_id, &group_id)?;
+    }
+
+    Ok(())
   }
 
-  pub fn remove_members(
-      &mut self,
-      base_group_id: &String,
-      ids_to_remove: Vec<&String>,
-  ) {
-    for id_to_remove in ids_to_remove {
-      self.unlink_groups(base_group_id, id_to_remove);
+  // Exchanges the contents (edges, contact level and metadata) of groups `a`
+  // and `b` and rewires all references to them, so that `a` takes `b`'s
+  // place in the graph and vice versa
+  pub fn swap_groups(&mut self, a: &str, b: &str) -> Result<(), Error> {
+    for id in [a, b] {
+      if !self.store.contains_key(id) {
+        return Err(Error::GroupDoesNotExist(id.to_string()));
+      }
+    }
+    if a == b {
+      return Ok(());
     }
-  }
 
-  pub fn resolve_ids<'a>(
-      &'a self,
-      ids: Vec<&'a String>,
-  ) -> HashSet<&String> {
-    let mut resolved_ids = HashSet::<&String>::new();
-    let mut visited = HashSet::<&String>::new();
+    let mut affected = HashSet::<String>::new();
+    for id in [a, b] {
+      affected.insert(id.to_string());
+      affected.extend(self.referencing_groups(id));
+    }
 
-    for id in ids {
-      self.resolve_ids_helper(
-          &mut resolved_ids,
-          &mut visited,
-          id
-      );
+    // swap the two ids everywhere they occur by way of a temporary id
+    let temp_id = Uuid::new_v4().to_string();
+    let mut swapped = Vec::<Group>::new();
+    for id in affected.iter() {
+      if let Some(group_val) = self.store.get(id) {
+        let mut group_val = group_val.clone();
+        GroupStore::group_replace(&mut group_val, a.to_string(), temp_id.clone());
+        GroupStore::group_replace(&mut group_val, b.to_string(), a.to_string());
+        GroupStore::group_replace(&mut group_val, temp_id.clone(), b.to_string());
+        swapped.push(group_val);
+      }
+    }
+    for group_val in swapped {
+      self.set_group(group_val.group_id().to_string(), group_val);
     }
 
-    resolved_ids
+    Ok(())
   }
 
-  fn resolve_ids_helper<'a>(
-      &'a self,
-      resolved_ids: &mut HashSet<&'a String>,
-      visited: &mut HashSet<&'a String>,
-      id: &'a String,
-  ) {
-    let mut to_visit = Vec::<&String>::new();
-    to_visit.push(id);
+  // Moves `group_id` (with everything below it) from all of its current
+  // parents to `new_parent_id`
+  pub fn reparent(&mut self, group_id: &str, new_parent_id: &str) -> Result<(), Error> {
+    let cur_parents = match self.store.get(group_id) {
+      Some(group_val) => group_val.parents().clone(),
+      None => return Err(Error::GroupDoesNotExist(group_id.to_string())),
+    };
+    match self.store.get(new_parent_id) {
+      Some(parent_val) if parent_val.is_leaf() => {
+        return Err(Error::GroupHasNoChildren(new_parent_id.to_string()));
+      },
+      Some(_) => {},
+      None => return Err(Error::GroupDoesNotExist(new_parent_id.to_string())),
+    }
+    if self.ancestors_and_self(new_parent_id).contains(group_id) {
+      return Err(Error::WouldCreateCycle(group_id.to_string(), new_parent_id.to_string()));
+    }
 
-    while !to_visit.is_empty() {
-      let cur_id = to_visit.pop().unwrap();
+    let group_id = group_id.to_string();
+    for parent_id in cur_parents.iter() {
+      self.unlink_groups(parent_id, &group_id)?;
+    }
+    self.link_groups(&new_parent_id.to_string(), &group_id)
+  }
 
-      if visited.get(cur_id).is_some() {
-        continue;
-      }
+  // Changes the id of group `old_id` to `new_id`, rewriting every reference
+  // to it in its parents and children and in id-bearing attributes
+  pub fn rename_group(&mut self, old_id: &str, new_id: &str) -> Result<(), Error> {
+    let group_val = match self.store.get(old_id) {
+      Some(group_val) => group_val.clone(),
+      None => return Err(Error::GroupDoesNotExist(old_id.to_string())),
+    };
+    if self.store.contains_key(new_id) {
+      return Err(Error::GroupAlreadyExists(new_id.to_string()));
+    }
 
-      visited.insert(cur_id);
-      if let Some(children) = &self.get_group(cur_id).unwrap().children {
-        for child in children {
-          to_visit.push(&child);
-        }
-      } else {
-        resolved_ids.insert(cur_id);
+    let mut neighbors = self.referencing_groups(old_id);
+    neighbors.remove(old_id);
+    for neighbor_id in neighbors {
+      if let Some(neighbor_val) = self.store.get(&neighbor_id) {
+        let mut neighbor_val = neighbor_val.clone();
+        GroupStore::group_replace(&mut neighbor_val, old_id.to_string(), new_id.to_string());
+        self.set_group(neighbor_id, neighbor_val);
       }
     }
-  }
 
-  pub fn get_all_groups(&self) -> &HashMap<String, Group> {
-    &self.store
+    let mut group_val = group_val;
+    GroupStore::group_replace(&mut group_val, old_id.to_string(), new_id.to_string());
+    self.mark_changed(old_id);
+    self.store.remove(old_id);
+    self.set_group(new_id.to_string(), group_val);
+
+    Ok(())
   }
 
-  pub fn get_all_subgroups<'a>(
-      &'a self,
-      group_id: &'a String
-  ) -> HashMap<String, Group> {
-    let mut subgroups = HashMap::<String, Group>::new();
-    let mut visited = HashSet::<&String>::new();
-    let mut to_visit = Vec::<&String>::new();
-    to_visit.push(group_id);
+  // Groups that refer to `group_id`: its parents and children, plus any
+  // group with an ID_ATTRIBUTE_KEYS attribute set to it
+  fn referencing_groups(&self, group_id: &str) -> HashSet<String> {
+    let mut referencing = HashSet::<String>::new();
+    if let Some(group_val) = self.store.get(group_id) {
+      referencing.extend(group_val.parents().iter().cloned());
+      referencing.extend(group_val.children().iter().cloned());
+    }
+    for (id, val) in self.store.iter() {
+      let refers = ID_ATTRIBUTE_KEYS.iter()
+          .any(|key| val.get_attribute(key).is_some_and(|attr| attr == group_id));
+      if refers {
+        referencing.insert(id.to_string());
+      }
+    }
+    referencing
+  }
 
-    while !to_visit.is_empty() {
-      let cur_id = to_visit.pop().unwrap();
+  // Applies `ops` in order. Stops at the first failing op and reports its
+  // index; ops before it stay applied
+  pub fn apply_edge_ops(&mut self, ops: Vec<EdgeOp>) -> Result<(), Error> {
+    for (index, op) in ops.into_iter().enumerate() {
+      let result = match op {
+        EdgeOp::SetGroup(group_id, group_val) => {
+          self.set_group(group_id, group_val);
+          Ok(())
+        },
+        EdgeOp::DeleteGroup(group_id) => {
+          match self.remove_group(&group_id) {
+            Some(_) => Ok(()),
+            None => Err(Error::GroupDoesNotExist(group_id)),
+          }
+        },
+        EdgeOp::LinkGroups(parent_id, child_id) => self.link_groups(&parent_id, &child_id),
+        EdgeOp::UnlinkGroups(parent_id, child_id) => self.unlink_groups(&parent_id, &child_id),
+        EdgeOp::AddParent(group_id, parent_id) => self.add_parent(&group_id, &parent_id),
+        EdgeOp::RemoveParent(group_id, parent_id) => self.remove_parent(&group_id, &parent_id),
+        EdgeOp::AddChild(group_id, child_id) => self.add_child(&group_id, &child_id),
+        EdgeOp::RemoveChild(group_id, child_id) => self.remove_child(&group_id, &child_id),
+      };
+      result.map_err(|err| Error::EdgeOpFailed { index, source: Box::new(err) })?;
+    }
+    Ok(())
+  }
 
-      if visited.get(cur_id).is_some() {
+  pub fn delete_group(&mut self, group_id: &str) -> Option<Group> {
+    self.remove_group(group_id)
+  }
+
+  // Same as `delete_group`, but takes any string id. The removed group is
+  // handed back with its own parent and child lists intact, e.g. to undo or
+  // move it
+  pub fn remove_group(&mut self, group_id: &str) -> Option<Group> {
+    let group_val = self.store.get(group_id)?.clone();
+    let group_id = group_id.to_string();
+
+    // delete from all parents' children lists
+    for parent_id in &group_val.parents {
+      let mut parent_group = self.get_group_mut(&parent_id).unwrap().clone();
+      // a group's parents always have children, so this cannot fail
+      let _ = parent_group.remove_child(&group_id);
+      self.set_group(parent_id.to_string(), parent_group);
+    }
+
+    // delete from any childrens' parents lists
+    for child_id in group_val.children {
+      let mut child_group = self.get_group_mut(&child_id).unwrap().clone();
+      child_group.remove_parent(&group_id);
+      self.set_group(child_id.to_string(), child_group);
+    }
+
+    self.mark_changed(&group_id);
+    self.store.remove(&group_id)
+  }
+
+  // Merges every group of `other` into this store. When both stores contain
+  // a group, the one with the later `updated_at` wins; ties are broken by
+  // comparing the groups' sorted contents, so that merging in either
+  // direction produces the same result
+  pub fn merge(&mut self, other: &GroupStore) -> MergeReport {
+    self.merge_with_policy(other, AttributeMergePolicy::default())
+  }
+
+  // Same as `merge`, but skips groups of `other` whose ids are in
+  // `tombstones` (groups deleted locally), along with `other`'s edges to
+  // them, so that a peer that has not seen the deletion cannot resurrect them
+  pub fn merge_with_tombstones(
+      &mut self,
+      other: &GroupStore,
+      tombstones: &HashSet<String>,
+  ) -> MergeReport {
+    let mut live = GroupStore::new();
+    for (id, group_val) in other.store.iter() {
+      if tombstones.contains(id) {
         continue;
       }
-      visited.insert(cur_id);
+      let mut group_val = group_val.clone();
+      group_val.parents.retain(|parent_id| !tombstones.contains(parent_id));
+      group_val.children.retain(|child_id| !tombstones.contains(child_id));
+      live.store.insert(id.to_string(), group_val);
+    }
+    self.merge(&live)
+  }
 
-      let cur_val = self.get_group(cur_id).unwrap();
-      subgroups.insert(cur_id.to_string(), cur_val.clone());
+  pub fn set_merge_observer(&mut self, observer: Arc<dyn MergeObserver + Send + Sync>) {
+    self.merge_observer = Some(MergeObserverHandle(observer));
+  }
 
-      if let Some(children) = &cur_val.children {
-        for child in children {
-          to_visit.push(&child);
+  pub fn clear_merge_observer(&mut self) {
+    self.merge_observer = None;
+  }
+
+  // Same as `merge`, but combines the attributes of groups that exist in
+  // both stores according to `policy`
+  pub fn merge_with_policy(
+      &mut self,
+      other: &GroupStore,
+      policy: AttributeMergePolicy,
+  ) -> MergeReport {
+    let plan = self.plan_merge_with_policy(other, policy);
+    if let Some(MergeObserverHandle(observer)) = self.merge_observer.as_ref() {
+      for id in plan.conflicts() {
+        let existing_val = self.store.get(id).unwrap();
+        let chosen_val = plan.upserts.get(id).unwrap_or(existing_val);
+        observer.on_conflict(id, existing_val, other.store.get(id).unwrap(), chosen_val);
+      }
+    }
+    self.apply_plan(plan)
+  }
+
+  // Computes what `merge` would do, without modifying the store
+  pub fn plan_merge(&self, other: &GroupStore) -> MergePlan {
+    self.plan_merge_with_policy(other, AttributeMergePolicy::default())
+  }
+
+  pub fn plan_merge_with_policy(
+      &self,
+      other: &GroupStore,
+      policy: AttributeMergePolicy,
+  ) -> MergePlan {
+    let mut upserts = HashMap::<String, Group>::new();
+    let mut created = Vec::<String>::new();
+    let mut updated = Vec::<String>::new();
+    let mut conflicts = Vec::<String>::new();
+
+    // only groups that differ from the local copy can change anything
+    for (id, incoming_val) in other.diff(self).upserted.into_iter() {
+      match self.store.get(&id) {
+        Some(existing_val) => {
+          conflicts.push(id.clone());
+          let merged_val = GroupStore::merge_group(existing_val, incoming_val, policy);
+          if &merged_val == existing_val {
+            continue;
+          }
+          updated.push(id.clone());
+          upserts.insert(id, merged_val);
+        },
+        None => {
+          created.push(id.clone());
+          upserts.insert(id, incoming_val);
+        },
+      }
+    }
+
+    created.sort();
+    updated.sort();
+    conflicts.sort();
+    MergePlan {
+      generation: self.generation,
+      upserts,
+      report: MergeReport { created, updated },
+      conflicts,
+    }
+  }
+
+  // Applies a plan from `plan_merge`, unless the store has changed since
+  pub fn commit_plan(&mut self, plan: MergePlan) -> Result<MergeReport, Error> {
+    if plan.generation != self.generation {
+      return Err(Error::StalePlan);
+    }
+    Ok(self.apply_plan(plan))
+  }
+
+  fn apply_plan(&mut self, plan: MergePlan) -> MergeReport {
+    for (id, group_val) in plan.upserts.into_iter() {
+      self.set_group(id, group_val);
+    }
+    plan.report
+  }
+
+  // Same as `merge`, but first rejects `other` if any of its groups is more
+  // than `max_depth` levels below one of its roots (groups with no parent in
+  // `other`, or a group on a cycle with no root above it), so a peer cannot
+  // make us process an arbitrarily deep tree. The store is left unchanged on
+  // error
+  pub fn merge_bounded(
+      &mut self,
+      other: &GroupStore,
+      max_depth: usize,
+  ) -> Result<MergeReport, Error> {
+    if other.exceeds_depth(max_depth) {
+      return Err(Error::DepthExceeded(max_depth));
+    }
+    Ok(self.merge(other))
+  }
+
+  // Walks down breadth-first from the roots, so each group counts at its
+  // shortest distance from one and cycles are not walked twice. Groups not
+  // reachable from any root (a rootless cycle and whatever hangs below it)
+  // are then measured from one of their own in turn
+  fn exceeds_depth(&self, max_depth: usize) -> bool {
+    let children_of = |group_ids: &HashSet<&String>| {
+      group_ids.iter()
+          .filter_map(|group_id| self.store.get(*group_id))
+          .flat_map(|group_val| group_val.children().iter())
+          .filter(|child_id| self.store.contains_key(*child_id))
+          .collect::<HashSet::<&String>>()
+    };
+
+    let mut frontier = self.store
+        .iter()
+        .filter(|(_, group_val)| {
+          group_val.parents().iter().all(|parent_id| !self.store.contains_key(parent_id))
+        })
+        .map(|(group_id, _)| group_id)
+        .collect::<HashSet::<&String>>();
+    let mut reached = HashSet::<&String>::new();
+    loop {
+      reached.extend(frontier.iter());
+      let mut depth = 0;
+      while !frontier.is_empty() {
+        if depth > max_depth {
+          return true;
         }
+        frontier = children_of(&frontier)
+            .into_iter()
+            .filter(|child_id| reached.insert(*child_id))
+            .collect();
+        depth += 1;
+      }
+
+      match self.store.keys().filter(|group_id| !reached.contains(group_id)).min() {
+        Some(group_id) => frontier = HashSet::from([group_id]),
+        None => return false,
       }
     }
+  }
 
-    subgroups
+  // The winning group (see `merge`) with attributes combined per `policy`
+  fn merge_group(
+      existing_val: &Group,
+      incoming_val: Group,
+      policy: AttributeMergePolicy,
+  ) -> Group {
+    let incoming_wins = GroupStore::merge_wins(&incoming_val, existing_val);
+    let (mut winner_val, loser_val) = match incoming_wins {
+      true => (incoming_val, existing_val.clone()),
+      false => (existing_val.clone(), incoming_val),
+    };
+
+    match policy {
+      AttributeMergePolicy::Overwrite => {},
+      AttributeMergePolicy::Union => {
+        for (key, val) in loser_val.attributes.into_iter() {
+          winner_val.attributes.entry(key).or_insert(val);
+        }
+      },
+      AttributeMergePolicy::KeepExisting => {
+        let mut attributes = match incoming_wins {
+          true => std::mem::take(&mut winner_val.attributes),
+          false => loser_val.attributes,
+        };
+        attributes.extend(existing_val.attributes().clone());
+        winner_val.attributes = attributes;
+      },
+    }
+    winner_val
   }
 
-  pub fn is_group_member<'a>(
+  pub(crate) fn merge_wins(incoming_val: &Group, existing_val: &Group) -> bool {
+    GroupStore::merge_order_key(incoming_val) > GroupStore::merge_order_key(existing_val)
+  }
+
+  fn merge_order_key(group_val: &Group) -> MergeOrderKey<'_> {
+    let mut parents = group_val.parents().iter().collect::<Vec::<&String>>();
+    parents.sort();
+    let mut children = group_val.children().iter().collect::<Vec::<&String>>();
+    children.sort();
+    let mut attributes = group_val.attributes()
+        .iter()
+        .collect::<Vec::<(&String, &String)>>();
+    attributes.sort();
+    (
+      group_val.updated_at(),
+      *group_val.contact_level(),
+      parents,
+      (group_val.is_leaf(), children),
+      attributes,
+      group_val.origin(),
+    )
+  }
+
+  // Hash of the store's full contents that does not depend on HashMap or
+  // HashSet iteration order, so equal stores always hash equally
+  pub fn content_hash(&self) -> u64 {
+    let mut ids = self.store.keys().collect::<Vec::<&String>>();
+    ids.sort();
+
+    let mut hasher = DefaultHasher::new();
+    for id in ids {
+      id.hash(&mut hasher);
+      GroupStore::merge_order_key(self.store.get(id).unwrap()).hash(&mut hasher);
+    }
+    hasher.finish()
+  }
+
+  // Compares only the graph shape (ids and edges) and contact levels,
+  // ignoring metadata such as attributes and timestamps
+  pub fn structurally_eq(&self, other: &GroupStore) -> bool {
+    if self.store.len() != other.store.len() {
+      return false;
+    }
+
+    self.store.iter().all(|(id, val)| {
+      match other.store.get(id) {
+        Some(other_val) => {
+          val.group_id() == other_val.group_id()
+              && val.contact_level() == other_val.contact_level()
+              && val.parents() == other_val.parents()
+              && val.is_leaf() == other_val.is_leaf()
+              && val.children() == other_val.children()
+        },
+        None => false,
+      }
+    })
+  }
+
+  // Computes the changes needed to turn `baseline` into this store
+  pub fn diff(&self, baseline: &GroupStore) -> GroupStoreDiff {
+    let upserted = self.store
+        .iter()
+        .filter(|(id, val)| baseline.store.get(*id) != Some(*val))
+        .map(|(id, val)| (id.to_string(), val.clone()))
+        .collect::<HashMap::<String, Group>>();
+    let removed = baseline.store
+        .keys()
+        .filter(|id| !self.store.contains_key(*id))
+        .map(|id| id.to_string())
+        .collect::<HashSet::<String>>();
+
+    GroupStoreDiff { upserted, removed }
+  }
+
+  // Groups are inserted and removed as-is: any neighbors whose edges changed
+  // are part of the diff themselves
+  pub fn apply_diff(&mut self, diff: &GroupStoreDiff) {
+    for id in diff.removed.iter() {
+      self.mark_changed(id);
+      self.store.remove(id);
+    }
+    for (id, val) in diff.upserted.iter() {
+      self.set_group(id.to_string(), val.clone());
+    }
+  }
+
+  pub fn is_device_group(&self, group_val: &Group) -> bool {
+    group_val.is_leaf()
+  }
+
+  pub fn add_members(
+      &mut self,
+      base_group_id: &String,
+      ids_to_add: Vec<&String>,
+  ) {
+    for id_to_add in ids_to_add {
+      self.link_groups(base_group_id, id_to_add);
+    }
+  }
+
+  pub fn remove_members(
+      &mut self,
+      base_group_id: &String,
+      ids_to_remove: Vec<&String>,
+  ) {
+    for id_to_remove in ids_to_remove {
+      self.unlink_groups(base_group_id, id_to_remove);
+    }
+  }
+
+  // Panics if any of `ids`, or any group reachable from them, does not
+  // exist; see try_resolve_ids for untrusted ids. `ids` can be owned or
+  // borrowed strings
+  pub fn resolve_ids<I, S>(&self, ids: I) -> HashSet<&String>
+  where
+      I: IntoIterator<Item = S>,
+      S: AsRef<str>,
+  {
+    // use the store's own copy of each id, so that the result borrows only
+    // from the store
+    let ids = ids.into_iter()
+        .map(|id| match self.store.get_key_value(id.as_ref()) {
+          Some((id, _)) => id,
+          None => panic!("group {} does not exist", id.as_ref()),
+        })
+        .collect::<Vec::<&String>>();
+
+    if self.resolve_cache.borrow().is_none() {
+      return self.resolve_ids_excluding(ids, &HashSet::new());
+    }
+
+    let mut resolved_ids = HashSet::<&String>::new();
+    for id in ids {
+      let mut resolve_cache = self.resolve_cache.borrow_mut();
+      let entries = &mut resolve_cache.as_mut().unwrap().entries;
+      if !entries.contains_key(id) {
+        let mut id_resolved = HashSet::<&String>::new();
+        let mut id_visited = HashSet::<&String>::new();
+        self.resolve_ids_helper(&mut id_resolved, &mut id_visited, &HashSet::new(), id);
+        entries.insert(id.to_string(), (
+          id_resolved.into_iter().cloned().collect(),
+          id_visited.into_iter().cloned().collect(),
+        ));
+      }
+
+      // hand out references into the store rather than into the cache
+      for member in entries.get(id).unwrap().0.iter() {
+        resolved_ids.insert(self.store.get_key_value(member).unwrap().0);
+      }
+    }
+
+    resolved_ids
+  }
+
+  // Owned, sorted resolve_ids output, for deterministic iteration
+  pub fn resolve_ids_sorted(&self, ids: Vec<&String>) -> Vec<String> {
+    let mut resolved_ids = self.resolve_ids(ids)
+        .into_iter()
+        .cloned()
+        .collect::<Vec::<String>>();
+    resolved_ids.sort();
+    resolved_ids
+  }
+
+  // Like resolve_ids, but returns an error instead of panicking when one of
+  // `ids` does not exist
+  pub fn try_resolve_ids<'a>(
       &'a self,
-      is_member_id: &'a String,
-      group_id: &'a String,
-  ) -> bool {
+      ids: Vec<&'a String>,
+  ) -> Result<HashSet<&'a String>, Error> {
+    for id in ids.iter() {
+      if !self.store.contains_key(*id) {
+        return Err(Error::GroupDoesNotExist(id.to_string()));
+      }
+    }
+    Ok(self.resolve_ids(ids))
+  }
+
+  // Like resolve_ids, but excluded groups are pruned during the traversal:
+  // neither an excluded group nor anything reachable only through it is
+  // resolved. This differs from filtering the output of resolve_ids, which
+  // would only drop excluded devices themselves
+  pub fn resolve_ids_excluding<'a>(
+      &'a self,
+      ids: Vec<&'a String>,
+      exclude: &HashSet<String>,
+  ) -> HashSet<&'a String> {
+    let mut resolved_ids = HashSet::<&String>::new();
     let mut visited = HashSet::<&String>::new();
+
+    for id in ids {
+      self.resolve_ids_helper(
+          &mut resolved_ids,
+          &mut visited,
+          exclude,
+          id
+      );
+    }
+
+    resolved_ids
+  }
+
+  fn resolve_ids_helper<'a>(
+      &'a self,
+      resolved_ids: &mut HashSet<&'a String>,
+      visited: &mut HashSet<&'a String>,
+      exclude: &HashSet<String>,
+      id: &'a String,
+  ) {
     let mut to_visit = Vec::<&String>::new();
-    to_visit.push(group_id);
+    to_visit.push(id);
 
     while !to_visit.is_empty() {
       let cur_id = to_visit.pop().unwrap();
 
-      if visited.get(cur_id).is_some() {
+      if visited.get(cur_id).is_some() || exclude.contains(cur_id) {
         continue;
       }
 
-      if cur_id == is_member_id {
-        return true;
+      // blocked groups are still recorded as visited, so that the resolve
+      // cache is invalidated when they are unblocked
+      visited.insert(cur_id);
+      if self.blocklist.contains(cur_id) {
+        continue;
+      }
+      let cur_val = self.get_group(cur_id).unwrap();
+      if !cur_val.is_leaf() {
+        for child in cur_val.children() {
+          to_visit.push(&child);
+        }
+      } else {
+        resolved_ids.insert(cur_id);
       }
+    }
+  }
 
-      visited.insert(cur_id);
-      if let Some(children) = &self.get_group(cur_id).unwrap().children {
+  // Same members as resolve_ids(vec![root]), each mapped to a path of group
+  // ids from `root` to the member (both included) that explains why it is a
+  // member. Paths are shortest, with ties broken by child id
+  pub fn resolve_with_witness(&self, root: &String) -> HashMap<String, Vec<String>> {
+    let mut witnesses = HashMap::<String, Vec<String>>::new();
+    let mut predecessors = HashMap::<&String, Option<&String>>::new();
+    let mut to_visit = std::collections::VecDeque::<&String>::new();
+    if self.store.contains_key(root) && !self.blocklist.contains(root) {
+      predecessors.insert(root, None);
+      to_visit.push_back(root);
+    }
+
+    while let Some(cur_id) = to_visit.pop_front() {
+      let cur_val = self.get_group(cur_id).unwrap();
+      if !cur_val.is_leaf() {
+        let mut children = cur_val.children().iter().collect::<Vec::<&String>>();
+        children.sort();
         for child in children {
-          to_visit.push(&child);
+          let reachable = self.store.contains_key(child) && !self.blocklist.contains(child);
+          if reachable && !predecessors.contains_key(child) {
+            predecessors.insert(child, Some(cur_id));
+            to_visit.push_back(child);
+          }
+        }
+      } else {
+        let mut path = vec![cur_id.to_string()];
+        let mut next = predecessors.get(cur_id).unwrap();
+        while let Some(prev_id) = next {
+          path.push(prev_id.to_string());
+          next = predecessors.get(prev_id).unwrap();
         }
+        path.reverse();
+        witnesses.insert(cur_id.to_string(), path);
       }
     }
 
-    false
+    witnesses
   }
 
-  pub fn group_replace(
-      group: &mut Group,
-      id_to_replace: String,
-      replacement_id: String,
+  // Invokes `f` once per member that resolve_ids would return, without
+  // collecting the members first
+  pub fn for_each_member<'a>(
+      &'a self,
+      ids: Vec<&'a String>,
+      mut f: impl FnMut(&String),
   ) {
-    if group.group_id() == &id_to_replace {
-      group.group_id = replacement_id.clone();
+    let mut visited = HashSet::<&String>::new();
+    let mut to_visit = ids;
+
+    while let Some(cur_id) = to_visit.pop() {
+      if visited.contains(cur_id) || self.blocklist.contains(cur_id) {
+        continue;
+      }
+
+      visited.insert(cur_id);
+      let cur_val = self.get_group(cur_id).unwrap();
+      if !cur_val.is_leaf() {
+        for child in cur_val.children() {
+          to_visit.push(child);
+        }
+      } else {
+        f(cur_id);
+      }
     }
-    if group.remove_parent(&id_to_replace) {
-      group.add_parent(replacement_id.clone());
+  }
+
+  // The first of `roots` whose members include `member`, e.g. to find which
+  // top-level branch a device hangs under. Roots that do not exist are
+  // skipped
+  pub fn containing_root(&self, member: &str, roots: &[String]) -> Option<String> {
+    roots.iter()
+        .filter(|root| self.store.contains_key(*root))
+        .find(|root| self.resolve_ids([root]).iter().any(|id| id.as_str() == member))
+        .cloned()
+  }
+
+  // Sorted ids of the device groups reachable from group_id, e.g. for
+  // sending a recipient list without the group structure
+  pub fn flatten_to_members(&self, group_id: &str) -> Vec<String> {
+    let mut members = Vec::<String>::new();
+    if let Some((group_id, _)) = self.store.get_key_value(group_id) {
+      self.for_each_member(vec![group_id], |member| members.push(member.to_string()));
     }
-    group.remove_child(&id_to_replace).map(|result| {
-      if result {
-        group.add_child(replacement_id);
-      }
-    });
+    members.sort();
+    members
   }
 
-  pub fn group_contains(
-      group: &Group,
-      id_to_check: String,
-  ) -> bool {
-    if group.group_id() == &id_to_check {
-      return true;
+  pub fn get_all_groups(&self) -> &HashMap<String, Group> {
+    &self.store
+  }
+
+  // Takes the groups out of a store that is no longer needed, without
+  // cloning them
+  pub fn into_groups(self) -> HashMap<String, Group> {
+    self.store
+  }
+
+  // Checks that every referenced group exists and that every parent/child
+  // edge is recorded on both of its endpoints
+  pub fn validate(&self) -> Result<(), Vec<GroupInconsistency>> {
+    let mut inconsistencies = Vec::<GroupInconsistency>::new();
+
+    for (id, val) in self.store.iter() {
+      if id != val.group_id() {
+        inconsistencies.push(GroupInconsistency::IdMismatch(id.to_string()));
+      }
+      if val.is_leaf() && !val.children().is_empty() {
+        inconsistencies.push(GroupInconsistency::LeafWithChildren(id.to_string()));
+      }
+
+      for parent in val.parents() {
+        match self.store.get(parent) {
+          Some(parent_val) => {
+            if !parent_val.children().contains(id) {
+              inconsistencies.push(GroupInconsistency::ParentMissingChildEdge(
+                  id.to_string(),
+                  parent.to_string(),
+              ));
+            }
+          },
+          None => inconsistencies.push(GroupInconsistency::MissingParent(
+              id.to_string(),
+              parent.to_string(),
+          )),
+        }
+      }
+
+      for child in val.children().iter() {
+        match self.store.get(child) {
+          Some(child_val) => {
+            if !child_val.parents().contains(id) {
+              inconsistencies.push(GroupInconsistency::ChildMissingParentEdge(
+                  id.to_string(),
+                  child.to_string(),
+              ));
+            }
+          },
+          None => inconsistencies.push(GroupInconsistency::MissingChild(
+              id.to_string(),
+              child.to_string(),
+          )),
+        }
+      }
     }
-    if group.parents().contains(&id_to_check) {
-      return true;
+
+    if inconsistencies.is_empty() {
+      Ok(())
+    } else {
+      Err(inconsistencies)
     }
-    match group.children() {
-      Some(children) => {
-        if children.contains(&id_to_check) {
-          return true;
+  }
+
+  pub fn stats(&self) -> GroupStoreStats {
+    let roots = self.store
+        .iter()
+        .filter(|(_, val)| val.parents().is_empty())
+        .map(|(id, _)| id)
+        .collect::<Vec::<&String>>();
+
+    // breadth-first from every root so each group is assigned its
+    // shortest distance from a root, which also terminates on cycles
+    let mut max_depth = 0;
+    let mut visited = HashSet::<&String>::new();
+    let mut cur_level = roots.clone();
+    let mut depth = 0;
+    while !cur_level.is_empty() {
+      let mut next_level = Vec::<&String>::new();
+      for cur_id in cur_level {
+        if !visited.insert(cur_id) {
+          continue;
         }
-      },
-      _ => {},
+        max_depth = depth;
+        if let Some(val) = self.store.get(cur_id) {
+          next_level.extend(val.children().iter());
+        }
+      }
+      cur_level = next_level;
+      depth += 1;
+    }
+
+    GroupStoreStats {
+      group_count: self.store.len(),
+      edge_count: self.store
+          .values()
+          .map(|val| val.children().len())
+          .sum(),
+      max_depth,
+      contact_count: self.store.values().filter(|val| *val.contact_level()).count(),
+      root_count: roots.len(),
     }
-    false
   }
-}
 
-mod tests {
-  use std::collections::HashMap;
-  use std::collections::HashSet;
-  use crate::groups::{Group, GroupStore};
+  // (non-contact groups, contact groups)
+  pub fn count_by_contact_level(&self) -> (usize, usize) {
+    let contacts = self.store.values().filter(|val| *val.contact_level()).count();
+    (self.store.len() - contacts, contacts)
+  }
+
+  // Returns the lowest group that is an ancestor of (or equal to) both `a`
+  // and `b`, i.e. a shared ancestor none of whose descendants is also shared.
+  // Groups may have several parents, so several such ancestors can exist;
+  // the one closest to `a` and `b` combined is chosen, breaking ties by id
+  pub fn lowest_common_ancestor(&self, a: &str, b: &str) -> Option<String> {
+    let a_ancestors = self.ancestor_distances(a);
+    let b_ancestors = self.ancestor_distances(b);
+    let common = a_ancestors
+        .keys()
+        .filter(|id| b_ancestors.contains_key(*id))
+        .copied()
+        .collect::<Vec::<&String>>();
+
+    // drop shared ancestors that are strict ancestors of another shared one
+    let lowest = common
+        .iter()
+        .filter(|id| {
+          !common.iter().any(|other| {
+            other != *id && self.ancestor_distances(other).contains_key(*id)
+          })
+        })
+        .collect::<Vec::<&&String>>();
+
+    lowest
+        .into_iter()
+        .min_by_key(|id| (a_ancestors[**id] + b_ancestors[**id], **id))
+        .map(|id| id.to_string())
+  }
+
+  // All groups reachable from `group_id` via parent edges, excluding
+  // `group_id` itself
+  pub fn ancestors(&self, group_id: &str) -> HashSet<String> {
+    let mut ancestors = self.ancestor_distances(group_id)
+        .into_keys()
+        .cloned()
+        .collect::<HashSet::<String>>();
+    ancestors.remove(group_id);
+    ancestors
+  }
+
+  pub fn ancestors_and_self(&self, group_id: &str) -> HashSet<String> {
+    let mut ancestors = self.ancestors(group_id);
+    ancestors.insert(group_id.to_string());
+    ancestors
+  }
+
+  // Shortest distance from `group_id` up to a root (a group without
+  // parents), or None if the group does not exist or no root is reachable,
+  // e.g. because it is only part of a parent cycle
+  pub fn depth_of(&self, group_id: &str) -> Option<usize> {
+    self.ancestor_distances(group_id)
+        .into_iter()
+        .filter(|(id, _)| self.store.get(*id).is_some_and(|val| val.parents().is_empty()))
+        .map(|(_, distance)| distance)
+        .min()
+  }
+
+  // Breadth-first over parent edges, mapping each ancestor of `group_id`
+  // (including itself) to its shortest distance from `group_id`
+  fn ancestor_distances(&self, group_id: &str) -> HashMap<&String, usize> {
+    let mut distances = HashMap::<&String, usize>::new();
+    let mut cur_level = match self.store.get_key_value(group_id) {
+      Some((id, _)) => vec![id],
+      None => return distances,
+    };
+    let mut distance = 0;
+    while !cur_level.is_empty() {
+      let mut next_level = Vec::<&String>::new();
+      for cur_id in cur_level {
+        if distances.contains_key(cur_id) {
+          continue;
+        }
+        distances.insert(cur_id, distance);
+        if let Some(val) = self.store.get(cur_id) {
+          next_level.extend(val.parents().iter());
+        }
+      }
+      cur_level = next_level;
+      distance += 1;
+    }
+    distances
+  }
+
+  // None both for unknown groups and for groups created locally
+  pub fn origin_of(&self, group_id: &str) -> Option<&String> {
+    self.store.get(group_id).and_then(|group_val| group_val.origin())
+  }
+
+  pub fn iter(&self) -> std::collections::hash_map::Iter<'_, String, Group> {
+    self.store.iter()
+  }
+
+  // Releases capacity left over from bulk deletions; contents are unchanged
+  pub fn compact(&mut self) {
+    self.store.shrink_to_fit();
+    for group_val in self.store.values_mut() {
+      group_val.parents.shrink_to_fit();
+      group_val.attributes.shrink_to_fit();
+      group_val.children.shrink_to_fit();
+    }
+  }
+
+  #[cfg(test)]
+  fn capacity(&self) -> usize {
+    self.store.capacity()
+  }
+
+  pub fn as_adjacency(&self) -> Adjacency<'_> {
+    let mut children = HashMap::<&str, Vec<&String>>::new();
+    let mut parents = HashMap::<&str, Vec<&String>>::new();
+    for (group_id, group_val) in self.store.iter() {
+      children.insert(group_id, group_val.children().iter().collect());
+      parents.insert(group_id, group_val.parents().iter().collect());
+    }
+    Adjacency { children, parents }
+  }
+
+  // Every (parent, child) relationship, taken from the parents' children
+  // lists so that each edge is yielded exactly once
+  pub fn edges(&self) -> impl Iterator<Item = (&String, &String)> + '_ {
+    self.store.iter().flat_map(|(parent_id, parent_val)| {
+      parent_val.children()
+          .iter()
+          .map(move |child_id| (parent_id, child_id))
+    })
+  }
+
+  pub fn get_all_subgroups<'a>(
+      &'a self,
+      group_id: &'a String
+  ) -> HashMap<String, Group> {
+    self.get_all_subgroups_ref(group_id)
+        .into_iter()
+        .map(|(id, val)| (id.to_string(), val.clone()))
+        .collect()
+  }
+
+  // The subtree rooted at `group_id` as a self-contained store: edges to
+  // groups outside of the subtree (e.g. the root's parents) are dropped
+  pub fn subgraph(&self, group_id: &str) -> Result<GroupStore, Error> {
+    let group_id = match self.store.get_key_value(group_id) {
+      Some((group_id, _)) => group_id,
+      None => return Err(Error::GroupDoesNotExist(group_id.to_string())),
+    };
+
+    let subgroups = self.get_all_subgroups_ref(group_id);
+    let mut subgraph = GroupStore::new();
+    for (id, val) in subgroups.iter() {
+      let mut val = (*val).clone();
+      val.parents.retain(|parent_id| subgroups.contains_key(parent_id));
+      subgraph.set_group(id.to_string(), val);
+    }
+    Ok(subgraph)
+  }
+
+  // Borrowing variant of get_all_subgroups for read-only callers
+  pub fn get_all_subgroups_ref(&self, group_id: &String) -> HashMap<&String, &Group> {
+    let mut subgroups = HashMap::<&String, &Group>::new();
+    let mut to_visit = Vec::<&String>::new();
+    to_visit.push(self.store.get_key_value(group_id).unwrap().0);
+
+    while !to_visit.is_empty() {
+      let cur_id = to_visit.pop().unwrap();
+
+      if subgroups.contains_key(cur_id) {
+        continue;
+      }
+
+      let (cur_id, cur_val) = self.store.get_key_value(cur_id).unwrap();
+      subgroups.insert(cur_id, cur_val);
+
+      for child in cur_val.children() {
+        to_visit.push(child);
+      }
+    }
+
+    subgroups
+  }
+
+  // Same as get_all_subgroups, but safe to call on untrusted input: fails
+  // instead of panicking on missing groups, and stops once more than
+  // `max_nodes` groups would be collected
+  pub fn get_all_subgroups_bounded(
+      &self,
+      group_id: &String,
+      max_nodes: usize,
+  ) -> Result<HashMap<String, Group>, Error> {
+    let mut subgroups = HashMap::<String, Group>::new();
+    let mut visited = HashSet::<&String>::new();
+    let mut to_visit = Vec::<&String>::new();
+    to_visit.push(group_id);
+
+    while let Some(cur_id) = to_visit.pop() {
+      if visited.contains(cur_id) {
+        continue;
+      }
+      visited.insert(cur_id);
+
+      if subgroups.len() == max_nodes {
+        return Err(Error::LimitExceeded);
+      }
+
+      let cur_val = match self.get_group(cur_id) {
+        Some(cur_val) => cur_val,
+        None => return Err(Error::GroupDoesNotExist(cur_id.to_string())),
+      };
+      subgroups.insert(cur_id.to_string(), cur_val.clone());
+
+      for child in cur_val.children() {
+        to_visit.push(child);
+      }
+    }
+
+    Ok(subgroups)
+  }
+
+  pub fn is_group_member<'a>(
+      &'a self,
+      is_member_id: &'a String,
+      group_id: &'a String,
+  ) -> bool {
+    let mut visited = HashSet::<&String>::new();
+    let mut to_visit = Vec::<&String>::new();
+    to_visit.push(group_id);
+
+    while !to_visit.is_empty() {
+      let cur_id = to_visit.pop().unwrap();
+
+      if visited.get(cur_id).is_some() {
+        continue;
+      }
+
+      if cur_id == is_member_id {
+        return true;
+      }
+
+      visited.insert(cur_id);
+      for child in self.get_group(cur_id).unwrap().children() {
+        to_visit.push(child);
+      }
+    }
+
+    false
+  }
+
+  pub fn group_replace(
+      group: &mut Group,
+      id_to_replace: String,
+      replacement_id: String,
+  ) {
+    GroupStore::group_replace_with_keys(
+        group,
+        id_to_replace,
+        replacement_id,
+        ID_ATTRIBUTE_KEYS,
+    );
+  }
+
+  // Same as `group_replace`, with `id_attribute_keys` in place of
+  // ID_ATTRIBUTE_KEYS as the attributes that hold group ids
+  pub fn group_replace_with_keys(
+      group: &mut Group,
+      id_to_replace: String,
+      replacement_id: String,
+      id_attribute_keys: &[&str],
+  ) {
+    for key in id_attribute_keys {
+      if group.get_attribute(key) == Some(&id_to_replace) {
+        group.set_attribute(key.to_string(), replacement_id.clone());
+      }
+    }
+    if group.group_id() == &id_to_replace {
+      group.group_id = replacement_id.clone();
+    }
+    if group.remove_parent(&id_to_replace) {
+      group.add_parent(replacement_id.clone());
+    }
+    // a group that had `id_to_replace` as a child is not a leaf, so adding
+    // the replacement cannot fail
+    if let Ok(true) = group.remove_child(&id_to_replace) {
+      let _ = group.add_child(replacement_id);
+    }
+  }
+
+  pub fn group_contains(
+      group: &Group,
+      id_to_check: String,
+  ) -> bool {
+    if group.group_id() == &id_to_check {
+      return true;
+    }
+    if group.parents().contains(&id_to_check) {
+      return true;
+    }
+    if group.children().contains(&id_to_check) {
+      return true;
+    }
+    false
+  }
+
+  // Orders the ids of `groups` so that every group comes after all of the
+  // groups in `groups` that list it as a child (others are ignored). Only
+  // children lists are used, so `groups` need not have consistent parent
+  // lists, e.g. when they come from a peer. Ties are broken by id so the
+  // order is deterministic
+  pub fn topological_order(
+      groups: &HashMap<String, Group>,
+  ) -> Result<Vec<String>, CycleError> {
+    let mut remaining_parents = groups
+        .keys()
+        .map(|id| (id, 0))
+        .collect::<HashMap::<&String, usize>>();
+    for val in groups.values() {
+      for child_id in val.children() {
+        if let Some(count) = remaining_parents.get_mut(child_id) {
+          *count += 1;
+        }
+      }
+    }
+
+    let mut ready = remaining_parents
+        .iter()
+        .filter(|(_, count)| **count == 0)
+        .map(|(id, _)| *id)
+        .collect::<BTreeSet::<&String>>();
+    let mut order = Vec::<String>::new();
+
+    while let Some(id) = ready.iter().next().cloned() {
+      ready.remove(id);
+      order.push(id.to_string());
+      for child_id in groups.get(id).unwrap().children() {
+        if let Some(count) = remaining_parents.get_mut(child_id) {
+          *count -= 1;
+          if *count == 0 {
+            ready.insert(groups.get_key_value(child_id).unwrap().0);
+          }
+        }
+      }
+    }
+
+    if order.len() != groups.len() {
+      return Err(CycleError);
+    }
+    Ok(order)
+  }
+}
+
+impl<'a> IntoIterator for &'a GroupStore {
+  type Item = (&'a String, &'a Group);
+  type IntoIter = std::collections::hash_map::Iter<'a, String, Group>;
+
+  fn into_iter(self) -> Self::IntoIter {
+    self.iter()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+  use std::collections::HashSet;
+  use crate::groups::{
+    AttributeMergePolicy,
+    CycleError,
+    EdgeOp,
+    Error,
+    Group,
+    GroupInconsistency,
+    GroupStore,
+    GroupStoreStats,
+    MergeObserver,
+    MergeReport,
+  };
+  use std::sync::{Arc, Mutex};
+
+  #[test]
+  fn test_new() {
+    assert_eq!(GroupStore::new().store, HashMap::<String, Group>::new());
+  }
+
+  #[test]
+  fn test_set_get_group() {
+    let group = Group::new(None, true, false);
+    let mut group_store = GroupStore::new();
+    group_store.set_group(group.group_id.clone(), group.clone());
+    assert_eq!(*group_store.get_group(&group.group_id).unwrap(), group);
+  }
+
+  #[test]
+  fn test_modify_group_parents() {
+    let mut group_0 = Group::new(None, true, false);
+    let group_1 = Group::new(None, true, true);
+
+    group_0.add_parent(group_1.group_id.clone());
+    assert_eq!(
+        group_0.parents,
+        HashSet::from([group_1.group_id.clone()])
+    );
+
+    group_0.remove_parent(&group_1.group_id.clone());
+    assert_eq!(group_0.parents, HashSet::new());
+  }
+
+  #[test]
+  fn test_modify_group_children() {
+    let mut group_0 = Group::new(None, true, true);
+    let group_1 = Group::new(None, true, false);
+
+    group_0.add_child(group_1.group_id.clone()).unwrap();
+    assert_eq!(
+        group_0.children(),
+        &HashSet::from([group_1.group_id.clone()])
+    );
+
+    group_0.remove_child(&group_1.group_id.clone()).unwrap();
+    assert_eq!(group_0.children(), &HashSet::new());
+  }
+
+  #[test]
+  fn test_leaf_and_empty_group_are_distinct() {
+    let mut leaf = Group::new(Some(String::from("g")), false, false);
+    let mut empty = Group::new(Some(String::from("g")), false, true);
+    assert!(leaf.is_leaf());
+    assert!(!empty.is_leaf());
+    assert_eq!(leaf.children(), empty.children());
+    assert_eq!(
+        leaf.add_child(String::from("c")),
+        Err(Error::GroupHasNoChildren(String::from("g")))
+    );
+    assert_eq!(empty.add_child(String::from("c")), Ok(()));
+    assert_eq!(empty.remove_child(&String::from("c")), Ok(true));
+
+    let mut leaf_store = GroupStore::new();
+    leaf_store.set_group(String::from("g"), leaf);
+    let mut empty_store = GroupStore::new();
+    empty_store.set_group(String::from("g"), empty);
+    assert!(!leaf_store.structurally_eq(&empty_store));
+    assert_eq!(leaf_store.resolve_ids(["g"]), HashSet::from([&String::from("g")]));
+    assert!(empty_store.resolve_ids(["g"]).is_empty());
+
+    // merging keeps an empty intermediate group distinct from a leaf
+    let mut merged = GroupStore::from_edges(&[("root", "a")], &[]);
+    merged.merge(&empty_store);
+    assert!(!merged.get_group(&String::from("g")).unwrap().is_leaf());
+    assert!(merged.get_group(&String::from("a")).unwrap().is_leaf());
+    assert_eq!(merged.link_groups(&String::from("g"), &String::from("a")), Ok(()));
+    assert_eq!(
+        merged.resolve_ids(["g", "root"]),
+        HashSet::from([&String::from("a")])
+    );
+  }
+
+  #[test]
+  fn test_link_groups() {
+    let group_0 = Group::new(None, true, true);
+    let group_1 = Group::new(None, true, false);
+
+    let mut group_store = GroupStore::new();
+    group_store.set_group(group_0.group_id.clone(), group_0.clone());
+    group_store.set_group(group_1.group_id.clone(), group_1.clone());
+
+    group_store.link_groups(&group_0.group_id, &group_1.group_id).unwrap();
+
+    let new_group_0 = group_store.get_group(&group_0.group_id).unwrap();
+    assert_eq!(
+        new_group_0.children(),
+        &HashSet::from([group_1.group_id.clone()])
+    );
+
+    let new_group_1 = group_store.get_group(&group_1.group_id).unwrap();
+    assert_eq!(
+        new_group_1.parents,
+        HashSet::from([group_0.group_id.clone()])
+    );
+  }
+
+  #[test]
+  fn test_add_parent_reciprocal() {
+    let group_0 = Group::new(None, true, true);
+    let group_1 = Group::new(None, true, false);
+
+    let mut group_store = GroupStore::new();
+    group_store.set_group(group_0.group_id.clone(), group_0.clone());
+    group_store.set_group(group_1.group_id.clone(), group_1.clone());
+
+    assert_eq!(group_store.add_parent(&group_1.group_id, &group_0.group_id), Ok(()));
+    assert_eq!(
+        group_store.get_group(&group_0.group_id).unwrap().children(),
+        &HashSet::from([group_1.group_id.clone()])
+    );
+    assert_eq!(
+        group_store.get_group(&group_1.group_id).unwrap().parents,
+        HashSet::from([group_0.group_id.clone()])
+    );
+
+    // adding the same edge from the other side is a no-op
+    assert_eq!(group_store.add_child(&group_0.group_id, &group_1.group_id), Ok(()));
+    assert_eq!(
+        group_store.get_group(&group_0.group_id).unwrap().children(),
+        &HashSet::from([group_1.group_id.clone()])
+    );
+  }
+
+  #[test]
+  fn test_add_child_reciprocal() {
+    let group_0 = Group::new(None, true, true);
+    let group_1 = Group::new(None, true, false);
+
+    let mut group_store = GroupStore::new();
+    group_store.set_group(group_0.group_id.clone(), group_0.clone());
+    group_store.set_group(group_1.group_id.clone(), group_1.clone());
+
+    assert_eq!(group_store.add_child(&group_0.group_id, &group_1.group_id), Ok(()));
+    assert_eq!(
+        group_store.get_group(&group_0.group_id).unwrap().children(),
+        &HashSet::from([group_1.group_id.clone()])
+    );
+    assert_eq!(
+        group_store.get_group(&group_1.group_id).unwrap().parents,
+        HashSet::from([group_0.group_id.clone()])
+    );
+
+    assert_eq!(
+        group_store.add_child(&group_1.group_id, &group_0.group_id),
+        Err(Error::GroupHasNoChildren(group_1.group_id.clone()))
+    );
+  }
+
+  #[test]
+  fn test_unlink_groups() {
+    let group_0 = Group::new(None, true, true);
+    let group_1 = Group::new(None, true, false);
+
+    let mut group_store = GroupStore::new();
+    group_store.set_group(group_0.group_id.clone(), group_0.clone());
+    group_store.set_group(group_1.group_id.clone(), group_1.clone());
+
+    group_store.link_groups(&group_0.group_id, &group_1.group_id).unwrap();
+    group_store.unlink_groups(&group_0.group_id, &group_1.group_id).unwrap();
+
+    assert_eq!(&group_0, group_store.get_group(&group_0.group_id).unwrap());
+    assert_eq!(&group_1, group_store.get_group(&group_1.group_id).unwrap());
+  }
+
+  #[test]
+  fn test_set_children() {
+    let base_group = Group::new(None, true, true);
+    let group_0 = Group::new(None, true, false);
+    let group_1 = Group::new(None, true, false);
+    let group_2 = Group::new(None, true, false);
+
+    let mut group_store = GroupStore::new();
+    group_store.set_group(base_group.group_id.clone(), base_group.clone());
+    group_store.set_group(group_0.group_id.clone(), group_0.clone());
+    group_store.set_group(group_1.group_id.clone(), group_1.clone());
+    group_store.set_group(group_2.group_id.clone(), group_2.clone());
+
+    group_store.add_members(
+        base_group.group_id(),
+        vec![group_0.group_id(), group_1.group_id()]
+    );
+
+    assert_eq!(
+        group_store.set_children(
+            base_group.group_id(),
+            HashSet::from([group_1.group_id.clone(), group_2.group_id.clone()])
+        ),
+        Ok(())
+    );
+
+    assert_eq!(
+        group_store.get_group(base_group.group_id()).unwrap().children(),
+        &HashSet::from([group_1.group_id.clone(), group_2.group_id.clone()])
+    );
+    assert_eq!(
+        group_store.get_group(group_0.group_id()).unwrap().parents,
+        HashSet::new()
+    );
+    assert_eq!(
+        group_store.get_group(group_1.group_id()).unwrap().parents,
+        HashSet::from([base_group.group_id.clone()])
+    );
+    assert_eq!(
+        group_store.get_group(group_2.group_id()).unwrap().parents,
+        HashSet::from([base_group.group_id.clone()])
+    );
+
+    assert_eq!(
+        group_store.set_children(
+            base_group.group_id(),
+            HashSet::from([String::from("none")])
+        ),
+        Err(Error::GroupDoesNotExist(String::from("none")))
+    );
+  }
+
+  #[test]
+  fn test_set_parents() {
+    let group_0 = Group::new(None, true, true);
+    let group_1 = Group::new(None, true, true);
+    let group_2 = Group::new(None, true, false);
+
+    let mut group_store = GroupStore::new();
+    group_store.set_group(group_0.group_id.clone(), group_0.clone());
+    group_store.set_group(group_1.group_id.clone(), group_1.clone());
+    group_store.set_group(group_2.group_id.clone(), group_2.clone());
+    group_store.link_groups(group_0.group_id(), group_2.group_id()).unwrap();
+
+    assert_eq!(
+        group_store.set_parents(
+            group_2.group_id(),
+            HashSet::from([group_1.group_id.clone()])
+        ),
+        Ok(())
+    );
+
+    assert_eq!(
+        group_store.get_group(group_2.group_id()).unwrap().parents,
+        HashSet::from([group_1.group_id.clone()])
+    );
+    assert_eq!(
+        group_store.get_group(group_0.group_id()).unwrap().children(),
+        &HashSet::new()
+    );
+    assert_eq!(
+        group_store.get_group(group_1.group_id()).unwrap().children(),
+        &HashSet::from([group_2.group_id.clone()])
+    );
+  }
+
+  #[test]
+  fn test_delete_group() {
+    let group = Group::new(None, true, false);
+    let mut group_store = GroupStore::new();
+    group_store.set_group(group.group_id.clone(), group.clone());
+    group_store.delete_group(&group.group_id);
+    assert_eq!(group_store.get_group(&group.group_id), None);
+  }
+
+  #[test]
+  fn test_delete_linked_group() {
+    let group_0 = Group::new(None, true, true);
+    let group_1 = Group::new(None, true, false);
+
+    let mut group_store = GroupStore::new();
+    group_store.set_group(group_0.group_id.clone(), group_0.clone());
+    group_store.set_group(group_1.group_id.clone(), group_1.clone());
+
+    group_store.link_groups(&group_0.group_id, &group_1.group_id).unwrap();
+    group_store.delete_group(&group_0.group_id);
+
+    assert_eq!(group_store.get_group(&group_0.group_id), None);
+    assert_eq!(group_store.get_group(&group_1.group_id).unwrap(), &group_1);
+  }
+
+  #[test]
+  fn test_add_members() {
+    let base_group = Group::new(None, true, true);
+    let group_0 = Group::new(None, true, false);
+    let group_1 = Group::new(None, true, false);
+    let group_2 = Group::new(None, true, false);
+
+    let mut group_store = GroupStore::new();
+
+    group_store.set_group(base_group.group_id.clone(), base_group.clone());
+    group_store.set_group(group_0.group_id.clone(), group_0.clone());
+    group_store.set_group(group_1.group_id.clone(), group_1.clone());
+    group_store.set_group(group_2.group_id.clone(), group_2.clone());
+
+    group_store.add_members(
+        base_group.group_id(),
+        vec![group_0.group_id(), group_1.group_id(), group_2.group_id()]
+    );
+
+    let new_base_group = group_store.get_group(base_group.group_id()).unwrap();
+    assert_eq!(
+        new_base_group.children(),
+        &HashSet::from([
+            group_0.group_id.clone(),
+            group_1.group_id.clone(),
+            group_2.group_id.clone(),
+        ]),
+    );
+
+    assert_eq!(
+        group_store.get_group(group_0.group_id()).unwrap().parents,
+        HashSet::from([base_group.group_id.clone()])
+    );
+
+    assert_eq!(
+        group_store.get_group(group_1.group_id()).unwrap().parents,
+        HashSet::from([base_group.group_id.clone()])
+    );
+
+    assert_eq!(
+        group_store.get_group(group_2.group_id()).unwrap().parents,
+        HashSet::from([base_group.group_id.clone()])
+    );
+  }
+
+  #[test]
+  fn test_remove_members() {
+    let base_group = Group::new(None, true, true);
+    let group_0 = Group::new(None, true, false);
+    let group_1 = Group::new(None, true, false);
+    let group_2 = Group::new(None, true, false);
+
+    let mut group_store = GroupStore::new();
+
+    group_store.set_group(base_group.group_id.clone(), base_group.clone());
+    group_store.set_group(group_0.group_id.clone(), group_0.clone());
+    group_store.set_group(group_1.group_id.clone(), group_1.clone());
+    group_store.set_group(group_2.group_id.clone(), group_2.clone());
+
+    group_store.add_members(
+        base_group.group_id(),
+        vec![group_0.group_id(), group_1.group_id(), group_2.group_id()]
+    );
+
+    group_store.remove_members(
+        base_group.group_id(),
+        vec![group_0.group_id(), group_2.group_id()]
+    );
+
+    let new_base_group = group_store.get_group(base_group.group_id()).unwrap();
+    assert_eq!(
+        new_base_group.children(),
+        &HashSet::from([group_1.group_id.clone()]),
+    );
+
+    assert_eq!(
+        group_store.get_group(group_0.group_id()).unwrap().parents,
+        HashSet::new()
+    );
+
+    assert_eq!(
+        group_store.get_group(group_1.group_id()).unwrap().parents,
+        HashSet::from([base_group.group_id.clone()])
+    );
+
+    assert_eq!(
+        group_store.get_group(group_2.group_id()).unwrap().parents,
+        HashSet::new()
+    );
+  }
+
+  #[test]
+  fn test_resolve_ids() {
+    let base_group = Group::new(None, true, true);
+    let group_0 = Group::new(None, true, true);
+    let group_0a = Group::new(None, true, false);
+    let group_0b = Group::new(None, true, false);
+    let group_1 = Group::new(None, true, true);
+    let group_1a = Group::new(None, true, false);
+    let group_1b = Group::new(None, true, false);
+
+    let mut group_store = GroupStore::new();
+
+    group_store.set_group(base_group.group_id.clone(), base_group.clone());
+    group_store.set_group(group_0.group_id.clone(), group_0.clone());
+    group_store.set_group(group_0a.group_id.clone(), group_0a.clone());
+    group_store.set_group(group_0b.group_id.clone(), group_0b.clone());
+    group_store.set_group(group_1.group_id.clone(), group_1.clone());
+    group_store.set_group(group_1a.group_id.clone(), group_1a.clone());
+    group_store.set_group(group_1b.group_id.clone(), group_1b.clone());
+
+    group_store.add_members(
+        base_group.group_id(),
+        vec![group_0.group_id(), group_1.group_id()]
+    );
+
+    group_store.add_members(
+        group_0.group_id(),
+        vec![group_0a.group_id(), group_0b.group_id()]
+    );
+
+    group_store.add_members(
+        group_1.group_id(),
+        vec![group_1a.group_id(), group_1b.group_id()]
+    );
+
+    let expected_ids = HashSet::from([
+        group_0a.group_id(),
+        group_0b.group_id(),
+        group_1a.group_id(),
+        group_1b.group_id(),
+    ]);
+
+    assert_eq!(
+      group_store.resolve_ids(vec![base_group.group_id()]),
+      expected_ids
+    );
+
+    assert_eq!(
+      group_store.resolve_ids(vec![group_0.group_id(), group_1.group_id()]),
+      expected_ids
+    );
+  }
+
+  #[test]
+  fn test_set_contact_level() {
+    let group_0 = Group::new(None, false, true);
+    let group_1 = Group::new(None, false, false);
+
+    let mut group_store = GroupStore::new();
+    group_store.set_group(group_0.group_id.clone(), group_0.clone());
+    group_store.set_group(group_1.group_id.clone(), group_1.clone());
+    group_store.link_groups(&group_0.group_id, &group_1.group_id).unwrap();
+
+    assert_eq!(group_store.set_contact_level(&group_1.group_id, true), Ok(()));
+    assert_eq!(group_store.set_contact_level(&group_0.group_id, true), Ok(()));
+    assert_eq!(
+        group_store.get_group(&group_0.group_id).unwrap().contact_level(),
+        &true
+    );
+    assert_eq!(
+        group_store.get_group(&group_1.group_id).unwrap().contact_level(),
+        &true
+    );
+  }
+
+  #[test]
+  fn test_set_contact_level_conflict() {
+    let group_0 = Group::new(None, false, true);
+    let group_1 = Group::new(None, false, false);
+
+    let mut group_store = GroupStore::new();
+    group_store.set_group(group_0.group_id.clone(), group_0.clone());
+    group_store.set_group(group_1.group_id.clone(), group_1.clone());
+    group_store.link_groups(&group_0.group_id, &group_1.group_id).unwrap();
+
+    assert_eq!(
+        group_store.set_contact_level(&group_0.group_id, true),
+        Err(Error::ContactLevelConflict(group_0.group_id.clone()))
+    );
+    assert_eq!(
+        group_store.get_group(&group_0.group_id).unwrap().contact_level(),
+        &false
+    );
+    assert_eq!(
+        group_store.set_contact_level(&String::from("none"), true),
+        Err(Error::GroupDoesNotExist(String::from("none")))
+    );
+  }
+
+  #[test]
+  fn test_iter() {
+    let group_0 = Group::new(None, true, true);
+    let group_1 = Group::new(None, true, false);
+    let group_2 = Group::new(None, true, false);
+
+    let mut group_store = GroupStore::new();
+    group_store.set_group(group_0.group_id.clone(), group_0.clone());
+    group_store.set_group(group_1.group_id.clone(), group_1.clone());
+    group_store.set_group(group_2.group_id.clone(), group_2.clone());
+
+    let mut ids = HashSet::<&String>::new();
+    for (id, group_val) in &group_store {
+      assert_eq!(id, group_val.group_id());
+      ids.insert(id);
+    }
+    assert_eq!(
+        ids,
+        HashSet::from([group_0.group_id(), group_1.group_id(), group_2.group_id()])
+    );
+    assert_eq!(group_store.iter().count(), 3);
+  }
+
+  #[test]
+  fn test_validate() {
+    let group_0 = Group::new(None, true, true);
+    let group_1 = Group::new(None, true, false);
+
+    let mut group_store = GroupStore::new();
+    group_store.set_group(group_0.group_id.clone(), group_0.clone());
+    group_store.set_group(group_1.group_id.clone(), group_1.clone());
+    group_store.link_groups(&group_0.group_id, &group_1.group_id).unwrap();
+    assert_eq!(group_store.validate(), Ok(()));
+
+    // one-sided edge
+    group_store.get_group_mut(&group_1.group_id).unwrap().remove_parent(&group_0.group_id);
+    assert_eq!(
+        group_store.validate(),
+        Err(vec![GroupInconsistency::ChildMissingParentEdge(
+            group_0.group_id.clone(),
+            group_1.group_id.clone(),
+        )])
+    );
+
+    // dangling edge
+    group_store.get_group_mut(&group_1.group_id).unwrap().add_parent(group_0.group_id.clone());
+    group_store.delete_group(&group_1.group_id);
+    group_store.get_group_mut(&group_0.group_id)
+        .unwrap()
+        .add_child(String::from("none"))
+        .unwrap();
+    assert_eq!(
+        group_store.validate(),
+        Err(vec![GroupInconsistency::MissingChild(
+            group_0.group_id.clone(),
+            String::from("none"),
+        )])
+    );
+  }
+
+  #[test]
+  fn test_merge_newer_wins() {
+    let mut old_group = Group::new(Some(String::from("0")), false, true);
+    old_group.set_updated_at(1);
+    let mut new_group = Group::new(Some(String::from("0")), true, true);
+    new_group.set_updated_at(2);
+    let other_group = Group::new(Some(String::from("1")), false, false);
+
+    let mut group_store_0 = GroupStore::new();
+    group_store_0.set_group(old_group.group_id.clone(), old_group.clone());
+    group_store_0.set_group(other_group.group_id.clone(), other_group.clone());
+
+    let mut group_store_1 = GroupStore::new();
+    group_store_1.set_group(new_group.group_id.clone(), new_group.clone());
+
+    let mut merged_0 = group_store_0.clone();
+    merged_0.merge(&group_store_1);
+    let mut merged_1 = group_store_1.clone();
+    merged_1.merge(&group_store_0);
+
+    assert_eq!(merged_0.get_group(&new_group.group_id), Some(&new_group));
+    assert_eq!(merged_0.get_group(&other_group.group_id), Some(&other_group));
+    assert_eq!(merged_0, merged_1);
+  }
+
+  #[test]
+  fn test_merge_tie_is_deterministic() {
+    let mut group_a = Group::new(Some(String::from("0")), false, true);
+    group_a.add_child(String::from("a")).unwrap();
+    group_a.set_updated_at(5);
+    let mut group_b = Group::new(Some(String::from("0")), false, true);
+    group_b.add_child(String::from("b")).unwrap();
+    group_b.set_updated_at(5);
+
+    let mut group_store_a = GroupStore::new();
+    group_store_a.set_group(group_a.group_id.clone(), group_a.clone());
+    let mut group_store_b = GroupStore::new();
+    group_store_b.set_group(group_b.group_id.clone(), group_b.clone());
+
+    let mut merged_a = group_store_a.clone();
+    merged_a.merge(&group_store_b);
+    let mut merged_b = group_store_b.clone();
+    merged_b.merge(&group_store_a);
+
+    assert_eq!(merged_a, merged_b);
+    assert_eq!(merged_a.get_group(&String::from("0")), Some(&group_b));
+  }
+
+  #[test]
+  fn test_merge_tie_on_origin_is_deterministic() {
+    let group_a = Group::new(Some(String::from("0")), false, true);
+    let mut group_b = group_a.clone();
+    group_b.set_origin(Some(String::from("sender")));
+
+    let mut group_store_a = GroupStore::new();
+    group_store_a.set_group(group_a.group_id.clone(), group_a.clone());
+    let mut group_store_b = GroupStore::new();
+    group_store_b.set_group(group_b.group_id.clone(), group_b.clone());
+    assert_ne!(group_store_a.content_hash(), group_store_b.content_hash());
+
+    let mut merged_a = group_store_a.clone();
+    merged_a.merge(&group_store_b);
+    let mut merged_b = group_store_b.clone();
+    merged_b.merge(&group_store_a);
+
+    assert_eq!(merged_a, merged_b);
+    assert_eq!(merged_a.get_group(&String::from("0")), Some(&group_b));
+  }
+
+  #[test]
+  fn test_structurally_eq() {
+    let group_0 = Group::new(None, false, true);
+    let group_1 = Group::new(None, false, false);
+
+    let mut group_store_0 = GroupStore::new();
+    group_store_0.set_group(group_0.group_id.clone(), group_0.clone());
+    group_store_0.set_group(group_1.group_id.clone(), group_1.clone());
+    group_store_0.link_groups(&group_0.group_id, &group_1.group_id).unwrap();
+
+    let mut group_store_1 = group_store_0.clone();
+    group_store_1.get_group_mut(&group_0.group_id)
+        .unwrap()
+        .set_attribute(String::from("name"), String::from("laptop"));
+
+    assert!(group_store_0.structurally_eq(&group_store_1));
+    assert_ne!(group_store_0, group_store_1);
+
+    group_store_1.unlink_groups(&group_0.group_id, &group_1.group_id).unwrap();
+    assert!(!group_store_0.structurally_eq(&group_store_1));
+  }
+
+  #[test]
+  fn test_stats() {
+    let base_group = Group::new(None, false, true);
+    let group_0 = Group::new(None, false, true);
+    let group_0a = Group::new(None, false, false);
+    let group_1 = Group::new(None, true, false);
+    let other_group = Group::new(None, true, false);
+
+    let mut group_store = GroupStore::new();
+    group_store.set_group(base_group.group_id.clone(), base_group.clone());
+    group_store.set_group(group_0.group_id.clone(), group_0.clone());
+    group_store.set_group(group_0a.group_id.clone(), group_0a.clone());
+    group_store.set_group(group_1.group_id.clone(), group_1.clone());
+    group_store.set_group(other_group.group_id.clone(), other_group.clone());
+
+    group_store.add_members(
+        base_group.group_id(),
+        vec![group_0.group_id(), group_1.group_id()]
+    );
+    group_store.add_members(group_0.group_id(), vec![group_0a.group_id()]);
+
+    assert_eq!(group_store.stats(), GroupStoreStats {
+      group_count: 5,
+      edge_count: 3,
+      max_depth: 2,
+      contact_count: 2,
+      root_count: 2,
+    });
+  }
+
+  #[test]
+  fn test_get_all_subgroups_bounded() {
+    let base_group = Group::new(None, true, true);
+    let mut group_store = GroupStore::new();
+    group_store.set_group(base_group.group_id.clone(), base_group.clone());
+
+    for _ in 0..999 {
+      let group = Group::new(None, true, false);
+      group_store.set_group(group.group_id.clone(), group.clone());
+      group_store.link_groups(base_group.group_id(), group.group_id()).unwrap();
+    }
+
+    assert_eq!(
+        group_store.get_all_subgroups_bounded(base_group.group_id(), 100),
+        Err(Error::LimitExceeded)
+    );
+    assert_eq!(
+        group_store
+            .get_all_subgroups_bounded(base_group.group_id(), 1000)
+            .unwrap()
+            .len(),
+        1000
+    );
+  }
+
+  #[test]
+  fn test_for_each_member() {
+    let base_group = Group::new(None, true, true);
+    let group_0 = Group::new(None, true, true);
+    let group_0a = Group::new(None, true, false);
+    let group_1 = Group::new(None, true, false);
+
+    let mut group_store = GroupStore::new();
+    group_store.set_group(base_group.group_id.clone(), base_group.clone());
+    group_store.set_group(group_0.group_id.clone(), group_0.clone());
+    group_store.set_group(group_0a.group_id.clone(), group_0a.clone());
+    group_store.set_group(group_1.group_id.clone(), group_1.clone());
+
+    group_store.add_members(
+        base_group.group_id(),
+        vec![group_0.group_id(), group_1.group_id()]
+    );
+    group_store.add_members(group_0.group_id(), vec![group_0a.group_id()]);
+    // cycle back to the base group
+    group_store.add_members(group_0.group_id(), vec![base_group.group_id()]);
+
+    let mut members = Vec::<String>::new();
+    group_store.for_each_member(
+        vec![base_group.group_id(), group_0.group_id()],
+        |member| members.push(member.clone())
+    );
+
+    assert_eq!(members.len(), 2);
+    assert_eq!(
+        members.iter().collect::<HashSet::<&String>>(),
+        group_store.resolve_ids(vec![base_group.group_id(), group_0.group_id()])
+    );
+  }
+
+  #[test]
+  fn test_resolve_ids_excluding() {
+    let base_group = Group::new(None, true, true);
+    let group_0 = Group::new(None, true, true);
+    let group_0a = Group::new(None, true, false);
+    let group_0b = Group::new(None, true, false);
+    let group_1 = Group::new(None, true, false);
+
+    let mut group_store = GroupStore::new();
+    group_store.set_group(base_group.group_id.clone(), base_group.clone());
+    group_store.set_group(group_0.group_id.clone(), group_0.clone());
+    group_store.set_group(group_0a.group_id.clone(), group_0a.clone());
+    group_store.set_group(group_0b.group_id.clone(), group_0b.clone());
+    group_store.set_group(group_1.group_id.clone(), group_1.clone());
+
+    group_store.add_members(
+        base_group.group_id(),
+        vec![group_0.group_id(), group_1.group_id()]
+    );
+    group_store.add_members(
+        group_0.group_id(),
+        vec![group_0a.group_id(), group_0b.group_id()]
+    );
+
+    let exclude = HashSet::from([group_0.group_id.clone()]);
+
+    // post-filtering cannot remove group_0's members since group_0 itself
+    // is never part of the resolved set
+    let post_filtered = group_store
+        .resolve_ids(vec![base_group.group_id()])
+        .into_iter()
+        .filter(|id| !exclude.contains(*id))
+        .collect::<HashSet::<&String>>();
+    assert_eq!(
+        post_filtered,
+        HashSet::from([group_0a.group_id(), group_0b.group_id(), group_1.group_id()])
+    );
+
+    assert_eq!(
+        group_store.resolve_ids_excluding(vec![base_group.group_id()], &exclude),
+        HashSet::from([group_1.group_id()])
+    );
+  }
+
+  #[test]
+  fn test_topological_order() {
+    let mut root = Group::new(Some(String::from("root")), false, true);
+    let mut left = Group::new(Some(String::from("left")), false, true);
+    let mut right = Group::new(Some(String::from("right")), false, true);
+    let mut leaf = Group::new(Some(String::from("leaf")), false, false);
+
+    // diamond: root -> {left, right} -> leaf
+    root.add_child(left.group_id().to_string()).unwrap();
+    root.add_child(right.group_id().to_string()).unwrap();
+    left.add_parent(root.group_id().to_string());
+    left.add_child(leaf.group_id().to_string()).unwrap();
+    right.add_parent(root.group_id().to_string());
+    right.add_child(leaf.group_id().to_string()).unwrap();
+    leaf.add_parent(left.group_id().to_string());
+    leaf.add_parent(right.group_id().to_string());
+
+    let groups = HashMap::from([
+      (leaf.group_id().to_string(), leaf.clone()),
+      (right.group_id().to_string(), right.clone()),
+      (left.group_id().to_string(), left.clone()),
+      (root.group_id().to_string(), root.clone()),
+    ]);
+
+    assert_eq!(
+        GroupStore::topological_order(&groups),
+        Ok(vec![
+          String::from("root"),
+          String::from("left"),
+          String::from("right"),
+          String::from("leaf"),
+        ])
+    );
+
+    let mut group_store = GroupStore::new();
+    assert_eq!(group_store.bulk_set_groups(groups.clone()), Ok(()));
+    assert_eq!(group_store.get_all_groups(), &groups);
+    assert_eq!(group_store.validate(), Ok(()));
+  }
+
+  #[test]
+  fn test_topological_order_cycle() {
+    let mut a = Group::new(Some(String::from("a")), false, true);
+    let mut b = Group::new(Some(String::from("b")), false, true);
+    let mut c = Group::new(Some(String::from("c")), false, false);
+
+    // a -> b -> a, with c hanging off of b
+    a.add_child(b.group_id().to_string()).unwrap();
+    a.add_parent(b.group_id().to_string());
+    b.add_child(a.group_id().to_string()).unwrap();
+    b.add_child(c.group_id().to_string()).unwrap();
+    b.add_parent(a.group_id().to_string());
+    c.add_parent(b.group_id().to_string());
+
+    let groups = HashMap::from([
+      (a.group_id().to_string(), a),
+      (b.group_id().to_string(), b),
+      (c.group_id().to_string(), c),
+    ]);
+
+    assert_eq!(GroupStore::topological_order(&groups), Err(CycleError));
+
+    let mut group_store = GroupStore::new();
+    assert_eq!(group_store.bulk_set_groups(groups), Err(CycleError));
+    assert_eq!(group_store, GroupStore::new());
+  }
+
+  #[test]
+  fn test_topological_order_inconsistent_edges() {
+    let mut parent = Group::new(Some(String::from("parent")), false, true);
+    let mut other = Group::new(Some(String::from("other")), false, true);
+    let mut child = Group::new(Some(String::from("child")), false, false);
+
+    // parent lists child, but child does not list parent; child lists
+    // other, but other does not list child
+    parent.add_child(child.group_id().to_string()).unwrap();
+    child.add_parent(other.group_id().to_string());
+    other.add_parent(child.group_id().to_string());
+
+    let groups = HashMap::from([
+      (parent.group_id().to_string(), parent),
+      (other.group_id().to_string(), other),
+      (child.group_id().to_string(), child),
+    ]);
+    assert_eq!(
+        GroupStore::topological_order(&groups),
+        Ok(vec![String::from("other"), String::from("parent"), String::from("child")])
+    );
+  }
+
+  #[test]
+  fn test_diff() {
+    let mut group_store = GroupStore::new();
+    let parent = Group::new(Some(String::from("parent")), false, true);
+    let child = Group::new(Some(String::from("child")), false, false);
+    let removed = Group::new(Some(String::from("removed")), false, false);
+    group_store.set_group(parent.group_id().to_string(), parent.clone());
+    group_store.set_group(child.group_id().to_string(), child.clone());
+    group_store.set_group(removed.group_id().to_string(), removed.clone());
+    let baseline = group_store.clone();
+
+    assert!(group_store.diff(&baseline).is_empty());
+
+    group_store.link_groups(parent.group_id(), child.group_id()).unwrap();
+    group_store.delete_group(removed.group_id());
+    let added = Group::new(Some(String::from("added")), false, false);
+    group_store.set_group(added.group_id().to_string(), added.clone());
+
+    let diff = group_store.diff(&baseline);
+    assert_eq!(
+        diff.upserted().keys().collect::<HashSet::<&String>>(),
+        HashSet::from([parent.group_id(), child.group_id(), added.group_id()])
+    );
+    assert_eq!(diff.removed(), &HashSet::from([removed.group_id().to_string()]));
+
+    let mut stale = baseline.clone();
+    stale.apply_diff(&diff);
+    assert_eq!(stale, group_store);
+  }
+
+  #[test]
+  fn test_lowest_common_ancestor_tree() {
+    let mut group_store = GroupStore::new();
+    for (id, has_children) in [
+      ("root", true),
+      ("left", true),
+      ("right", true),
+      ("a", false),
+      ("b", false),
+      ("c", false),
+      ("disjoint", false),
+    ] {
+      group_store.set_group(
+          id.to_string(),
+          Group::new(Some(id.to_string()), false, has_children)
+      );
+    }
+    for (parent, child) in [
+      ("root", "left"),
+      ("root", "right"),
+      ("left", "a"),
+      ("left", "b"),
+      ("right", "c"),
+    ] {
+      group_store.link_groups(&parent.to_string(), &child.to_string()).unwrap();
+    }
+
+    assert_eq!(group_store.lowest_common_ancestor("a", "b"), Some(String::from("left")));
+    assert_eq!(group_store.lowest_common_ancestor("a", "c"), Some(String::from("root")));
+    assert_eq!(group_store.lowest_common_ancestor("a", "left"), Some(String::from("left")));
+    assert_eq!(group_store.lowest_common_ancestor("a", "a"), Some(String::from("a")));
+    assert_eq!(group_store.lowest_common_ancestor("a", "disjoint"), None);
+    assert_eq!(group_store.lowest_common_ancestor("a", "missing"), None);
+  }
+
+  #[test]
+  fn test_lowest_common_ancestor_diamond() {
+    let mut group_store = GroupStore::new();
+    for (id, has_children) in [
+      ("root", true),
+      ("left", true),
+      ("right", true),
+      ("a", false),
+      ("b", false),
+    ] {
+      group_store.set_group(
+          id.to_string(),
+          Group::new(Some(id.to_string()), false, has_children)
+      );
+    }
+    // both a and b are children of both left and right
+    for (parent, child) in [
+      ("root", "left"),
+      ("root", "right"),
+      ("left", "a"),
+      ("left", "b"),
+      ("right", "a"),
+      ("right", "b"),
+    ] {
+      group_store.link_groups(&parent.to_string(), &child.to_string()).unwrap();
+    }
+
+    // left and right are equally close, so the smaller id is chosen
+    for _ in 0..10 {
+      assert_eq!(group_store.lowest_common_ancestor("a", "b"), Some(String::from("left")));
+      assert_eq!(group_store.lowest_common_ancestor("b", "a"), Some(String::from("left")));
+    }
+  }
+
+  #[test]
+  fn test_resolve_cache() {
+    let mut group_store = GroupStore::new();
+    for (id, has_children) in [
+      ("root", true),
+      ("left", true),
+      ("right", true),
+      ("a", false),
+      ("b", false),
+      ("c", false),
+    ] {
+      group_store.set_group(
+          id.to_string(),
+          Group::new(Some(id.to_string()), false, has_children)
+      );
+    }
+    for (parent, child) in [
+      ("root", "left"),
+      ("root", "right"),
+      ("left", "a"),
+      ("right", "b"),
+    ] {
+      group_store.link_groups(&parent.to_string(), &child.to_string()).unwrap();
+    }
+
+    let root = String::from("root");
+    let left = String::from("left");
+    let right = String::from("right");
+    let a = String::from("a");
+    let b = String::from("b");
+    let c = String::from("c");
+    let uncached = group_store.resolve_ids(vec![&root, &left, &right])
+        .into_iter()
+        .cloned()
+        .collect::<HashSet::<String>>();
+    assert!(group_store.resolve_cache.borrow().is_none());
+
+    group_store.enable_resolve_cache();
+    let first = group_store.resolve_ids(vec![&root, &left, &right]);
+    let cached_ids = group_store.resolve_cache
+        .borrow()
+        .as_ref()
+        .unwrap()
+        .entries
+        .keys()
+        .cloned()
+        .collect::<HashSet::<String>>();
+    assert_eq!(cached_ids, HashSet::from([root.clone(), left.clone(), right.clone()]));
+
+    // hits return the same results as a fresh resolution
+    let second = group_store.resolve_ids(vec![&root, &left, &right]);
+    assert_eq!(first.into_iter().cloned().collect::<HashSet::<String>>(), uncached);
+    assert_eq!(second, group_store.resolve_ids(vec![&root, &left, &right]));
+    assert_eq!(group_store.resolve_ids(vec![&left]), HashSet::from([&a]));
+
+    // editing right evicts right and root, but not the unrelated left
+    group_store.set_group(c.clone(), Group::new(Some(c.clone()), false, false));
+    group_store.link_groups(&right, &c).unwrap();
+    let cached_ids = group_store.resolve_cache
+        .borrow()
+        .as_ref()
+        .unwrap()
+        .entries
+        .keys()
+        .cloned()
+        .collect::<HashSet::<String>>();
+    assert_eq!(cached_ids, HashSet::from([left.clone()]));
+
+    assert_eq!(group_store.resolve_ids(vec![&right]), HashSet::from([&b, &c]));
+    assert_eq!(group_store.resolve_ids(vec![&root]), HashSet::from([&a, &b, &c]));
+
+    group_store.delete_group(&a);
+    assert_eq!(group_store.resolve_ids(vec![&root]), HashSet::from([&b, &c]));
+    assert_eq!(group_store.resolve_ids(vec![&left]), HashSet::new());
+  }
+
+  #[test]
+  fn test_swap_groups() {
+    let mut group_store = GroupStore::new();
+    for (id, has_children) in [
+      ("root", true),
+      ("left", true),
+      ("right", true),
+      ("a", false),
+      ("b", false),
+      ("c", false),
+    ] {
+      group_store.set_group(
+          id.to_string(),
+          Group::new(Some(id.to_string()), false, has_children)
+      );
+    }
+    for (parent, child) in [
+      ("root", "left"),
+      ("root", "right"),
+      ("left", "a"),
+      ("right", "b"),
+      ("right", "c"),
+    ] {
+      group_store.link_groups(&parent.to_string(), &child.to_string()).unwrap();
+    }
+    let left = String::from("left");
+    let right = String::from("right");
+    group_store.get_group_mut(&left).unwrap().set_attribute(
+        String::from("name"),
+        String::from("left"),
+    );
+
+    assert_eq!(group_store.swap_groups("left", "right"), Ok(()));
+    assert_eq!(group_store.validate(), Ok(()));
+
+    let left_val = group_store.get_group(&left).unwrap();
+    assert_eq!(left_val.group_id(), &left);
+    assert_eq!(
+        left_val.children(),
+        &HashSet::from([String::from("b"), String::from("c")])
+    );
+    assert_eq!(left_val.get_attribute("name"), None);
+    let right_val = group_store.get_group(&right).unwrap();
+    assert_eq!(right_val.children(), &HashSet::from([String::from("a")]));
+    assert_eq!(right_val.get_attribute("name"), Some(&String::from("left")));
+    assert_eq!(
+        group_store.get_group(&String::from("a")).unwrap().parents(),
+        &HashSet::from([right.clone()])
+    );
+    assert_eq!(
+        group_store.get_group(&String::from("b")).unwrap().parents(),
+        &HashSet::from([left.clone()])
+    );
+
+    assert_eq!(
+        group_store.swap_groups("left", "missing"),
+        Err(Error::GroupDoesNotExist(String::from("missing")))
+    );
+  }
+
+  #[test]
+  fn test_swap_groups_adjacent() {
+    let mut group_store = GroupStore::new();
+    for (id, has_children) in [("a", true), ("b", true), ("c", false)] {
+      group_store.set_group(
+          id.to_string(),
+          Group::new(Some(id.to_string()), false, has_children)
+      );
+    }
+    group_store.link_groups(&String::from("a"), &String::from("b")).unwrap();
+    group_store.link_groups(&String::from("b"), &String::from("c")).unwrap();
+
+    // a -> b -> c becomes b -> a -> c
+    assert_eq!(group_store.swap_groups("a", "b"), Ok(()));
+    assert_eq!(group_store.validate(), Ok(()));
+    assert_eq!(group_store.get_group(&String::from("b")).unwrap().parents(), &HashSet::new());
+    assert_eq!(
+        group_store.get_group(&String::from("a")).unwrap().parents(),
+        &HashSet::from([String::from("b")])
+    );
+    assert_eq!(
+        group_store.get_group(&String::from("c")).unwrap().parents(),
+        &HashSet::from([String::from("a")])
+    );
+  }
+
+  #[test]
+  fn test_depth_of() {
+    let mut group_store = GroupStore::new();
+    for (id, has_children) in [
+      ("root", true),
+      ("child", true),
+      ("grandchild", false),
+    ] {
+      group_store.set_group(
+          id.to_string(),
+          Group::new(Some(id.to_string()), false, has_children)
+      );
+    }
+    group_store.link_groups(&String::from("root"), &String::from("child")).unwrap();
+    group_store.link_groups(&String::from("child"), &String::from("grandchild")).unwrap();
+
+    assert_eq!(group_store.depth_of("root"), Some(0));
+    assert_eq!(group_store.depth_of("child"), Some(1));
+    assert_eq!(group_store.depth_of("grandchild"), Some(2));
+    assert_eq!(group_store.depth_of("missing"), None);
+
+    // a shortcut from the root makes the grandchild shallower
+    group_store.link_groups(&String::from("root"), &String::from("grandchild")).unwrap();
+    assert_eq!(group_store.depth_of("grandchild"), Some(1));
+  }
+
+  #[test]
+  fn test_depth_of_cycle() {
+    let mut group_store = GroupStore::new();
+    for id in ["a", "b"] {
+      group_store.set_group(id.to_string(), Group::new(Some(id.to_string()), false, true));
+    }
+    group_store.link_groups(&String::from("a"), &String::from("b")).unwrap();
+    group_store.link_groups(&String::from("b"), &String::from("a")).unwrap();
+
+    assert_eq!(group_store.depth_of("a"), None);
+  }
+
+  #[test]
+  fn test_try_resolve_ids() {
+    let mut group_store = GroupStore::new();
+    let parent = String::from("parent");
+    let child = String::from("child");
+    let missing = String::from("missing");
+    group_store.set_group(parent.clone(), Group::new(Some(parent.clone()), false, true));
+    group_store.set_group(child.clone(), Group::new(Some(child.clone()), false, false));
+    group_store.link_groups(&parent, &child).unwrap();
+
+    assert_eq!(
+        group_store.try_resolve_ids(vec![&parent]),
+        Ok(HashSet::from([&child]))
+    );
+    assert_eq!(
+        group_store.try_resolve_ids(vec![&parent, &missing]),
+        Err(Error::GroupDoesNotExist(missing.clone()))
+    );
+  }
+
+  #[test]
+  fn test_link_groups_creating() {
+    let mut group_store = GroupStore::new();
+    let parent = String::from("parent");
+    let child = String::from("child");
+
+    assert_eq!(group_store.link_groups_creating(&parent, &child), Ok(()));
+    let parent_val = group_store.get_group(&parent).unwrap();
+    assert_eq!(parent_val.contact_level(), &false);
+    assert_eq!(parent_val.parents(), &HashSet::new());
+    assert_eq!(parent_val.children(), &HashSet::from([child.clone()]));
+    let child_val = group_store.get_group(&child).unwrap();
+    assert_eq!(child_val.contact_level(), &false);
+    assert_eq!(child_val.parents(), &HashSet::from([parent.clone()]));
+    assert!(child_val.is_leaf());
+    assert_eq!(group_store.validate(), Ok(()));
+
+    // existing endpoints are reused, and a device group cannot be a parent
+    let other = String::from("other");
+    assert_eq!(group_store.link_groups_creating(&parent, &other), Ok(()));
+    assert_eq!(
+        group_store.get_group(&parent).unwrap().children(),
+        &HashSet::from([child.clone(), other.clone()])
+    );
+    assert_eq!(
+        group_store.link_groups_creating(&child, &other),
+        Err(Error::GroupHasNoChildren(child.clone()))
+    );
+  }
+
+  #[test]
+  fn test_rename_group() {
+    let mut group_store = GroupStore::new();
+    group_store.link_groups_creating("parent", "old").unwrap();
+    group_store.link_groups_creating("other", "sibling").unwrap();
+
+    assert_eq!(group_store.rename_group("old", "new"), Ok(()));
+    assert_eq!(group_store.validate(), Ok(()));
+    assert!(group_store.get_group(&String::from("old")).is_none());
+    assert_eq!(
+        group_store.get_group(&String::from("new")).unwrap().group_id(),
+        &String::from("new")
+    );
+    assert_eq!(
+        group_store.get_group(&String::from("parent")).unwrap().children(),
+        &HashSet::from([String::from("new")])
+    );
+
+    assert_eq!(
+        group_store.rename_group("new", "sibling"),
+        Err(Error::GroupAlreadyExists(String::from("sibling")))
+    );
+    assert_eq!(
+        group_store.rename_group("old", "newer"),
+        Err(Error::GroupDoesNotExist(String::from("old")))
+    );
+  }
+
+  #[test]
+  fn test_get_all_subgroups_ref() {
+    let group_store = GroupStore::from_edges(
+        &[("root", "left"), ("root", "right"), ("left", "a"), ("right", "a"), ("other", "b")],
+        &[],
+    );
+    let root = String::from("root");
+
+    let owned = group_store.get_all_subgroups(&root);
+    let borrowed = group_store.get_all_subgroups_ref(&root);
+    assert_eq!(borrowed.len(), 4);
+    assert_eq!(
+        borrowed
+            .into_iter()
+            .map(|(id, val)| (id.clone(), val.clone()))
+            .collect::<HashMap::<String, Group>>(),
+        owned
+    );
+  }
+
+  #[test]
+  fn test_count_by_contact_level() {
+    assert_eq!(GroupStore::new().count_by_contact_level(), (0, 0));
+
+    let group_store = GroupStore::from_edges(
+        &[("linked", "0"), ("linked", "1"), ("contact", "2")],
+        &["contact", "2"],
+    );
+    assert_eq!(group_store.count_by_contact_level(), (3, 2));
+  }
+
+  #[test]
+  fn test_subgraph() {
+    let group_store = GroupStore::from_edges(
+        &[("root", "branch"), ("root", "a"), ("branch", "b"), ("other", "branch")],
+        &[],
+    );
+
+    let subgraph = group_store.subgraph("branch").unwrap();
+    assert_eq!(subgraph.get_all_groups().len(), 2);
+    assert_eq!(subgraph.get_group(&String::from("branch")).unwrap().parents(), &HashSet::new());
+    assert_eq!(subgraph.validate(), Ok(()));
+    assert_eq!(
+        group_store.subgraph("missing"),
+        Err(Error::GroupDoesNotExist(String::from("missing")))
+    );
+  }
 
   #[test]
-  fn test_new() {
-    assert_eq!(GroupStore::new().store, HashMap::<String, Group>::new());
+  fn test_content_hash() {
+    let group_store = GroupStore::from_edges(&[("linked", "0"), ("linked", "1")], &[]);
+    let mut reordered = GroupStore::new();
+    for id in ["1", "linked", "0"] {
+      let id = id.to_string();
+      reordered.set_group(id.clone(), group_store.get_group(&id).unwrap().clone());
+    }
+    assert_eq!(group_store.content_hash(), reordered.content_hash());
+
+    reordered.get_group_mut(&String::from("0")).unwrap().set_updated_at(1);
+    assert_ne!(group_store.content_hash(), reordered.content_hash());
   }
 
   #[test]
-  fn test_set_get_group() {
-    let group = Group::new(None, true, false);
-    let mut group_store = GroupStore::new();
-    group_store.set_group(group.group_id.clone(), group.clone());
-    assert_eq!(*group_store.get_group(&group.group_id).unwrap(), group);
+  fn test_remove_child_cascade_orphaned() {
+    let mut group_store = GroupStore::from_edges(&[("parent", "child")], &[]);
+    let parent = String::from("parent");
+    let child = String::from("child");
+
+    assert_eq!(group_store.remove_child_cascade(&parent, &child, true), Ok(true));
+    assert!(group_store.get_group(&child).is_none());
+    assert_eq!(group_store.get_group(&parent).unwrap().children(), &HashSet::new());
+    assert_eq!(group_store.validate(), Ok(()));
   }
 
   #[test]
-  fn test_modify_group_parents() {
-    let mut group_0 = Group::new(None, true, false);
-    let group_1 = Group::new(None, true, true);
+  fn test_remove_child_cascade_kept() {
+    let mut group_store = GroupStore::from_edges(
+        &[("parent", "child"), ("other", "child"), ("parent", "only")],
+        &[],
+    );
+    let parent = String::from("parent");
+    let child = String::from("child");
+    let only = String::from("only");
 
-    group_0.add_parent(group_1.group_id.clone());
+    // still referenced by other
+    assert_eq!(group_store.remove_child_cascade(&parent, &child, true), Ok(false));
     assert_eq!(
-        group_0.parents,
-        HashSet::from([group_1.group_id.clone()])
+        group_store.get_group(&child).unwrap().parents(),
+        &HashSet::from([String::from("other")])
     );
 
-    group_0.remove_parent(&group_1.group_id.clone());
-    assert_eq!(group_0.parents, HashSet::new());
+    // orphaned, but deletion was not requested
+    assert_eq!(group_store.remove_child_cascade(&parent, &only, false), Ok(false));
+    assert_eq!(group_store.get_group(&only).unwrap().parents(), &HashSet::new());
+    assert_eq!(group_store.validate(), Ok(()));
   }
 
   #[test]
-  fn test_modify_group_children() {
-    let mut group_0 = Group::new(None, true, true);
-    let group_1 = Group::new(None, true, false);
+  fn test_flatten_to_members() {
+    let group_store = GroupStore::from_edges(
+        &[("root", "mid"), ("root", "c"), ("mid", "b"), ("mid", "a")],
+        &[],
+    );
 
-    group_0.add_child(group_1.group_id.clone());
     assert_eq!(
-        group_0.children.as_ref().unwrap(),
-        &HashSet::from([group_1.group_id.clone()])
+        group_store.flatten_to_members("root"),
+        vec![String::from("a"), String::from("b"), String::from("c")]
     );
-
-    group_0.remove_child(&group_1.group_id.clone());
-    assert_eq!(group_0.children.unwrap(), HashSet::new());
+    assert_eq!(group_store.flatten_to_members("mid"), vec![String::from("a"), String::from("b")]);
+    assert_eq!(group_store.flatten_to_members("missing"), Vec::<String>::new());
   }
 
   #[test]
-  fn test_link_groups() {
-    let group_0 = Group::new(None, true, true);
-    let group_1 = Group::new(None, true, false);
+  fn test_apply_edge_ops() {
+    let group = |id: &str, has_children: bool| {
+      Group::new(Some(id.to_string()), false, has_children)
+    };
+    let ops = vec![
+      EdgeOp::SetGroup(String::from("linked"), group("linked", true)),
+      EdgeOp::SetGroup(String::from("0"), group("0", false)),
+      EdgeOp::SetGroup(String::from("1"), group("1", false)),
+      EdgeOp::SetGroup(String::from("2"), group("2", false)),
+      EdgeOp::LinkGroups(String::from("linked"), String::from("0")),
+      EdgeOp::AddChild(String::from("linked"), String::from("1")),
+      EdgeOp::AddParent(String::from("2"), String::from("linked")),
+      EdgeOp::UnlinkGroups(String::from("linked"), String::from("2")),
+      EdgeOp::DeleteGroup(String::from("2")),
+    ];
 
     let mut group_store = GroupStore::new();
-    group_store.set_group(group_0.group_id.clone(), group_0.clone());
-    group_store.set_group(group_1.group_id.clone(), group_1.clone());
+    assert_eq!(group_store.apply_edge_ops(ops), Ok(()));
+    assert_eq!(
+        group_store,
+        GroupStore::from_edges(&[("linked", "0"), ("linked", "1")], &[])
+    );
+  }
 
-    group_store.link_groups(&group_0.group_id, &group_1.group_id);
+  #[test]
+  fn test_apply_edge_ops_failure() {
+    let ops = vec![
+      EdgeOp::SetGroup(String::from("linked"), Group::new(Some(String::from("linked")), false, true)),
+      EdgeOp::LinkGroups(String::from("linked"), String::from("missing")),
+      EdgeOp::DeleteGroup(String::from("linked")),
+    ];
 
-    let new_group_0 = group_store.get_group(&group_0.group_id).unwrap();
+    let mut group_store = GroupStore::new();
     assert_eq!(
-        new_group_0.children.as_ref().unwrap(),
-        &HashSet::from([group_1.group_id.clone()])
+        group_store.apply_edge_ops(ops),
+        Err(Error::EdgeOpFailed {
+          index: 1,
+          source: Box::new(Error::GroupDoesNotExist(String::from("missing"))),
+        })
     );
+    // the first op stays applied, the last one never ran
+    assert!(group_store.get_group(&String::from("linked")).is_some());
+  }
 
-    let new_group_1 = group_store.get_group(&group_1.group_id).unwrap();
+  #[test]
+  fn test_ancestors() {
+    let group_store = GroupStore::from_edges(
+        &[("root", "left"), ("root", "right"), ("left", "a"), ("right", "a"), ("other", "b")],
+        &[],
+    );
+
+    let ancestors = group_store.ancestors("a");
     assert_eq!(
-        new_group_1.parents,
-        HashSet::from([group_0.group_id.clone()])
+        ancestors,
+        HashSet::from([String::from("left"), String::from("right"), String::from("root")])
     );
+    let mut with_self = ancestors.clone();
+    with_self.insert(String::from("a"));
+    assert_eq!(group_store.ancestors_and_self("a"), with_self);
+    assert_eq!(group_store.ancestors("root"), HashSet::new());
+    assert_eq!(group_store.ancestors_and_self("root"), HashSet::from([String::from("root")]));
   }
 
   #[test]
-  fn test_unlink_groups() {
-    let group_0 = Group::new(None, true, true);
-    let group_1 = Group::new(None, true, false);
-
-    let mut group_store = GroupStore::new();
-    group_store.set_group(group_0.group_id.clone(), group_0.clone());
-    group_store.set_group(group_1.group_id.clone(), group_1.clone());
+  fn test_reparent() {
+    let mut group_store = GroupStore::from_edges(
+        &[("root", "left"), ("root", "right"), ("left", "branch"), ("right", "branch"), ("branch", "a")],
+        &[],
+    );
 
-    group_store.link_groups(&group_0.group_id, &group_1.group_id);
-    group_store.unlink_groups(&group_0.group_id, &group_1.group_id);
+    assert_eq!(group_store.reparent("branch", "root"), Ok(()));
+    assert_eq!(group_store.validate(), Ok(()));
+    assert_eq!(
+        group_store.get_group(&String::from("branch")).unwrap().parents(),
+        &HashSet::from([String::from("root")])
+    );
+    assert_eq!(
+        group_store.get_group(&String::from("left")).unwrap().children(),
+        &HashSet::new()
+    );
+    // the subtree moves along
+    assert_eq!(group_store.flatten_to_members("branch"), vec![String::from("a")]);
 
-    assert_eq!(&group_0, group_store.get_group(&group_0.group_id).unwrap());
-    assert_eq!(&group_1, group_store.get_group(&group_1.group_id).unwrap());
+    assert_eq!(
+        group_store.reparent("branch", "a"),
+        Err(Error::GroupHasNoChildren(String::from("a")))
+    );
+    assert_eq!(
+        group_store.reparent("branch", "missing"),
+        Err(Error::GroupDoesNotExist(String::from("missing")))
+    );
   }
 
   #[test]
-  fn test_delete_group() {
-    let group = Group::new(None, true, false);
-    let mut group_store = GroupStore::new();
-    group_store.set_group(group.group_id.clone(), group.clone());
-    group_store.delete_group(&group.group_id);
-    assert_eq!(group_store.get_group(&group.group_id), None);
+  fn test_reparent_cycle() {
+    let mut group_store = GroupStore::from_edges(
+        &[("root", "mid"), ("mid", "leaf_parent"), ("leaf_parent", "a")],
+        &[],
+    );
+    let before = group_store.clone();
+
+    assert_eq!(
+        group_store.reparent("root", "leaf_parent"),
+        Err(Error::WouldCreateCycle(String::from("root"), String::from("leaf_parent")))
+    );
+    assert_eq!(
+        group_store.reparent("mid", "mid"),
+        Err(Error::WouldCreateCycle(String::from("mid"), String::from("mid")))
+    );
+    assert_eq!(group_store, before);
   }
 
   #[test]
-  fn test_delete_linked_group() {
-    let group_0 = Group::new(None, true, true);
-    let group_1 = Group::new(None, true, false);
-
-    let mut group_store = GroupStore::new();
-    group_store.set_group(group_0.group_id.clone(), group_0.clone());
-    group_store.set_group(group_1.group_id.clone(), group_1.clone());
+  fn test_merge_report() {
+    let mut group_store = GroupStore::from_edges(&[("linked", "0")], &[]);
+    let mut incoming = GroupStore::from_edges(&[("linked", "0"), ("linked", "1")], &[]);
+    incoming.get_group_mut(&String::from("linked")).unwrap().set_updated_at(1);
 
-    group_store.link_groups(&group_0.group_id, &group_1.group_id);
-    group_store.delete_group(&group_0.group_id);
+    assert_eq!(
+        group_store.merge(&incoming),
+        MergeReport {
+          created: vec![String::from("1")],
+          updated: vec![String::from("linked")],
+        }
+    );
+    assert_eq!(group_store, incoming);
 
-    assert_eq!(group_store.get_group(&group_0.group_id), None);
-    assert_eq!(group_store.get_group(&group_1.group_id).unwrap(), &group_1);
+    // nothing left to do the second time
+    assert_eq!(
+        group_store.merge(&incoming),
+        MergeReport { created: Vec::new(), updated: Vec::new() }
+    );
   }
 
   #[test]
-  fn test_add_members() {
-    let base_group = Group::new(None, true, true);
-    let group_0 = Group::new(None, true, false);
-    let group_1 = Group::new(None, true, false);
-    let group_2 = Group::new(None, true, false);
-
-    let mut group_store = GroupStore::new();
-
-    group_store.set_group(base_group.group_id.clone(), base_group.clone());
-    group_store.set_group(group_0.group_id.clone(), group_0.clone());
-    group_store.set_group(group_1.group_id.clone(), group_1.clone());
-    group_store.set_group(group_2.group_id.clone(), group_2.clone());
+  fn test_resolve_ids_sorted() {
+    let group_store = GroupStore::from_edges(
+        &[("root", "mid"), ("root", "c"), ("mid", "b"), ("mid", "a"), ("other", "d")],
+        &[],
+    );
+    let root = String::from("root");
+    let other = String::from("other");
+
+    let mut expected = group_store.resolve_ids(vec![&root, &other])
+        .into_iter()
+        .cloned()
+        .collect::<Vec::<String>>();
+    expected.sort();
+    assert_eq!(group_store.resolve_ids_sorted(vec![&root, &other]), expected);
+    assert_eq!(
+        group_store.resolve_ids_sorted(vec![&root]),
+        vec![String::from("a"), String::from("b"), String::from("c")]
+    );
+  }
 
-    group_store.add_members(
-        base_group.group_id(),
-        vec![group_0.group_id(), group_1.group_id(), group_2.group_id()]
+  #[test]
+  fn test_edges() {
+    let group_store = GroupStore::from_edges(
+        &[("root", "a"), ("root", "b"), ("a", "c"), ("b", "c")],
+        &[],
     );
 
-    let new_base_group = group_store.get_group(base_group.group_id()).unwrap();
+    let edges = group_store.edges().collect::<Vec::<(&String, &String)>>();
+    assert_eq!(edges.len(), 4);
+    let edges = edges.into_iter()
+        .map(|(parent_id, child_id)| (parent_id.as_str(), child_id.as_str()))
+        .collect::<HashSet::<(&str, &str)>>();
     assert_eq!(
-        new_base_group.children.as_ref().unwrap(),
-        &HashSet::from([
-            group_0.group_id.clone(),
-            group_1.group_id.clone(),
-            group_2.group_id.clone(),
-        ]),
+        edges,
+        HashSet::from([("root", "a"), ("root", "b"), ("a", "c"), ("b", "c")])
     );
+    assert_eq!(GroupStore::new().edges().count(), 0);
+  }
 
-    assert_eq!(
-        group_store.get_group(group_0.group_id()).unwrap().parents,
-        HashSet::from([base_group.group_id.clone()])
+  #[test]
+  fn test_merge_bounded() {
+    let mut group_store = GroupStore::from_edges(&[("root", "a")], &[]);
+    let before = group_store.clone();
+    // "d" is three levels below "x"
+    let incoming = GroupStore::from_edges(
+        &[("x", "b"), ("b", "c"), ("c", "d"), ("x", "e")],
+        &[],
     );
 
     assert_eq!(
-        group_store.get_group(group_1.group_id()).unwrap().parents,
-        HashSet::from([base_group.group_id.clone()])
+        group_store.merge_bounded(&incoming, 2),
+        Err(Error::DepthExceeded(2))
     );
-
+    assert_eq!(group_store, before);
+
+    let report = group_store.merge_bounded(&incoming, 3).unwrap();
+    assert_eq!(report.created.len(), 5);
+    assert!(group_store.get_group(&String::from("d")).is_some());
+
+    // a cycle at the top has no root, but the chain below it still counts
+    let mut group_store = GroupStore::from_edges(&[("root", "a")], &[]);
+    let mut edges = vec![("A", "B"), ("B", "A"), ("B", "c0")];
+    let chain = (0..10).map(|i| format!("c{}", i)).collect::<Vec::<String>>();
+    for pair in chain.windows(2) {
+      edges.push((pair[0].as_str(), pair[1].as_str()));
+    }
+    let incoming = GroupStore::from_edges(&edges, &[]);
     assert_eq!(
-        group_store.get_group(group_2.group_id()).unwrap().parents,
-        HashSet::from([base_group.group_id.clone()])
+        group_store.merge_bounded(&incoming, 3),
+        Err(Error::DepthExceeded(3))
     );
+    assert!(group_store.get_group(&String::from("A")).is_none());
+    assert!(group_store.merge_bounded(&incoming, 11).is_ok());
   }
 
   #[test]
-  fn test_remove_members() {
-    let base_group = Group::new(None, true, true);
-    let group_0 = Group::new(None, true, false);
-    let group_1 = Group::new(None, true, false);
-    let group_2 = Group::new(None, true, false);
+  fn test_remove_group() {
+    let mut group_store = GroupStore::from_edges(&[("root", "mid"), ("mid", "leaf")], &[]);
+    let mid = String::from("mid");
+    group_store.get_group_mut(&mid)
+        .unwrap()
+        .set_attribute(String::from("name"), String::from("middle"));
+    let stored = group_store.get_group(&mid).unwrap().clone();
+
+    assert_eq!(group_store.remove_group("mid"), Some(stored));
+    assert_eq!(group_store.get_group(&mid), None);
+    assert_eq!(group_store.validate(), Ok(()));
+    assert_eq!(group_store.remove_group("mid"), None);
+    assert_eq!(group_store.delete_group(&String::from("leaf")).map(|val| val.is_leaf()), Some(true));
+  }
+
+  #[test]
+  fn test_as_adjacency() {
+    let group_store = GroupStore::from_edges(
+        &[("root", "a"), ("root", "b"), ("a", "c"), ("b", "c")],
+        &["c"],
+    );
+    let adjacency = group_store.as_adjacency();
+
+    for (group_id, group_val) in group_store.iter() {
+      let children = adjacency.children_of(group_id)
+          .iter()
+          .map(|&child_id| child_id.clone())
+          .collect::<HashSet::<String>>();
+      assert_eq!(children, group_val.children().clone());
+      let parents = adjacency.parents_of(group_id)
+          .iter()
+          .map(|&parent_id| parent_id.clone())
+          .collect::<HashSet::<String>>();
+      assert_eq!(&parents, group_val.parents());
+    }
+    assert!(adjacency.children_of("missing").is_empty());
+  }
 
+  #[test]
+  fn test_compact() {
     let mut group_store = GroupStore::new();
+    let group_ids = (0..1000)
+        .map(|i| i.to_string())
+        .collect::<Vec::<String>>();
+    for group_id in group_ids.iter() {
+      group_store.set_group(group_id.clone(), Group::new(Some(group_id.clone()), false, false));
+    }
+    for group_id in group_ids[10..].iter() {
+      group_store.delete_group(group_id);
+    }
+    let before = group_store.clone();
+    let capacity_before = group_store.capacity();
 
-    group_store.set_group(base_group.group_id.clone(), base_group.clone());
-    group_store.set_group(group_0.group_id.clone(), group_0.clone());
-    group_store.set_group(group_1.group_id.clone(), group_1.clone());
-    group_store.set_group(group_2.group_id.clone(), group_2.clone());
+    group_store.compact();
+    assert!(group_store.capacity() < capacity_before);
+    assert_eq!(group_store, before);
+  }
 
-    group_store.add_members(
-        base_group.group_id(),
-        vec![group_0.group_id(), group_1.group_id(), group_2.group_id()]
+  #[test]
+  fn test_resolve_with_witness() {
+    let group_store = GroupStore::from_edges(
+        &[("root", "a"), ("root", "b"), ("a", "0"), ("a", "1"), ("b", "2")],
+        &[],
+    );
+    let root = String::from("root");
+    let path = |ids: &[&str]| ids.iter().map(|id| id.to_string()).collect::<Vec::<String>>();
+
+    let witnesses = group_store.resolve_with_witness(&root);
+    assert_eq!(witnesses.len(), 3);
+    assert_eq!(witnesses.get("0"), Some(&path(&["root", "a", "0"])));
+    assert_eq!(witnesses.get("1"), Some(&path(&["root", "a", "1"])));
+    assert_eq!(witnesses.get("2"), Some(&path(&["root", "b", "2"])));
+    assert_eq!(
+        witnesses.keys().collect::<HashSet::<&String>>(),
+        group_store.resolve_ids(vec![&root])
     );
+  }
 
-    group_store.remove_members(
-        base_group.group_id(),
-        vec![group_0.group_id(), group_2.group_id()]
+  #[test]
+  fn test_group_replace_attributes() {
+    let mut group_val = Group::new(Some(String::from("g")), false, true);
+    group_val.set_attribute(String::from("owner"), String::from("old"));
+    group_val.set_attribute(String::from("admin"), String::from("old"));
+    group_val.set_attribute(String::from("name"), String::from("old"));
+
+    let mut replaced = group_val.clone();
+    GroupStore::group_replace(&mut replaced, String::from("old"), String::from("new"));
+    assert_eq!(replaced.get_attribute("owner"), Some(&String::from("new")));
+    assert_eq!(replaced.get_attribute("admin"), Some(&String::from("old")));
+    assert_eq!(replaced.get_attribute("name"), Some(&String::from("old")));
+
+    let mut replaced = group_val.clone();
+    GroupStore::group_replace_with_keys(
+        &mut replaced,
+        String::from("old"),
+        String::from("new"),
+        &["admin"],
     );
+    assert_eq!(replaced.get_attribute("owner"), Some(&String::from("old")));
+    assert_eq!(replaced.get_attribute("admin"), Some(&String::from("new")));
 
-    let new_base_group = group_store.get_group(base_group.group_id()).unwrap();
+    // renaming also reaches groups that only refer to it by attribute
+    let mut group_store = GroupStore::from_edges(&[("old", "a"), ("other", "b")], &[]);
+    group_store.get_group_mut(&String::from("b"))
+        .unwrap()
+        .set_attribute(String::from("owner"), String::from("old"));
+    group_store.rename_group("old", "new").unwrap();
     assert_eq!(
-        new_base_group.children.as_ref().unwrap(),
-        &HashSet::from([group_1.group_id.clone()]),
+        group_store.get_group(&String::from("b")).unwrap().get_attribute("owner"),
+        Some(&String::from("new"))
     );
+  }
 
-    assert_eq!(
-        group_store.get_group(group_0.group_id()).unwrap().parents,
-        HashSet::new()
+  #[test]
+  fn test_commit_plan() {
+    let mut group_store = GroupStore::from_edges(&[("root", "a")], &[]);
+    let mut incoming = GroupStore::from_edges(&[("root", "a"), ("root", "b")], &[]);
+    incoming.get_group_mut(&String::from("root")).unwrap().set_updated_at(1);
+    let before = group_store.clone();
+
+    let plan = group_store.plan_merge(&incoming);
+    assert_eq!(group_store, before);
+    assert_eq!(plan.report().created, vec![String::from("b")]);
+    assert_eq!(plan.report().updated, vec![String::from("root")]);
+    assert_eq!(plan.conflicts(), &[String::from("root")]);
+
+    let report = group_store.commit_plan(plan.clone()).unwrap();
+    assert_eq!(&report, plan.report());
+    let mut merged = before.clone();
+    merged.merge(&incoming);
+    assert_eq!(group_store, merged);
+  }
+
+  #[test]
+  fn test_commit_plan_stale() {
+    let mut group_store = GroupStore::from_edges(&[("root", "a")], &[]);
+    let incoming = GroupStore::from_edges(&[("root", "a"), ("root", "b")], &[]);
+
+    let plan = group_store.plan_merge(&incoming);
+    group_store.link_groups_creating("root", "c").unwrap();
+    let before = group_store.clone();
+    assert_eq!(group_store.commit_plan(plan), Err(Error::StalePlan));
+    assert_eq!(group_store, before);
+  }
+
+  #[test]
+  fn test_resolve_ids_clone_and_serde() {
+    let group_store = GroupStore::from_edges(
+        &[
+          ("root", "a"), ("root", "b"), ("a", "c"), ("b", "c"),
+          ("c", "0"), ("c", "1"), ("a", "2"), ("b", "3"), ("other", "4"),
+        ],
+        &[],
     );
+    let roots = [String::from("root"), String::from("a"), String::from("other")];
+    let resolve_all = |group_store: &GroupStore| {
+      roots.iter()
+          .map(|root| group_store.resolve_ids_sorted(vec![root]))
+          .collect::<Vec::<Vec::<String>>>()
+    };
+    let expected = resolve_all(&group_store);
+    assert_eq!(expected[0], vec!["0", "1", "2", "3"]);
+
+    let cloned = group_store.clone();
+    assert_eq!(cloned, group_store);
+    assert_eq!(resolve_all(&cloned), expected);
+
+    let serialized = serde_json::to_string(&group_store).unwrap();
+    let deserialized = serde_json::from_str::<GroupStore>(&serialized).unwrap();
+    assert_eq!(deserialized, group_store);
+    assert_eq!(resolve_all(&deserialized), expected);
+
+    // a populated cache must not leak stale results into either copy
+    let mut cached = group_store.clone();
+    cached.enable_resolve_cache();
+    assert_eq!(resolve_all(&cached), expected);
+    assert_eq!(resolve_all(&cached.clone()), expected);
+    let serialized = serde_json::to_string(&cached).unwrap();
+    assert_eq!(resolve_all(&serde_json::from_str::<GroupStore>(&serialized).unwrap()), expected);
+  }
+
+  #[test]
+  fn test_merge_with_policy() {
+    let group_id = String::from("g");
+    let attribute = |key: &str, val: &str| (key.to_string(), val.to_string());
+    let mut group_store = GroupStore::from_edges(&[("g", "a")], &[]);
+    let group_val = group_store.get_group_mut(&group_id).unwrap();
+    group_val.set_attribute(String::from("color"), String::from("red"));
+    group_val.set_attribute(String::from("shape"), String::from("square"));
+    // the incoming version is newer and wins the merge
+    let mut incoming = GroupStore::from_edges(&[("g", "a")], &[]);
+    let group_val = incoming.get_group_mut(&group_id).unwrap();
+    group_val.set_attribute(String::from("color"), String::from("blue"));
+    group_val.set_attribute(String::from("size"), String::from("1"));
+    group_val.set_updated_at(1);
+
+    let merged_attributes = |policy: AttributeMergePolicy| {
+      let mut merged = group_store.clone();
+      let report = merged.merge_with_policy(&incoming, policy);
+      assert_eq!(report.updated, vec![group_id.clone()]);
+      merged.get_group(&group_id).unwrap().attributes().clone()
+    };
 
     assert_eq!(
-        group_store.get_group(group_1.group_id()).unwrap().parents,
-        HashSet::from([base_group.group_id.clone()])
+        merged_attributes(AttributeMergePolicy::Overwrite),
+        HashMap::from([attribute("color", "blue"), attribute("size", "1")])
     );
-
     assert_eq!(
-        group_store.get_group(group_2.group_id()).unwrap().parents,
-        HashSet::new()
+        merged_attributes(AttributeMergePolicy::Union),
+        HashMap::from([
+          attribute("color", "blue"),
+          attribute("shape", "square"),
+          attribute("size", "1"),
+        ])
+    );
+    assert_eq!(
+        merged_attributes(AttributeMergePolicy::KeepExisting),
+        HashMap::from([
+          attribute("color", "red"),
+          attribute("shape", "square"),
+          attribute("size", "1"),
+        ])
     );
   }
 
   #[test]
-  fn test_resolve_ids() {
-    let base_group = Group::new(None, true, true);
-    let group_0 = Group::new(None, true, true);
-    let group_0a = Group::new(None, true, false);
-    let group_0b = Group::new(None, true, false);
-    let group_1 = Group::new(None, true, true);
-    let group_1a = Group::new(None, true, false);
-    let group_1b = Group::new(None, true, false);
+  fn test_into_groups() {
+    let group_store = GroupStore::from_edges(&[("root", "a"), ("root", "b")], &[]);
+    let expected = group_store.get_all_groups().clone();
+    assert_eq!(group_store.into_groups(), expected);
+  }
 
-    let mut group_store = GroupStore::new();
+  #[test]
+  fn test_block() {
+    let mut group_store = GroupStore::from_edges(
+        &[("root", "mid"), ("root", "0"), ("mid", "1"), ("mid", "2")],
+        &[],
+    );
+    group_store.enable_resolve_cache();
+    let root = String::from("root");
+    assert_eq!(group_store.resolve_ids_sorted(vec![&root]), vec!["0", "1", "2"]);
+
+    assert!(group_store.block("mid"));
+    assert!(group_store.is_blocked("mid"));
+    assert_eq!(group_store.resolve_ids_sorted(vec![&root]), vec!["0"]);
+    assert_eq!(group_store.flatten_to_members("root"), vec!["0"]);
+    assert_eq!(group_store.resolve_with_witness(&root).len(), 1);
+
+    assert!(group_store.unblock("mid"));
+    assert!(!group_store.unblock("mid"));
+    assert_eq!(group_store.resolve_ids_sorted(vec![&root]), vec!["0", "1", "2"]);
+  }
 
-    group_store.set_group(base_group.group_id.clone(), base_group.clone());
-    group_store.set_group(group_0.group_id.clone(), group_0.clone());
-    group_store.set_group(group_0a.group_id.clone(), group_0a.clone());
-    group_store.set_group(group_0b.group_id.clone(), group_0b.clone());
-    group_store.set_group(group_1.group_id.clone(), group_1.clone());
-    group_store.set_group(group_1a.group_id.clone(), group_1a.clone());
-    group_store.set_group(group_1b.group_id.clone(), group_1b.clone());
+  #[test]
+  fn test_set_group_checked() {
+    let mut group_store = GroupStore::from_edges(&[("root", "a")], &[]);
+    let before = group_store.clone();
 
-    group_store.add_members(
-        base_group.group_id(),
-        vec![group_0.group_id(), group_1.group_id()]
+    let mut group_val = Group::new(Some(String::from("g")), false, true);
+    group_val.add_parent(String::from("root"));
+    group_val.add_child(String::from("missing")).unwrap();
+    assert_eq!(
+        group_store.set_group_checked(String::from("g"), group_val.clone()),
+        Err(Error::GroupDoesNotExist(String::from("missing")))
     );
+    assert_eq!(group_store, before);
 
-    group_store.add_members(
-        group_0.group_id(),
-        vec![group_0a.group_id(), group_0b.group_id()]
-    );
+    group_val.remove_child(&String::from("missing")).unwrap();
+    group_val.add_child(String::from("a")).unwrap();
+    assert_eq!(group_store.set_group_checked(String::from("g"), group_val.clone()), Ok(()));
+    assert_eq!(group_store.get_group(&String::from("g")), Some(&group_val));
+  }
 
-    group_store.add_members(
-        group_1.group_id(),
-        vec![group_1a.group_id(), group_1b.group_id()]
+  #[test]
+  fn test_merge_observer() {
+    #[derive(Default)]
+    struct RecordingObserver {
+      // (id, chosen updated_at)
+      calls: Mutex<Vec<(String, Option<u64>)>>,
+    }
+
+    impl MergeObserver for RecordingObserver {
+      fn on_conflict(&self, id: &str, _existing: &Group, _incoming: &Group, chosen: &Group) {
+        self.calls.lock().unwrap().push((id.to_string(), chosen.updated_at()));
+      }
+    }
+
+    let mut group_store = GroupStore::from_edges(&[("root", "a"), ("root", "b")], &[]);
+    group_store.get_group_mut(&String::from("a")).unwrap().set_updated_at(5);
+    let mut incoming = GroupStore::from_edges(
+        &[("root", "a"), ("root", "b"), ("root", "c")],
+        &[],
     );
+    incoming.get_group_mut(&String::from("root")).unwrap().set_updated_at(3);
+    incoming.get_group_mut(&String::from("a")).unwrap().set_updated_at(1);
 
-    let expected_ids = HashSet::from([
-        group_0a.group_id(),
-        group_0b.group_id(),
-        group_1a.group_id(),
-        group_1b.group_id(),
-    ]);
+    let observer = Arc::new(RecordingObserver::default());
+    group_store.set_merge_observer(observer.clone());
+    group_store.merge(&incoming);
 
+    let mut calls = observer.calls.lock().unwrap().clone();
+    calls.sort();
+    // "c" is new and "b" is identical, so neither is a conflict
     assert_eq!(
-      group_store.resolve_ids(vec![base_group.group_id()]),
-      expected_ids
+        calls,
+        vec![(String::from("a"), Some(5)), (String::from("root"), Some(3))]
+    );
+  }
+
+  #[test]
+  fn test_resolve_ids_generic_inputs() {
+    let group_store = GroupStore::from_edges(
+        &[("root", "mid"), ("mid", "0"), ("mid", "1"), ("other", "2")],
+        &[],
     );
+    let root = String::from("root");
+    let other = String::from("other");
+    let members = [String::from("0"), String::from("1"), String::from("2")];
+    let expected = members.iter().collect::<HashSet::<&String>>();
+
+    assert_eq!(group_store.resolve_ids(vec![&root, &other]), expected);
+    assert_eq!(group_store.resolve_ids(vec![root.clone(), other.clone()]), expected);
+    let ids: &[&str] = &["root", "other"];
+    assert_eq!(group_store.resolve_ids(ids), expected);
+  }
 
+  #[test]
+  fn test_merge_with_tombstones() {
+    let mut local = GroupStore::from_edges(&[("root", "a"), ("root", "b")], &[]);
+    let remote = local.clone();
+    local.delete_group(&String::from("b"));
+    let tombstones = HashSet::from([String::from("b")]);
+
+    let report = local.merge_with_tombstones(&remote, &tombstones);
+    assert!(report.created.is_empty());
+    assert!(local.get_group(&String::from("b")).is_none());
     assert_eq!(
-      group_store.resolve_ids(vec![group_0.group_id(), group_1.group_id()]),
-      expected_ids
+        local.get_group(&String::from("root")).unwrap().children(),
+        &HashSet::from([String::from("a")])
     );
+    assert_eq!(local.validate(), Ok(()));
+
+    // without the tombstone, a plain merge brings it back
+    local.merge(&remote);
+    assert!(local.get_group(&String::from("b")).is_some());
+  }
+
+  #[test]
+  fn test_containing_root() {
+    let group_store = GroupStore::from_edges(&[
+      ("left", "left_inner"),
+      ("left_inner", "a"),
+      ("right", "b"),
+      ("right", "c"),
+    ], &[]);
+    let roots = vec![String::from("left"), String::from("right")];
+
+    assert_eq!(group_store.containing_root("a", &roots), Some(String::from("left")));
+    assert_eq!(group_store.containing_root("c", &roots), Some(String::from("right")));
+    assert_eq!(group_store.containing_root("unknown", &roots), None);
+    // intermediate groups are not members
+    assert_eq!(group_store.containing_root("left_inner", &roots), None);
+  }
+
+  #[test]
+  fn test_group_serialization_format() {
+    let leaf_json = r#"{"group_id":"d","contact_level":false,"parents":["p"],"children":null}"#;
+    let leaf = serde_json::from_str::<Group>(leaf_json).unwrap();
+    assert!(leaf.is_leaf());
+    assert!(leaf.children().is_empty());
+    assert_eq!(leaf.parents(), &HashSet::from([String::from("p")]));
+
+    let empty_json = r#"{"group_id":"p","contact_level":false,"parents":[],"children":[]}"#;
+    let empty = serde_json::from_str::<Group>(empty_json).unwrap();
+    assert!(!empty.is_leaf());
+
+    let mut parent = Group::new(Some(String::from("p")), false, true);
+    parent.add_child(String::from("d")).unwrap();
+    for group_val in [leaf, empty, parent] {
+      let serialized = serde_json::to_value(&group_val).unwrap();
+      assert!(serialized.get("is_leaf").is_none());
+      assert_eq!(serialized["children"].is_null(), group_val.is_leaf());
+      assert_eq!(serde_json::from_value::<Group>(serialized).unwrap(), group_val);
+    }
+  }
+
+  #[test]
+  fn test_validate_leaf_with_children() {
+    let mut group_store = GroupStore::from_edges(&[("p", "d")], &[]);
+    group_store.get_group_mut(&String::from("d"))
+        .unwrap()
+        .children
+        .insert(String::from("p"));
+    let inconsistencies = group_store.validate().unwrap_err();
+    assert!(inconsistencies.contains(&GroupInconsistency::LeafWithChildren(String::from("d"))));
   }
 
   #[test]